@@ -1,5 +1,7 @@
+#[cfg(not(feature = "mmap"))]
 use std::process::Command;
 
+#[cfg(not(feature = "mmap"))]
 fn build_pmdk() {
     Command::new("git")
         .args(["submodule", "update", "--init", "--recursive"])
@@ -17,6 +19,7 @@ fn build_pmdk() {
 
 }
 
+#[cfg(not(feature = "mmap"))]
 fn build_ralloc() {
     // Build Ralloc
     Command::new("make")
@@ -46,6 +49,10 @@ fn build_ralloc() {
 }
 
 fn main() {
-    build_ralloc();
-    build_pmdk();
+    // The mmap allocator is written in Rust, so neither Ralloc nor PMDK is needed.
+    #[cfg(not(feature = "mmap"))]
+    {
+        build_ralloc();
+        build_pmdk();
+    }
 }
@@ -48,6 +48,45 @@ impl Collector {
     pub(crate) fn find(&self, tid: usize) -> Option<LocalHandle> {
         Local::find(self, tid)
     }
+
+    /// Persists the locations deferred by `Guard::defer_persist` of all participants
+    ///
+    /// # Safety
+    ///
+    /// No participant should be running (e.g. pinning or deferring a persist) until it returns.
+    pub unsafe fn persist_deferred(&self) {
+        Local::persist_deferred_all(self)
+    }
+
+    /// Returns the guard used by the `tid` thread of this collector
+    ///
+    /// Unlike `old_guard()` of the default collector, the local handle is not kept in a
+    /// thread-local variable but leaked, so that the `Local` of `tid` remains registered.
+    ///
+    /// # Safety
+    ///
+    /// Each `tid' should be used by only one thread.
+    pub unsafe fn old_guard(&self, tid: usize) -> Guard {
+        let handle = match self.find(tid) {
+            Some(handle) => {
+                // If it crashes during repin, we must ensure that there is a guard. See comments in `repin_after()`.
+                if handle.is_repinning() {
+                    handle.set_guard_count(1);
+                }
+                handle
+            }
+            None => self.register(Some(tid)),
+        };
+
+        // Creating a guard with previous context
+        let guard = handle.pin();
+
+        // Re-initialize the number of objs counted by `Local`. See comments in `old_guard()`.
+        handle.reset_count();
+        handle.set_guard_count(1);
+        core::mem::forget(handle);
+        guard
+    }
 }
 
 impl Clone for Collector {
@@ -428,6 +428,32 @@ impl Local {
         }
     }
 
+    /// Persists the deferred locations of all `Local`s in the provided `Global`
+    pub(crate) unsafe fn persist_deferred_all(collector: &Collector) {
+        // guard to iterate list
+        let tmp_handle = Local::register(collector, None);
+        let tmp_guard = tmp_handle.pin();
+
+        'persist: loop {
+            for local in collector.global.locals.iter(&tmp_guard) {
+                match local {
+                    Err(IterError::Stalled) => {
+                        // Persisting a location again is harmless, so just try again.
+                        continue 'persist;
+                    }
+                    Ok(local) => {
+                        let iter = local.persists.with(|v| (&*v).iter());
+                        for (ptr, len) in iter {
+                            persist(*ptr, *len, false);
+                        }
+                    }
+                }
+            }
+            sfence();
+            return;
+        }
+    }
+
     pub(crate) fn owner(&self) -> Option<usize> {
         self.tid
     }
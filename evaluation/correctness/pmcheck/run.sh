@@ -57,7 +57,7 @@ dmsg "[Run] target: $TARGET, TOOL: $TOOL, (option: $OPT, pool: $pool_id)"
 
 # Run
 export LD_LIBRARY_PATH=$PMCHECK:$RUSTSTD
-export PMCheck="-d/mnt/pmem0/test/$pool_id/$pool_id.pool_valid $OPT"
+export PMCheck="-d/mnt/pmem0/test/$pool_id/$pool_id.pool $OPT"
 ulimit -s 82920000
 mkdir -p $OUT/$TOOL
 # RUST_MIN_STACK=100000000 ./test_mmt_$TOOL $TARGET 2>&1>>$OUT/$TOOL/$TARGET.log
@@ -2,7 +2,6 @@
 #![deny(warnings)]
 
 use crossbeam_channel::{Receiver, Sender};
-use crossbeam_epoch::{self as epoch};
 use crossbeam_utils::CachePadded;
 use memento::ds::clevel::*;
 use memento::ploc::Handle;
@@ -51,7 +50,7 @@ fn get_send(tid: usize) -> &'static Sender<()> {
 #[no_mangle]
 pub extern "C" fn thread_init(tid: usize, pool: &'static PoolHandle) {
     let handles = unsafe { HANDLES.as_mut().unwrap() };
-    handles[tid] = Some(Handle::new(tid, epoch::pin(), pool));
+    handles[tid] = Some(Handle::new(tid, pool.pin(), pool));
 }
 
 #[no_mangle]
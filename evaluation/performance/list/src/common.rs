@@ -84,7 +84,7 @@ where
     let pool_handle = Pool::create::<O, M>(filepath, FILE_SIZE, nr_thread).unwrap();
 
     // Each thread executes op for `duration` seconds and accumulates execution count in `TOTAL_NOPS`
-    pool_handle.execute::<O, M>();
+    let _ = pool_handle.execute::<O, M>();
 
     // Load `TOTAL_NOPS`
     get_total_nops()
@@ -15,7 +15,8 @@ impl<T: 'static + Clone + Collectable> TestQueue for Queue<T> {
 
     fn enqueue(&self, input: Self::EnqInput, handle: &Handle) {
         let (value, enq_memento) = input;
-        self.enqueue(value, enq_memento, handle);
+        self.enqueue(value, enq_memento, handle)
+            .expect("Out of memory");
     }
 
     fn dequeue(&self, input: Self::DeqInput, handle: &Handle) {
@@ -42,7 +43,9 @@ impl PDefault for TestMementoQueue {
 
         let mut push_init = Enqueue::default();
         for i in 0..unsafe { QUEUE_INIT_SIZE } {
-            queue.enqueue(i, &mut push_init, handle);
+            queue
+                .enqueue(i, &mut push_init, handle)
+                .expect("Out of memory");
         }
         Self { queue }
     }
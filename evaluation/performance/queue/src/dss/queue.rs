@@ -299,7 +299,7 @@ impl<T: Clone> TestQueue for DSSQueue<T> {
         if val.is_some() {
             // deallocate previouse node in `x[tid]`
             let node_tid = self.x[tid].load(Ordering::Relaxed, &handle.guard);
-            unsafe { handle.guard.defer_pdestroy(node_tid) };
+            unsafe { handle.guard.defer_pdestroy(node_tid, handle.pool) };
         }
     }
 }
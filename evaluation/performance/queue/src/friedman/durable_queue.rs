@@ -114,7 +114,7 @@ impl<T: Clone> DurableQueue<T> {
 
         // deallocate previous ret_val
         if !prev.is_null() {
-            unsafe { guard.defer_pdestroy(prev) };
+            unsafe { guard.defer_pdestroy(prev, pool) };
         }
 
         loop {
@@ -160,7 +160,7 @@ impl<T: Clone> DurableQueue<T> {
                             guard,
                         );
                         guard.defer_persist(&*self.head); // persist inner of CachePadded
-                        unsafe { guard.defer_pdestroy(first) };
+                        unsafe { guard.defer_pdestroy(first, pool) };
                         return;
                     } else {
                         let deq_tid = next_ref.deq_tid.load(Ordering::SeqCst);
@@ -113,7 +113,7 @@ impl<T: Clone> LogQueue<T> {
 
         // deallocate previous log
         if !prev.is_null() {
-            unsafe { guard.defer_pdestroy(prev) };
+            unsafe { guard.defer_pdestroy(prev, pool) };
         }
 
         loop {
@@ -167,7 +167,7 @@ impl<T: Clone> LogQueue<T> {
 
         // deallocate previous log
         if !prev.is_null() {
-            unsafe { guard.defer_pdestroy(prev) };
+            unsafe { guard.defer_pdestroy(prev, pool) };
         }
 
         loop {
@@ -221,7 +221,7 @@ impl<T: Clone> LogQueue<T> {
                             guard,
                         );
                         guard.defer_persist(&*self.head); // persist inner of CachePadded
-                        unsafe { guard.defer_pdestroy(first) };
+                        unsafe { guard.defer_pdestroy(first, pool) };
                         return;
                     } else if self.head.load(Ordering::SeqCst, guard) == first {
                         persist_obj(&next_ref.log_remove, true);
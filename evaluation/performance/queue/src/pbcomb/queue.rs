@@ -162,7 +162,8 @@ pub struct PBCombQueue {
 impl Collectable for PBCombQueue {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         assert!(s.dummy.is_null());
-        Collectable::mark(unsafe { s.dummy.deref_mut(pool) }, tid, gc);
+        let dummy = unsafe { &mut *(s.dummy.deref_mut(pool) as *mut _) };
+        Collectable::mark(dummy, tid, gc, pool);
 
         for t in 1..MAX_THREADS + 1 {
             Collectable::filter(&mut *s.e_request[t], tid, gc, pool);
@@ -621,7 +622,7 @@ impl PBCombQueue {
         if !ret.is_null() {
             head.store(ret, Ordering::SeqCst);
             // NOTE: It should not be deallocated immediately as it may crash during deq combine.
-            unsafe { guard.defer_pdestroy(head_shared) };
+            unsafe { guard.defer_pdestroy(head_shared, pool) };
             return unsafe { ret.deref(pool) }.data;
         }
         Self::EMPTY
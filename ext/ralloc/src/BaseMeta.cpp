@@ -421,6 +421,63 @@ char* BaseMeta::sb_lookup(Descriptor* desc){
     return ret;
 }
 
+void BaseMeta::count_blocks(uint64_t* live_blocks, uint64_t* live_bytes, uint64_t* free_bytes){
+    for(int i = 0; i < MAX_SZ_IDX; i++) {
+        live_blocks[i] = 0;
+        live_bytes[i] = 0;
+    }
+    *free_bytes = 0;
+
+    char* curr_sb = _rgs->translate(SB_IDX, reinterpret_cast<char*>(SBSIZE)); // starting from first sb
+    char* sb_end = _rgs->regions[SB_IDX]->curr_addr_ptr->load();
+    while(curr_sb < sb_end) {
+        Descriptor* desc = desc_lookup(curr_sb);
+        if(desc->heap == nullptr || desc->superblock != curr_sb) {
+            // curr_sb isn't in use
+            curr_sb+=SBSIZE;
+            continue;
+        }
+        size_t sc_idx = desc->heap->sc_idx;
+        if(sc_idx == 0) {
+            // large sb that's in use
+            live_blocks[0]++;
+            live_bytes[0]+=desc->block_size;
+            curr_sb+=desc->block_size;
+            continue;
+        }
+        // small sb that's in use. anchor.count is the number of free blocks,
+        // which may be stale after a power failure
+        uint64_t free_cnt = min(desc->anchor.load().count, desc->maxcount);
+        live_blocks[sc_idx]+=desc->maxcount - free_cnt;
+        live_bytes[sc_idx]+=(desc->maxcount - free_cnt)*desc->block_size;
+        *free_bytes+=free_cnt*desc->block_size;
+        curr_sb+=SBSIZE;
+    }
+}
+
+char* BaseMeta::block_start(const char* ptr){
+    if(!_rgs->in_range(SB_IDX, ptr)) return nullptr;
+    char* sb = _rgs->translate(SB_IDX, reinterpret_cast<char*>(
+        ((uint64_t)_rgs->untranslate(SB_IDX, const_cast<char*>(ptr))>>SB_SHIFT)<<SB_SHIFT));
+    if(sb < _rgs->translate(SB_IDX, reinterpret_cast<char*>(SBSIZE)) ||
+        sb >= _rgs->regions[SB_IDX]->curr_addr_ptr->load()) {
+        // header of the region, or sb not allocated yet
+        return nullptr;
+    }
+    Descriptor* desc = desc_lookup(sb);
+    if(desc->heap == nullptr || desc->superblock != sb) {
+        // sb isn't in use, or isn't the first sb of a large block
+        return nullptr;
+    }
+    if(desc->heap->sc_idx == 0) {
+        // large sb
+        return ptr < sb + desc->block_size ? sb : nullptr;
+    }
+    uint64_t idx = (ptr - sb) / desc->block_size;
+    if(idx >= desc->maxcount) return nullptr;
+    return sb + idx * desc->block_size;
+}
+
 void BaseMeta::heap_push_partial(Descriptor* desc) {
     ProcHeap* heap = desc->heap;
     ptr_cnt<Descriptor> oldhead = heap->partial_list.load();
@@ -768,6 +825,12 @@ void GarbageCollection::operator() () {
     }
     printf("Done!\nReachable blocks = %lu\n", marked_blk.size());
 
+    // Count blocks in use before the crash with the anchors not yet reconstructed
+    uint64_t live_blocks[MAX_SZ_IDX], live_bytes[MAX_SZ_IDX], free_bytes;
+    base_md->count_blocks(live_blocks, live_bytes, &free_bytes);
+    uint64_t live_before = 0;
+    for(int i = 0; i < MAX_SZ_IDX; i++) live_before += live_blocks[i];
+
     // Step 2: sweep phase, update variables.
     printf("Reconstructing metadata...");
     char* curr_sb = _rgs->translate(SB_IDX, reinterpret_cast<char*>(SBSIZE)); // starting from first sb
@@ -877,6 +940,15 @@ void GarbageCollection::operator() () {
     ptr_cnt<Descriptor> tmp_avail_sb(avail_sb, 0);
     base_md->avail_sb.store(tmp_avail_sb);
     printf("Reconstructed! \n");
+
+    // Record the result: blocks that were in use but not reachable are reclaimed
+    base_md->count_blocks(live_blocks, live_bytes, &free_bytes);
+    uint64_t live_after = 0;
+    for(int i = 0; i < MAX_SZ_IDX; i++) live_after += live_blocks[i];
+    ralloc::last_gc.done = true;
+    ralloc::last_gc.reachable = live_after;
+    ralloc::last_gc.reclaimed = live_before > live_after ? live_before - live_after : 0;
+    printf("Reclaimed blocks = %lu\n", ralloc::last_gc.reclaimed);
     auto stop = high_resolution_clock::now();
     assert(curr_marked_blk == marked_blk.end());
     auto duration = duration_cast<milliseconds>(stop - start);
@@ -339,6 +339,14 @@ public:
 namespace ralloc{
     // (transient) filter functions for each root
     extern std::function<void(const CrossPtr<char, SB_IDX>&, size_t, GarbageCollection&)> roots_filter_func[MAX_ROOTS];
+
+    // (transient) result of the last garbage collection
+    struct GCResult {
+        bool done = false;
+        uint64_t reachable = 0;
+        uint64_t reclaimed = 0;
+    };
+    extern GCResult last_gc;
 }
 
 /*
@@ -458,6 +466,13 @@ public:
     Descriptor* desc_lookup(const char* ptr);
     inline Descriptor* desc_lookup(const void* ptr){return desc_lookup(reinterpret_cast<const char*>(ptr));}
     char* sb_lookup(Descriptor* desc);
+    // count live blocks and their bytes of each size class (0 for large), and
+    // bytes of free blocks in sbs in use, by walking all sbs in the region.
+    // it is called in GC, so it relies only on the anchors and not on lists.
+    void count_blocks(uint64_t* live_blocks, uint64_t* live_bytes, uint64_t* free_bytes);
+    // find the start of the block containing ptr in a sb in use, or nullptr.
+    // a small sb doesn't know which of its blocks are free, so any block in it is found.
+    char* block_start(const char* ptr);
 
 private:
     // helper func
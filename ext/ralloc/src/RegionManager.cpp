@@ -220,6 +220,30 @@ void* RegionManager::__fetch_heap_start(){
     return (void*) (*(((intptr_t*) base_addr) + 1) + (intptr_t) base_addr);
 }
 
+int RegionManager::__grow_persistent_region(const std::string& file_path, uint64_t size){
+    const uint64_t new_filesize = ((size/PAGESIZE)+2)*PAGESIZE;
+    const off_t size_off = 2*sizeof(atomic_pptr<char>);
+    uint64_t old_filesize;
+
+    int fd = open(file_path.c_str(), O_RDWR, S_IRUSR | S_IWUSR);
+    if (fd == -1) return -1;
+    if (pread(fd, &old_filesize, sizeof(uint64_t), size_off) != sizeof(uint64_t) ||
+        new_filesize < old_filesize){
+        close(fd);
+        return -1;
+    }
+
+    // extend the file before recording its size
+    if (ftruncate(fd, new_filesize) != 0 || fsync(fd) != 0 ||
+        pwrite(fd, &new_filesize, sizeof(uint64_t), size_off) != sizeof(uint64_t) ||
+        fsync(fd) != 0){
+        close(fd);
+        return -1;
+    }
+    close(fd);
+    return 0;
+}
+
 int RegionManager::__nvm_region_allocator(void** memptr, size_t alignment, size_t size){
     char* next;
     char* res;
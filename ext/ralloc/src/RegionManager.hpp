@@ -149,6 +149,14 @@ public:
 
     //destroy the region and delete the file
     void __destroy();
+
+    /* grow the file of an unmapped persistent region so that it is
+     * remapped with $size$. The region is extended at its end, so
+     * offsets of existing blocks stay the same.
+     *  0: succeed
+     * -1: fail due to io error or shrinking
+     */
+    static int __grow_persistent_region(const std::string& /*file_path */, uint64_t /*size */);
 };
 
 /*
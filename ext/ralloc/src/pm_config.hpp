@@ -67,7 +67,7 @@ enum RegionIndex : int {
 const uint64_t MAX_DESC_AMOUNT_BITS = 24;
 const uint64_t MIN_SB_REGION_SIZE = 1*1024*1024*1024ULL; // min sb region size
 const uint64_t SB_REGION_EXPAND_SIZE = MIN_SB_REGION_SIZE;
-const int MAX_ROOTS = 1024;
+const int MAX_ROOTS = 16384;
 
 /* System Macros */
 const int TYPE_SIZE = 4;
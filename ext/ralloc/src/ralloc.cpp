@@ -12,6 +12,7 @@
 #include <vector>
 #include <algorithm>
 #include <cstring>
+#include <linux/futex.h>
 
 #include "RegionManager.hpp"
 #include "BaseMeta.hpp"
@@ -26,6 +27,7 @@ namespace ralloc{
     BaseMeta* base_md;
     Regions* _rgs;
     std::function<void(const CrossPtr<char, SB_IDX>&, size_t, GarbageCollection&)> roots_filter_func[MAX_ROOTS];
+    GCResult last_gc;
     extern SizeClass sizeclass;
 };
 using namespace ralloc;
@@ -94,6 +96,7 @@ int RP_init(const char* _id, uint64_t size){
 }
 
 int RP_recover(){
+    last_gc = GCResult();
     return (int) base_md->restart();
 }
 
@@ -101,6 +104,22 @@ void RP_close(){
     delete _holder;
 }
 
+/*
+ * grow the heap file corresponding to id, which must not be mapped.
+ * desc region is grown together since it is indexed by superblocks.
+ * If it fails in the middle, calling it again with the same size completes it.
+ */
+int RP_grow(const char* _id, uint64_t size){
+    if(initialized) return -1;
+    if(size >= MAX_SB_REGION_SIZE || size < MIN_SB_REGION_SIZE) return -1;
+
+    string filepath = HEAPFILE_PREFIX + string(_id);
+    uint64_t num_sb = size/SBSIZE;
+    if(RegionManager::__grow_persistent_region(filepath+"_desc", num_sb*DESCSIZE) != 0) return -1;
+    if(RegionManager::__grow_persistent_region(filepath+"_sb", num_sb*SBSIZE) != 0) return -1;
+    return 0;
+}
+
 void* RP_malloc(size_t sz){
     assert(initialized&&"RPMalloc isn't initialized!");
     return base_md->do_malloc(sz);
@@ -173,3 +192,55 @@ void RP_set_root_filter(void (*filter_func)(char*, size_t, GarbageCollection&),
     assert(i<MAX_ROOTS);
     ralloc::roots_filter_func[i] = filter_func;
 }
+
+int RP_nr_classes(){
+    return MAX_SZ_IDX;
+}
+
+size_t RP_class_size(int idx){
+    assert(idx>=0 && idx<MAX_SZ_IDX);
+    if(idx == 0) return 0;
+    return sizeclass.get_sizeclass_by_idx(idx)->block_size;
+}
+
+size_t RP_heap_size(){
+    assert(initialized);
+    // the first sb is used for the header of the region
+    return _rgs->regions[SB_IDX]->FILESIZE - SBSIZE;
+}
+
+void RP_count_blocks(uint64_t* live_blocks, uint64_t* live_bytes, uint64_t* free_bytes){
+    assert(initialized);
+    base_md->count_blocks(live_blocks, live_bytes, free_bytes);
+}
+
+void* RP_block_start(void* ptr){
+    assert(initialized);
+    return base_md->block_start(reinterpret_cast<char*>(ptr));
+}
+
+int RP_region_file(int idx, void** base_addr, uint64_t* size){
+    if(base_addr == nullptr || size == nullptr || idx>=_rgs->cur_idx){
+        return 1;
+    }
+    *base_addr = (void*)_rgs->regions[idx]->base_addr;
+    *size = _rgs->regions[idx]->FILESIZE;
+    return 0;
+}
+
+void RP_mark_crashed(void* meta){
+    assert(initialized);
+    // BaseMeta is at the same offset in the copy as in the region
+    char* base = _rgs->regions[META_IDX]->base_addr;
+    BaseMeta* md = reinterpret_cast<BaseMeta*>((char*)meta + ((char*)base_md - base));
+    // dirty_mtx is held while the heap is mapped. the kernel sets this bit
+    // when the holder dies, which is_dirty() sees as EOWNERDEAD.
+    md->dirty_mtx.__data.__lock |= FUTEX_OWNER_DIED;
+}
+
+int RP_last_gc(uint64_t* reachable, uint64_t* reclaimed){
+    if(!last_gc.done) return 0;
+    *reachable = last_gc.reachable;
+    *reclaimed = last_gc.reclaimed;
+    return 1;
+}
@@ -36,6 +36,8 @@ int RP_init(const char* _id, uint64_t size);
 /* return 1 if it's dirty, otherwise 0. */
 int RP_recover();
 void RP_close();
+/* return 0 if the closed heap of id is grown to size, otherwise -1. */
+int RP_grow(const char* _id, uint64_t size);
 void* RP_malloc(size_t sz);
 void RP_free(void* ptr);
 void* RP_set_root(void* ptr, uint64_t i);
@@ -50,6 +52,38 @@ int RP_region_range(int idx, void** start_addr, void** end_addr);
 void RP_set_root_filter(
     void (*filter_func)(char*, size_t, GarbageCollection&),
     uint64_t i);
+/* return the number of size classes. class 0 is for large blocks. */
+int RP_nr_classes();
+/* return the block size of size class idx, or 0 for large blocks. */
+size_t RP_class_size(int idx);
+/* return the capacity of the superblock region in bytes. */
+size_t RP_heap_size();
+/*
+ * write the number and bytes of live blocks of each size class to live_blocks
+ * and live_bytes (arrays of RP_nr_classes()), and bytes of free blocks in
+ * superblocks in use to free_bytes. blocks in thread caches count as live.
+ */
+void RP_count_blocks(uint64_t* live_blocks, uint64_t* live_bytes, uint64_t* free_bytes);
+/*
+ * return 1 and write the result of the GC of the last RP_recover if it ran,
+ * otherwise 0.
+ */
+int RP_last_gc(uint64_t* reachable, uint64_t* reclaimed);
+/*
+ * return the start of the block containing ptr if it is in a superblock in
+ * use, otherwise nullptr. free blocks in a small superblock are also found.
+ */
+void* RP_block_start(void* ptr);
+/*
+ * return 1 if the query is invalid, otherwise 0 and write the address where
+ * the file of region idx is mapped and the size of the file.
+ */
+int RP_region_file(int idx, void** base_addr, uint64_t* size);
+/*
+ * mark the dirty flag in meta, a copy of the file of META_IDX region, as if
+ * this process had crashed, so that the copy is garbage-collected when opened.
+ */
+void RP_mark_crashed(void* meta);
 #ifdef __cplusplus
 }
 #endif
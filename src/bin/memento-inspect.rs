@@ -0,0 +1,62 @@
+//! Print the metadata, heap usage, and contents of a pool without changing it
+//!
+//! ```text
+//! memento-inspect <filepath> <size> [--root <queue|stack|list>]
+//! ```
+//!
+//! With `--root`, the root object is regarded as the given data structure of `usize` elements
+//! (or a wrapper whose first field is it), and its contents are summarized.
+
+use std::process::exit;
+
+use memento::ds::{list::List, queue::Queue, treiber_stack::TreiberStack};
+use memento::pmem::inspect::{Inspect, PoolView};
+
+const USAGE: &str = "usage: memento-inspect <filepath> <size> [--root <queue|stack|list>]";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1)
+}
+
+/// Parse size in bytes with an optional suffix (K, M, G)
+fn parse_size(s: &str) -> Option<usize> {
+    let (num, unit) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+fn summary<T: Inspect>(view: &PoolView) -> String {
+    match unsafe { view.root_obj::<T>() } {
+        Some(obj) => obj.summary(view.pool()),
+        None => "no root object".to_owned(),
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (filepath, size, root) = match args.as_slice() {
+        [filepath, size] => (filepath, size, None),
+        [filepath, size, opt, root] if opt == "--root" => (filepath, size, Some(root.as_str())),
+        _ => fail(USAGE),
+    };
+    let size = parse_size(size).unwrap_or_else(|| fail(USAGE));
+
+    let view = PoolView::open(filepath, size)
+        .unwrap_or_else(|e| fail(&format!("cannot open {filepath}: {e}")));
+    print!("{}", view.report());
+
+    if let Some(root) = root {
+        let summary = match root {
+            "queue" => summary::<Queue<usize>>(&view),
+            "stack" => summary::<TreiberStack<usize>>(&view),
+            "list" => summary::<List<usize, usize>>(&view),
+            _ => fail(USAGE),
+        };
+        println!("root: {summary}");
+    }
+}
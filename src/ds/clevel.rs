@@ -438,9 +438,9 @@ impl<T: Collectable> Collectable for Node<T> {
         let guard = unsafe { epoch::unprotected() };
 
         let mut data = node.data.load(Ordering::SeqCst, guard);
-        let data_ref = unsafe { data.deref_mut(pool) };
+        let data_ref = unsafe { &mut *(data.deref_mut(pool) as *mut [MaybeUninit<T>]) };
         for b in data_ref.iter_mut() {
-            MaybeUninit::<T>::mark(b, tid, gc);
+            MaybeUninit::<T>::mark(b, tid, gc, pool);
         }
 
         DetectableCASAtomic::filter(&mut node.next, tid, gc, pool);
@@ -458,7 +458,7 @@ impl<'g, T: Debug + Collectable> Iterator for NodeIter<'g, T> {
     type Item = &'g [MaybeUninit<T>];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let pool = global_pool().unwrap();
+        let pool = self.handle.pool;
         let inner_ref = unsafe { self.inner.as_ref(pool) }?;
         self.inner = if self.inner == self.last {
             PShared::null()
@@ -708,7 +708,7 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Context<K, V> {
                 .cas_non_detectable(find_result.slot_ptr, PShared::null(), handle)
             {
                 Ok(_) => unsafe {
-                    handle.guard.defer_pdestroy(find_result.slot_ptr);
+                    handle.guard.defer_pdestroy(find_result.slot_ptr, handle.pool);
                 },
                 Err(e) => {
                     if e == find_result.slot_ptr.with_tag(1) {
@@ -790,7 +790,7 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
             .next
             .cas(PShared::null(), my_node, &mut mmt.next_cas, handle)
         {
-            unsafe { guard.defer_pdestroy(my_node) };
+            unsafe { guard.defer_pdestroy(my_node, pool) };
             return e;
         }
 
@@ -873,7 +873,7 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
             );
 
             if out {
-                unsafe { guard.defer_pdestroy(ctx_new) };
+                unsafe { guard.defer_pdestroy(ctx_new, pool) };
                 return (cur, false);
             }
 
@@ -1237,10 +1237,10 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
                 &mut mmt.ctx_cas,
                 handle,
             ) {
-                unsafe { guard.defer_pdestroy(ctx_new) }; // ctx_new: stable by checkpoint
+                unsafe { guard.defer_pdestroy(ctx_new, pool) }; // ctx_new: stable by checkpoint
                 phi = PAtomic::from(e);
             } else {
-                unsafe { guard.defer_pdestroy(old_last_lv) }; // old_last_lv: stable because the `ctx_ref` is stable.
+                unsafe { guard.defer_pdestroy(old_last_lv, pool) }; // old_last_lv: stable because the `ctx_ref` is stable.
                 return ctx_new;
             }
         }
@@ -1706,13 +1706,13 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
                 || {
                     let (ctx, find_res) = self.find(&key, key_tag, key_hashes, handle);
                     let found = find_res.is_some();
+                    // slot is null if found or out of memory
                     let slot = if found {
                         PAtomic::null()
                     } else {
-                        PAtomic::from(
-                            alloc_persist(Slot { key, value }, pool)
-                                .with_high_tag(key_tag as usize),
-                        )
+                        try_alloc_persist(Slot { key, value }, pool).map_or(PAtomic::null(), |s| {
+                            PAtomic::from(s.with_high_tag(key_tag as usize))
+                        })
                     };
                     (found, slot, PAtomic::from(ctx))
                 },
@@ -1730,6 +1730,10 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
             return Err(InsertError::Occupied);
         }
 
+        if slot.is_null() {
+            return Err(InsertError::OutOfMemory);
+        }
+
         let (ctx_new, ins_res) = self.insert_inner(
             ctx,  // stable by checkpoint
             slot, // stable by checkpoint
@@ -1787,7 +1791,7 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
             return Err(());
         }
 
-        unsafe { guard.defer_pdestroy(slot_ptr) };
+        unsafe { guard.defer_pdestroy(slot_ptr, pool) };
         Ok(true)
     }
 
@@ -1804,6 +1808,7 @@ impl<K: Debug + PartialEq + Hash, V: Debug + Collectable> Clevel<K, V> {
 #[derive(Debug, Clone)]
 pub enum InsertError {
     Occupied,
+    OutOfMemory,
 }
 
 #[cfg(test)]
@@ -2190,3 +2195,9 @@ fn alloc_persist<T>(init: T, pool: &PoolHandle) -> POwned<T> {
     persist_obj(unsafe { ptr.deref(pool) }, true);
     ptr
 }
+
+fn try_alloc_persist<T>(init: T, pool: &PoolHandle) -> Result<POwned<T>, AllocError> {
+    let ptr = POwned::try_new(init, pool)?;
+    persist_obj(unsafe { ptr.deref(pool) }, true);
+    Ok(ptr)
+}
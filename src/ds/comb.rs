@@ -1,23 +1,22 @@
 //! Detectable Combining Operation
 #![allow(missing_docs)]
-use crate::pepoch::PAtomic;
+use crate::pepoch::{unprotected, PAtomic, POwned};
 use crate::ploc::Handle;
 use crate::pmem::{persist_obj, Collectable, GarbageCollection, PoolHandle};
 use crate::Memento;
-use array_init::array_init;
 use crossbeam_epoch::Guard;
 use crossbeam_utils::{Backoff, CachePadded};
 use libc::c_void;
 use mmt_derive::Collectable;
+use std::mem::MaybeUninit;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use self::combining_lock::CombiningLock;
 
-pub const MAX_THREADS: usize = 64;
 const COMBINING_ROUNDS: usize = 20;
 
-/// restriction of combining iteration
-pub static mut NR_THREADS: usize = MAX_THREADS;
+/// restriction of combining iteration (every thread of the pool by default)
+pub static mut NR_THREADS: usize = usize::MAX;
 
 /// Node
 #[derive(Debug, Collectable)]
@@ -44,58 +43,101 @@ pub struct CombRequest {
     activate: AtomicUsize,
 }
 
+/// Per-thread entries of tid `0..=max_threads` of the pool, allocated in the pool
+#[derive(Debug)]
+pub struct PerThreadArr<T> {
+    arr: PAtomic<[MaybeUninit<T>]>,
+}
+
+impl<T: Default> PerThreadArr<T> {
+    pub fn new(pool: &PoolHandle) -> Self {
+        let mut arr = POwned::<[MaybeUninit<T>]>::init(pool.max_threads() + 1, pool);
+        let arr_ref = unsafe { arr.deref_mut(pool) };
+        for e in arr_ref.iter_mut() {
+            let _ = e.write(T::default());
+        }
+        persist_obj(arr_ref, true);
+        Self {
+            arr: PAtomic::from(arr),
+        }
+    }
+}
+
+impl<T> PerThreadArr<T> {
+    #[inline]
+    pub fn get<'p>(&self, pool: &'p PoolHandle) -> &'p [T] {
+        let arr = unsafe { self.arr.load(Ordering::Relaxed, unprotected()).deref(pool) };
+        unsafe { &*(arr as *const [MaybeUninit<T>] as *const [T]) }
+    }
+}
+
+impl<T: Collectable> Collectable for PerThreadArr<T> {
+    fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        let mut arr = s.arr.load(Ordering::Relaxed, unsafe { unprotected() });
+        if arr.is_null() {
+            return;
+        }
+        let arr_ref = unsafe { &mut *(arr.deref_mut(pool) as *mut [MaybeUninit<T>]) };
+        for e in arr_ref.iter_mut() {
+            MaybeUninit::<T>::mark(e, tid, gc, pool);
+        }
+    }
+}
+
 /// state obj
 #[derive(Debug)]
 pub struct CombStateRec {
     pub data: PAtomic<c_void>, // The actual data of the state (e.g. tail for enqueue, head for dequeue)
-    return_value: [usize; MAX_THREADS + 1],
-    deactivate: [AtomicUsize; MAX_THREADS + 1],
+    return_value: PerThreadArr<AtomicUsize>,
+    deactivate: PerThreadArr<AtomicUsize>,
 }
 
 impl CombStateRec {
-    pub fn new<T>(data: PAtomic<T>) -> Self {
+    pub fn new<T>(data: PAtomic<T>, pool: &PoolHandle) -> Self {
         Self {
             data: unsafe { (&data as *const _ as *const PAtomic<c_void>).read() },
-            return_value: array_init(|_| Default::default()),
-            deactivate: array_init(|_| Default::default()),
+            return_value: PerThreadArr::new(pool),
+            deactivate: PerThreadArr::new(pool),
         }
     }
+
+    /// Copy `other` into itself (the per-thread entries are copied, not shared)
+    fn copy_from(&mut self, other: &Self, pool: &PoolHandle) {
+        self.data = other.data.clone();
+        for (dst, src) in [
+            (&self.return_value, &other.return_value),
+            (&self.deactivate, &other.deactivate),
+        ] {
+            for (d, s) in dst.get(pool).iter().zip(src.get(pool)) {
+                d.store(s.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn persist(&self, pool: &PoolHandle) {
+        persist_obj(self.return_value.get(pool), false);
+        persist_obj(self.deactivate.get(pool), false);
+        persist_obj(self, true);
+    }
 }
 
 impl Collectable for CombStateRec {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         Collectable::filter(&mut s.data, tid, gc, pool);
-    }
-}
-
-impl Clone for CombStateRec {
-    fn clone(&self) -> Self {
-        Self {
-            data: self.data.clone(),
-            return_value: array_init(|i| self.return_value[i]),
-            deactivate: array_init(|i| {
-                AtomicUsize::new(self.deactivate[i].load(Ordering::Relaxed))
-            }),
-        }
+        Collectable::filter(&mut s.return_value, tid, gc, pool);
+        Collectable::filter(&mut s.deactivate, tid, gc, pool);
     }
 }
 
 /// per-thread state for combining
-#[derive(Debug)]
+///
+/// Its states are allocated at the first combine of the thread.
+#[derive(Debug, Default)]
 pub struct CombThreadState {
     index: AtomicUsize,
     state: [PAtomic<CombStateRec>; 2],
 }
 
-impl CombThreadState {
-    pub fn new<T>(data: PAtomic<T>, pool: &PoolHandle) -> Self {
-        Self {
-            index: Default::default(),
-            state: array_init(|_| PAtomic::new(CombStateRec::new(data.clone()), pool)),
-        }
-    }
-}
-
 impl Collectable for CombThreadState {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         Collectable::filter(&mut s.state[0], tid, gc, pool);
@@ -114,15 +156,13 @@ pub struct CombStruct {
     lock: &'static CachePadded<CombiningLock>,
 
     // Variables located at persistent location
-    request: [CachePadded<CombRequest>; MAX_THREADS + 1], // per-thread requests
-    pub pstate: CachePadded<PAtomic<CombStateRec>>,       // stable state
+    request: PerThreadArr<CachePadded<CombRequest>>, // per-thread requests
+    pub pstate: CachePadded<PAtomic<CombStateRec>>,  // stable state
 }
 
 impl Collectable for CombStruct {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
-        for t in 0..s.request.len() {
-            Collectable::filter(&mut *s.request[t], tid, gc, pool);
-        }
+        Collectable::filter(&mut s.request, tid, gc, pool);
         Collectable::filter(&mut *s.pstate, tid, gc, pool);
     }
 }
@@ -132,14 +172,14 @@ impl CombStruct {
         final_func: Option<&'static dyn Fn(&CombStruct, &Guard, &PoolHandle)>,
         after_func: Option<&'static dyn Fn(&CombStruct, &Guard, &PoolHandle)>,
         lock: &'static CachePadded<CombiningLock>,
-        request: [CachePadded<CombRequest>; MAX_THREADS + 1],
         pstate: CachePadded<PAtomic<CombStateRec>>,
+        pool: &PoolHandle,
     ) -> Self {
         Self {
             final_func,
             after_func,
             lock,
-            request,
+            request: PerThreadArr::new(pool),
             pstate,
         }
     }
@@ -161,42 +201,42 @@ impl Combining {
         handle: &Handle,
     ) -> usize {
         let (tid, guard, pool) = (handle.tid, &handle.guard, handle.pool);
+        let request = &s.request.get(pool)[tid];
 
         // Checkpoint activate
-        let activate =
-            mmt.chk_activate(s.request[tid].activate.load(Ordering::Relaxed) + 1, handle);
+        let activate = mmt.chk_activate(request.activate.load(Ordering::Relaxed) + 1, handle);
 
         // Check if my request was already performed.
         if handle.rec.load(Ordering::Relaxed) {
             let latest_state = unsafe { s.pstate.load(Ordering::SeqCst, guard).deref(pool) };
-            let deactivate = latest_state.deactivate[tid].load(Ordering::SeqCst);
+            let deactivate = latest_state.deactivate.get(pool)[tid].load(Ordering::SeqCst);
             if activate < deactivate {
                 return mmt.peek_retval();
             }
 
             // if i was a combiner, i have to finalize the combine.
             if activate == deactivate && !s.lock.is_owner(tid) {
-                let retval = latest_state.return_value[tid];
+                let retval = latest_state.return_value.get(pool)[tid].load(Ordering::Relaxed);
                 mmt.backup_retval(retval);
                 return retval;
             }
 
-            if activate < s.request[tid].activate.load(Ordering::Relaxed) {
+            if activate < request.activate.load(Ordering::Relaxed) {
                 return mmt.peek_retval();
             }
             handle.rec.store(false, Ordering::Relaxed);
         }
 
         // Register request
-        s.request[tid].arg.store(arg, Ordering::Relaxed);
-        s.request[tid].activate.store(activate, Ordering::Release);
+        request.arg.store(arg, Ordering::Relaxed);
+        request.activate.store(activate, Ordering::Release);
 
         // Do
         loop {
             match s.lock.try_lock(tid) {
                 Ok(_) => return Self::do_combine((s, st_thread, sfunc), mmt, handle),
                 Err(_) => {
-                    if let Ok(retval) = Self::do_non_combine(s, mmt, handle.tid) {
+                    if let Ok(retval) = Self::do_non_combine(s, mmt, handle) {
                         return retval;
                     }
                 }
@@ -235,22 +275,41 @@ impl Combining {
         // ready
         let ind = st_thread.index.load(Ordering::Relaxed);
         let mut new_state = st_thread.state[ind].load(Ordering::Relaxed, guard);
+        if new_state.is_null() {
+            new_state = POwned::new(CombStateRec::new(PAtomic::<c_void>::null(), pool), pool)
+                .into_shared(guard);
+            persist_obj(unsafe { new_state.deref(pool) }, false);
+            st_thread.state[ind].store(new_state, Ordering::Relaxed);
+            persist_obj(&st_thread.state[ind], true);
+        }
         let new_state_ref = unsafe { new_state.deref_mut(pool) };
-        *new_state_ref = unsafe { s.pstate.load(Ordering::Relaxed, guard).deref(pool) }.clone(); // create a copy of current state
+        new_state_ref.copy_from(
+            unsafe { s.pstate.load(Ordering::Relaxed, guard).deref(pool) },
+            pool,
+        ); // create a copy of current state
 
         // perform requests
+        let requests = s.request.get(pool);
+        let (return_value, deactivate) = (
+            new_state_ref.return_value.get(pool),
+            new_state_ref.deactivate.get(pool),
+        );
+        let nr_threads = unsafe { NR_THREADS }.min(requests.len() - 1);
         for _ in 0..COMBINING_ROUNDS {
             let mut serve_reqs = 0;
 
-            for t in 1..unsafe { NR_THREADS } + 1 {
-                let t_activate = s.request[t].activate.load(Ordering::Acquire);
-                if t_activate > new_state_ref.deactivate[t].load(Ordering::Relaxed) {
-                    new_state_ref.return_value[t] = sfunc(
-                        &new_state_ref.data,
-                        s.request[t].arg.load(Ordering::Relaxed),
-                        handle,
+            for t in 1..nr_threads + 1 {
+                let t_activate = requests[t].activate.load(Ordering::Acquire);
+                if t_activate > deactivate[t].load(Ordering::Relaxed) {
+                    return_value[t].store(
+                        sfunc(
+                            &new_state_ref.data,
+                            requests[t].arg.load(Ordering::Relaxed),
+                            handle,
+                        ),
+                        Ordering::Relaxed,
                     );
-                    new_state_ref.deactivate[t].store(t_activate, Ordering::Release);
+                    deactivate[t].store(t_activate, Ordering::Release);
 
                     // cnt
                     serve_reqs += 1;
@@ -266,7 +325,7 @@ impl Combining {
         if let Some(func) = s.final_func {
             func(s, guard, pool);
         }
-        persist_obj(new_state_ref, true);
+        new_state_ref.persist(pool);
 
         // 3.1 central state = pt.state[pt.index] (commit point)
         s.pstate.store(new_state, Ordering::Release);
@@ -283,7 +342,7 @@ impl Combining {
         // 3.3. release lock with new state
         unsafe { s.lock.unlock(new_state_ref as *const _ as usize) };
 
-        let retval = new_state_ref.return_value[tid];
+        let retval = return_value[tid].load(Ordering::Relaxed);
         mmt.backup_retval(retval);
         retval
     }
@@ -293,8 +352,9 @@ impl Combining {
         // &self,
         s: &CombStruct,
         mmt: &mut M,
-        tid: usize,
+        handle: &Handle,
     ) -> Result<usize, ()> {
+        let (tid, pool) = (handle.tid, handle.pool);
         // wait until the combiner unlocks the lock
         let backoff = Backoff::new();
         let mut combined_ptr;
@@ -312,10 +372,10 @@ impl Combining {
 
         // check if my request was performed
         let lastest_state = unsafe { (combined_ptr as *const CombStateRec).as_ref().unwrap() };
-        if s.request[tid].activate.load(Ordering::Relaxed)
-            <= lastest_state.deactivate[tid].load(Ordering::Acquire)
+        if s.request.get(pool)[tid].activate.load(Ordering::Relaxed)
+            <= lastest_state.deactivate.get(pool)[tid].load(Ordering::Acquire)
         {
-            let retval = lastest_state.return_value[tid];
+            let retval = lastest_state.return_value.get(pool)[tid].load(Ordering::Relaxed);
             mmt.backup_retval(retval);
             return Ok(retval);
         }
@@ -329,14 +389,14 @@ pub mod combining_lock {
     use core::sync::atomic::Ordering;
     use std::sync::atomic::AtomicUsize;
 
-    use crate::impl_left_bits;
+    use crate::{impl_left_bits, pepoch::atomic::NR_TID_BITS};
 
     // Auxiliary Bits
-    // aux bits: MSB 55-bit in 64-bit
+    // aux bits: MSB 52-bit in 64-bit (the rest for tid bits as many as those of `PAtomic`)
     // Used for:
     // - Comb: Indicating ptr of combined state
     pub(crate) const POS_AUX_BITS: u32 = 0;
-    pub(crate) const NR_AUX_BITS: u32 = 55;
+    pub(crate) const NR_AUX_BITS: u32 = usize::BITS - NR_TID_BITS;
     impl_left_bits!(aux_bits, POS_AUX_BITS, NR_AUX_BITS, usize);
 
     #[inline]
@@ -355,7 +415,7 @@ pub mod combining_lock {
     /// thread-recoverable spin lock
     #[derive(Debug, Default)]
     pub struct CombiningLock {
-        inner: AtomicUsize, // 55:ptr of state, 9:tid occupying the lock
+        inner: AtomicUsize, // 52:ptr of state, 12:tid occupying the lock
     }
 
     impl CombiningLock {
@@ -7,9 +7,9 @@ use crossbeam_utils::CachePadded;
 use etrace::some_or;
 use std::cmp::Ordering::{Equal, Greater, Less};
 
-use crate::pepoch::{PAtomic, PDestroyable, POwned, PShared};
-use crate::pmem::alloc::{Collectable, GarbageCollection};
-use crate::pmem::{ll::*, pool::*, AsPPtr, PPtr};
+use crate::pepoch::{self as epoch, PAtomic, PDestroyable, POwned, PShared};
+use crate::pmem::alloc::{AllocError, Collectable, GarbageCollection};
+use crate::pmem::{inspect::Inspect, ll::*, pool::*, AsPPtr, PPtr};
 use crate::*;
 use mmt_derive::Collectable;
 
@@ -136,7 +136,13 @@ enum ListErr {
 
 /// Insertion error
 #[derive(Debug)]
-pub struct KeyExists;
+pub enum InsertError {
+    /// The key already exists
+    KeyExists,
+
+    /// The pool is out of memory
+    OutOfMemory,
+}
 
 /// Deletion error
 #[derive(Debug)]
@@ -156,6 +162,27 @@ impl<K, V: Collectable> PDefault for List<K, V> {
     }
 }
 
+impl<K, V: Collectable> Inspect for List<K, V> {
+    fn summary(&self, pool: &PoolHandle) -> String {
+        let guard = unsafe { epoch::unprotected() };
+        let (mut len, mut nr_marked) = (0, 0);
+        let mut curr = self.head.load_raw(guard);
+        while let Some(curr_node) = curr.and_then(|c| unsafe { c.as_ref(pool) }) {
+            curr = curr_node.next.load_raw(guard);
+            match curr {
+                Some(next) if next.tag() != 0 => nr_marked += 1,
+                Some(_) => len += 1,
+                None => {
+                    return format!(
+                        "list of at least {len} elements ({nr_marked} logically deleted, traversal stopped at a CAS in progress)"
+                    )
+                }
+            }
+        }
+        format!("list of {len} elements ({nr_marked} logically deleted)")
+    }
+}
+
 impl<K: Ord, V: Collectable> List<K, V> {
     fn find_inner<'g>(
         &'g self,
@@ -215,7 +242,7 @@ impl<K: Ord, V: Collectable> List<K, V> {
         while node.with_tag(0) != curr {
             unsafe {
                 let next = node.deref(handle.pool).next.load(Ordering::Acquire, handle);
-                handle.guard.defer_pdestroy(node);
+                handle.guard.defer_pdestroy(node, handle.pool);
                 node = next;
             }
         }
@@ -283,7 +310,7 @@ impl<K: Ord, V: Collectable> List<K, V> {
         };
 
         if found {
-            unsafe { guard.defer_pdestroy(node) };
+            unsafe { guard.defer_pdestroy(node, pool) };
             return Err(ListErr::Fail);
         }
 
@@ -293,31 +320,38 @@ impl<K: Ord, V: Collectable> List<K, V> {
     }
 
     /// Insert
+    ///
+    /// Fail if the pool is out of memory. The failure is checkpointed, so it is also returned after a crash.
     pub fn insert(
         &self,
         key: K,
         value: V,
         ins: &mut Insert<K, V>,
         handle: &Handle,
-    ) -> Result<(), KeyExists> {
+    ) -> Result<(), InsertError> {
         let (guard, pool) = (&handle.guard, handle.pool);
         let node = ins
             .node
             .checkpoint(
-                || {
-                    let node = POwned::new(Node::from((key, value)), pool);
-                    persist_obj(unsafe { node.deref(pool) }, true);
-                    PAtomic::from(node)
+                || match POwned::try_new(Node::from((key, value)), pool) {
+                    Ok(node) => {
+                        persist_obj(unsafe { node.deref(pool) }, true);
+                        PAtomic::from(node)
+                    }
+                    Err(AllocError) => PAtomic::null(),
                 },
                 handle,
             )
             .load(Ordering::Relaxed, guard);
-        let node_ref = unsafe { node.deref(pool) };
+        let node_ref = some_or!(
+            unsafe { node.as_ref(pool) },
+            return Err(InsertError::OutOfMemory)
+        );
 
         loop {
             match self.try_insert(node, &node_ref.key, &mut ins.try_ins, handle) {
                 Ok(()) => return Ok(()),
-                Err(ListErr::Fail) => return Err(KeyExists),
+                Err(ListErr::Fail) => return Err(InsertError::KeyExists),
                 Err(ListErr::Retry) => (),
             };
         }
@@ -384,7 +418,7 @@ impl<K: Ord, V: Collectable> List<K, V> {
         }
 
         if prev.cas(curr, next, &mut try_del.physical, handle).is_ok() {
-            unsafe { handle.guard.defer_pdestroy(curr) };
+            unsafe { handle.guard.defer_pdestroy(curr, handle.pool) };
         }
 
         Ok(())
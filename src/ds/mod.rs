@@ -7,6 +7,7 @@ pub mod queue;
 pub mod queue_comb;
 pub mod queue_general;
 pub mod queue_lp;
+pub mod scheduler;
 pub mod stack;
 // // pub mod tlock;
 pub mod treiber_stack;
@@ -8,8 +8,8 @@ use insert_delete::{Delete, Insert};
 use std::mem::MaybeUninit;
 
 use crate::pepoch::{self as epoch, Guard, PAtomic, POwned, PShared};
-use crate::pmem::alloc::{Collectable, GarbageCollection};
-use crate::pmem::{ll::*, pool::*};
+use crate::pmem::alloc::{AllocError, Collectable, GarbageCollection};
+use crate::pmem::{inspect::Inspect, ll::*, pool::*};
 use crate::*;
 use mmt_derive::Collectable;
 
@@ -177,6 +177,21 @@ impl<T: Clone + Collectable> Traversable<Node<T>> for Queue<T> {
     }
 }
 
+impl<T: Clone + Collectable> Inspect for Queue<T> {
+    fn summary(&self, pool: &PoolHandle) -> String {
+        let guard = unsafe { epoch::unprotected() };
+        let mut nr_nodes = 0;
+        let mut curr = self.head.load(false, Ordering::SeqCst, guard);
+        while let Some(curr_ref) = unsafe { curr.as_ref(pool) } {
+            nr_nodes += 1;
+            curr = curr_ref.next.load(false, Ordering::SeqCst, guard);
+        }
+
+        // The first node is the sentinel
+        format!("queue of {} elements", nr_nodes - 1)
+    }
+}
+
 impl<T: Clone + Collectable> Queue<T> {
     /// Try enqueue
     pub fn try_enqueue(
@@ -220,20 +235,34 @@ impl<T: Clone + Collectable> Queue<T> {
     }
 
     /// Enqueue
-    pub fn enqueue(&self, value: T, enq: &mut Enqueue<T>, handle: &Handle) {
+    ///
+    /// Fail if the pool is out of memory. The failure is checkpointed, so it is also returned after a crash.
+    pub fn enqueue(
+        &self,
+        value: T,
+        enq: &mut Enqueue<T>,
+        handle: &Handle,
+    ) -> Result<(), AllocError> {
         let node = enq
             .node
             .checkpoint(
-                || {
-                    let node = POwned::new(Node::from(value), handle.pool);
-                    persist_obj(unsafe { node.deref(handle.pool) }, true);
-                    PAtomic::from(node)
+                || match POwned::try_new(Node::from(value), handle.pool) {
+                    Ok(node) => {
+                        persist_obj(unsafe { node.deref(handle.pool) }, true);
+                        PAtomic::from(node)
+                    }
+                    Err(AllocError) => PAtomic::null(),
                 },
                 handle,
             )
             .load(Ordering::Relaxed, &handle.guard);
 
+        if node.is_null() {
+            return Err(AllocError);
+        }
+
         while self.try_enqueue(node, &mut enq.try_enq, handle).is_err() {}
+        Ok(())
     }
 
     /// Try dequeue
@@ -359,11 +388,14 @@ pub(crate) mod test {
             let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
 
             for seq in 0..NR_COUNT {
-                let _ = self.obj.enqueue(
-                    TestValue::new(handle.tid, seq),
-                    &mut enq_deq.enqs[seq],
-                    handle,
-                );
+                assert!(self
+                    .obj
+                    .enqueue(
+                        TestValue::new(handle.tid, seq),
+                        &mut enq_deq.enqs[seq],
+                        handle,
+                    )
+                    .is_ok());
                 let res = self.obj.dequeue(&mut enq_deq.deqs[seq], handle);
 
                 assert!(res.is_some(), "tid:{}, seq:{seq}", handle.tid);
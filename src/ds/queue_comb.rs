@@ -6,7 +6,6 @@ use crate::pepoch::{unprotected, PAtomic, PDestroyable, POwned};
 use crate::ploc::{Checkpoint, Handle};
 use crate::pmem::{persist_obj, sfence, Collectable, GarbageCollection, PPtr, PoolHandle};
 use crate::{Memento, PDefault};
-use array_init::array_init;
 use crossbeam_epoch::Guard;
 use crossbeam_utils::CachePadded;
 use libc::c_void;
@@ -15,7 +14,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use tinyvec::{tiny_vec, TinyVec};
 
 use super::comb::{
-    CombStateRec, CombStruct, CombThreadState, Combinable, Combining, Node, MAX_THREADS,
+    CombStateRec, CombStruct, CombThreadState, Combinable, Combining, Node, PerThreadArr,
 };
 
 /// memento for enqueue
@@ -89,11 +88,11 @@ struct DequeueCombStruct {
 pub struct CombiningQueue {
     // Shared non-volatile variables used by Enqueue
     enqueue_struct: CachePadded<EnqueueCombStruct>,
-    enqueue_thread_state: [CachePadded<CombThreadState>; MAX_THREADS + 1],
+    enqueue_thread_state: PerThreadArr<CachePadded<CombThreadState>>,
 
     // Shared non-volatile variables used by Dequeue
     dequeue_struct: CachePadded<DequeueCombStruct>,
-    dequeue_thread_state: [CachePadded<CombThreadState>; MAX_THREADS + 1],
+    dequeue_thread_state: PerThreadArr<CachePadded<CombThreadState>>,
 }
 
 unsafe impl Sync for CombiningQueue {}
@@ -102,13 +101,9 @@ unsafe impl Send for CombiningQueue {}
 impl Collectable for CombiningQueue {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         Collectable::filter(&mut *s.enqueue_struct, tid, gc, pool);
-        for tstate in &mut s.enqueue_thread_state {
-            Collectable::filter(&mut **tstate, tid, gc, pool);
-        }
+        Collectable::filter(&mut s.enqueue_thread_state, tid, gc, pool);
         Collectable::filter(&mut *s.dequeue_struct, tid, gc, pool);
-        for tstate in &mut s.dequeue_thread_state {
-            Collectable::filter(&mut **tstate, tid, gc, pool);
-        }
+        Collectable::filter(&mut s.dequeue_thread_state, tid, gc, pool);
 
         // initialize global volatile variables
         let tail = s
@@ -147,27 +142,29 @@ impl PDefault for CombiningQueue {
                     Some(&Self::persist_new_nodes), // persist new nodes
                     Some(&Self::update_old_tail),   // update old tail
                     &E_LOCK,
-                    array_init(|_| CachePadded::new(Default::default())),
-                    CachePadded::new(PAtomic::new(CombStateRec::new(PAtomic::from(dummy)), pool)),
+                    CachePadded::new(PAtomic::new(
+                        CombStateRec::new(PAtomic::from(dummy), pool),
+                        pool,
+                    )),
+                    pool,
                 )),
                 tail: CachePadded::new(PAtomic::from(dummy)),
             }),
-            enqueue_thread_state: array_init(|_| {
-                CachePadded::new(CombThreadState::new(PAtomic::from(dummy), pool))
-            }),
+            enqueue_thread_state: PerThreadArr::new(pool),
             dequeue_struct: CachePadded::new(DequeueCombStruct {
                 inner: CachePadded::new(CombStruct::new(
                     None,
                     None,
                     &D_LOCK,
-                    array_init(|_| CachePadded::new(Default::default())),
-                    CachePadded::new(PAtomic::new(CombStateRec::new(PAtomic::from(dummy)), pool)),
+                    CachePadded::new(PAtomic::new(
+                        CombStateRec::new(PAtomic::from(dummy), pool),
+                        pool,
+                    )),
+                    pool,
                 )),
                 head: CachePadded::new(PAtomic::from(dummy)),
             }),
-            dequeue_thread_state: array_init(|_| {
-                CachePadded::new(CombThreadState::new(PAtomic::from(dummy), pool))
-            }),
+            dequeue_thread_state: PerThreadArr::new(pool),
         }
     }
 }
@@ -179,7 +176,7 @@ impl CombiningQueue {
             arg,
             (
                 &self.enqueue_struct.inner,
-                &self.enqueue_thread_state[handle.tid],
+                &self.enqueue_thread_state.get(handle.pool)[handle.tid],
                 &Self::enqueue_raw,
             ),
             enq,
@@ -244,7 +241,7 @@ impl CombiningQueue {
             0, // unit-like
             (
                 &self.dequeue_struct.inner,
-                &self.dequeue_thread_state[handle.tid],
+                &self.dequeue_thread_state.get(handle.pool)[handle.tid],
                 &Self::dequeue_raw,
             ),
             deq,
@@ -272,7 +269,7 @@ impl CombiningQueue {
         let ret = head_ref.next.load(Ordering::SeqCst, guard);
         if !ret.is_null() {
             head.store(ret, Ordering::SeqCst);
-            unsafe { guard.defer_pdestroy(head_shared) };
+            unsafe { guard.defer_pdestroy(head_shared, pool) };
             return unsafe { ret.deref(pool) }.data;
         }
         Self::EMPTY
@@ -6,9 +6,9 @@ use core::sync::atomic::Ordering;
 use crossbeam_utils::CachePadded;
 use std::mem::MaybeUninit;
 
-use crate::pepoch::{self as epoch, PAtomic, PDestroyable, POwned, PShared};
+use crate::pepoch::{PAtomic, PDestroyable, POwned, PShared};
 use crate::pmem::alloc::{Collectable, GarbageCollection};
-use crate::pmem::{global_pool, ll::*, pool::*};
+use crate::pmem::{ll::*, pool::*};
 use crate::*;
 use mmt_derive::Collectable;
 
@@ -138,7 +138,10 @@ impl<T: Clone + Collectable> Collectable for QueueGeneral<T> {
         DetectableCASAtomic::filter(&mut queue.head, tid, gc, pool);
 
         // Align head and tail
-        let tmp_handle = Handle::new(tid, epoch::pin(), global_pool().unwrap());
+        // Opened pools live until the end of the process.
+        // (`tid` is not a tid of any thread if the queue is in the root obj, so use tid 1 like the rest of recovery.)
+        let pool = unsafe { &*(pool as *const PoolHandle) };
+        let tmp_handle = Handle::new(1, pool.pin(), pool);
         let head = queue.head.load(Ordering::SeqCst, &tmp_handle);
         let tail = queue.tail.load(Ordering::SeqCst, &tmp_handle);
         let _ = queue.tail.cas_non_detectable(tail, head, &tmp_handle);
@@ -253,7 +256,7 @@ impl<T: Clone + Collectable> QueueGeneral<T> {
         }
 
         Ok(unsafe {
-            guard.defer_pdestroy(head);
+            guard.defer_pdestroy(head, pool);
             Some((*next.deref(pool).data.as_ptr()).clone())
         })
     }
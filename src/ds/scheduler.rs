@@ -0,0 +1,543 @@
+//! Persistent task scheduler
+//!
+//! A `Scheduler` keeps persistent task records in submission order, each of which owns the memento of its task,
+//! and runs them on a fixed set of workers (e.g. root mementos calling `Scheduler::run_worker`).
+//!
+//! A worker claims the oldest pending task by writing its tid to the record, and marks it finished after persisting
+//! its output. A task is run with the tid that claimed it, since the detectable CASes in its memento are bound to
+//! the tid. After a crash, a task that was running is resumed in recovery mode by the worker of the same tid, or,
+//! if no live thread holds that tid (e.g. its root memento was given up), by another worker that takes the tid over
+//! while running it. A task is retired by `Scheduler::wait`, and its record is freed once every earlier task is
+//! retired too.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_utils::{Backoff, CachePadded};
+use etrace::some_or;
+
+use crate::pepoch::atomic::Pointer;
+use crate::pepoch::{self as epoch, PAtomic, PDestroyable, POwned, PShared};
+use crate::ploc::{Cas, Checkpoint, DetectableCASAtomic, Handle, Timestamp};
+use crate::pmem::alloc::{AllocError, Collectable, GarbageCollection};
+use crate::pmem::inspect::Inspect;
+use crate::pmem::ll::*;
+use crate::pmem::{PPtr, PoolHandle};
+use crate::*;
+use mmt_derive::Collectable;
+
+/// Task run by a `Scheduler`
+pub trait Task: Collectable + Send + Sync {
+    /// Memento of the task, which is owned by its record
+    type Memento: Memento;
+
+    /// Output of the task, which is persisted once `run` returns
+    type Output: Clone + Default + Collectable;
+
+    /// Run the task
+    ///
+    /// If a crash occurs, it is called again with `handle.rec` set, so its steps should be detectable with `mmt`.
+    /// `handle` may not be the handle of the worker, and it should not be repinned.
+    fn run(&self, mmt: &mut Self::Memento, handle: &Handle) -> Self::Output;
+}
+
+/// Record of a task
+pub struct TaskNode<T: Task> {
+    /// `None` for the sentinel record that the scheduler starts with
+    task: Option<T>,
+    mmt: T::Memento,
+
+    /// 0 if pending, or the tid of the worker that claimed it (with `FINISHED` and `RETIRED` flags)
+    state: AtomicUsize,
+    output: T::Output,
+    next: DetectableCASAtomic<Self>,
+}
+
+impl<T: Task> TaskNode<T> {
+    const FINISHED: usize = 1 << (usize::BITS - 1);
+    const RETIRED: usize = 1 << (usize::BITS - 2);
+
+    fn new(task: Option<T>) -> Self {
+        Self {
+            state: AtomicUsize::new(if task.is_some() {
+                0
+            } else {
+                Self::FINISHED | Self::RETIRED
+            }),
+            task,
+            mmt: Default::default(),
+            output: Default::default(),
+            next: DetectableCASAtomic::default(),
+        }
+    }
+
+    /// Output of the task if it has finished
+    pub fn output(&self) -> Option<&T::Output> {
+        (self.state.load(Ordering::SeqCst) & Self::FINISHED != 0).then(|| &self.output)
+    }
+
+    /// Tid of the worker running the task, if it is claimed and not finished
+    fn runner(state: usize) -> Option<usize> {
+        (state != 0 && state & Self::FINISHED == 0).then(|| state)
+    }
+}
+
+// `T::Memento` and `T::Output` may not implement `Debug`.
+impl<T: Task> fmt::Debug for TaskNode<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskNode")
+            .field("state", &self.state)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+impl<T: Task> Collectable for TaskNode<T> {
+    fn filter(node: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        // The memento is traced with the tid of the worker that ran it, which recovers the CAS information of the tid.
+        let runner = node.state.load(Ordering::SeqCst) & !(Self::FINISHED | Self::RETIRED);
+        let mmt_tid = if runner == 0 { tid } else { runner };
+
+        Collectable::filter(&mut node.task, tid, gc, pool);
+        <T::Memento as Collectable>::filter(&mut node.mmt, mmt_tid, gc, pool);
+        <T::Output as Collectable>::filter(&mut node.output, tid, gc, pool);
+        DetectableCASAtomic::filter(&mut node.next, tid, gc, pool);
+    }
+}
+
+/// Submit memento
+#[derive(Debug, Memento, Collectable)]
+pub struct Submit<T: Task> {
+    node: Checkpoint<PAtomic<TaskNode<T>>>,
+    tail: Checkpoint<PAtomic<TaskNode<T>>>,
+    insert: Cas<TaskNode<T>>,
+    forward_tail: Cas<TaskNode<T>>,
+}
+
+impl<T: Task> Default for Submit<T> {
+    fn default() -> Self {
+        Self {
+            node: Default::default(),
+            tail: Default::default(),
+            insert: Default::default(),
+            forward_tail: Default::default(),
+        }
+    }
+}
+
+unsafe impl<T: Task> Send for Submit<T> {}
+
+/// Persistent task scheduler
+#[derive(Debug)]
+pub struct Scheduler<T: Task> {
+    head: CachePadded<DetectableCASAtomic<TaskNode<T>>>,
+    tail: CachePadded<DetectableCASAtomic<TaskNode<T>>>,
+
+    /// Record up to which every task is claimed, from which workers look for a pending task
+    ///
+    /// It is volatile and reset to `head` on recovery.
+    cursor: CachePadded<AtomicUsize>,
+}
+
+impl<T: Task> PDefault for Scheduler<T> {
+    fn pdefault(handle: &Handle) -> Self {
+        let sentinel = POwned::new(TaskNode::new(None), handle.pool).into_shared(&handle.guard);
+        persist_obj(unsafe { sentinel.deref(handle.pool) }, true);
+
+        Self {
+            head: CachePadded::new(DetectableCASAtomic::from(sentinel)),
+            tail: CachePadded::new(DetectableCASAtomic::from(sentinel)),
+            cursor: CachePadded::new(AtomicUsize::new(sentinel.into_usize())),
+        }
+    }
+}
+
+impl<T: Task> Collectable for Scheduler<T> {
+    fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        DetectableCASAtomic::filter(&mut s.head, tid, gc, pool);
+
+        // Align tail and cursor with head
+        // (`tid` is not a tid of any thread if the scheduler is in the root obj, so use tid 1 like the rest of recovery.)
+        let pool = unsafe { &*(pool as *const PoolHandle) };
+        let tmp_handle = Handle::new(1, pool.pin(), pool);
+        let head = s.head.load(Ordering::SeqCst, &tmp_handle);
+        let tail = s.tail.load(Ordering::SeqCst, &tmp_handle);
+        let _ = s.tail.cas_non_detectable(tail, head, &tmp_handle);
+        s.cursor.store(head.into_usize(), Ordering::SeqCst);
+    }
+}
+
+impl<T: Task> Scheduler<T> {
+    /// Submit a task
+    ///
+    /// Return its record, from which its output is read once it finishes.
+    /// Fail if the pool is out of memory. The failure is checkpointed, so it is also returned after a crash.
+    pub fn submit(
+        &self,
+        task: T,
+        submit: &mut Submit<T>,
+        handle: &Handle,
+    ) -> Result<PPtr<TaskNode<T>>, AllocError> {
+        let (guard, pool) = (&handle.guard, handle.pool);
+        let node = submit
+            .node
+            .checkpoint(
+                || match POwned::try_new(TaskNode::new(Some(task)), pool) {
+                    Ok(node) => {
+                        persist_obj(unsafe { node.deref(pool) }, true);
+                        PAtomic::from(node)
+                    }
+                    Err(AllocError) => PAtomic::null(),
+                },
+                handle,
+            )
+            .load(Ordering::Relaxed, guard);
+
+        if node.is_null() {
+            return Err(AllocError);
+        }
+
+        loop {
+            let tail = submit
+                .tail
+                .checkpoint(
+                    || {
+                        let tail = loop {
+                            let tail = self.tail.load(Ordering::SeqCst, handle);
+                            let tail_ref = unsafe { tail.deref(pool) };
+                            let next = tail_ref.next.load(Ordering::SeqCst, handle);
+
+                            if next.is_null() {
+                                break tail;
+                            }
+
+                            // tail is stale
+                            let _ = self.tail.cas_non_detectable(tail, next, handle);
+                        };
+                        PAtomic::from(tail)
+                    },
+                    handle,
+                )
+                .load(Ordering::Relaxed, guard);
+            let tail_ref = unsafe { tail.deref(pool) };
+
+            if tail_ref
+                .next
+                .cas(PShared::null(), node, &mut submit.insert, handle)
+                .is_ok()
+            {
+                let _ = self.tail.cas(tail, node, &mut submit.forward_tail, handle);
+                return Ok(node.as_ptr());
+            }
+        }
+    }
+
+    /// Run tasks as the worker of `handle.tid` until no task is pending
+    ///
+    /// It first resumes the task that the worker was running when a crash occurred, if any, and the tasks of
+    /// workers that are not live.
+    /// Each task starts with its own recovery state, and the recovery state of `handle` is restored on return.
+    pub fn run_worker(&self, handle: &Handle) {
+        let (rec, local_max_time) = (
+            handle.rec.load(Ordering::Relaxed),
+            handle.local_max_time.load(),
+        );
+
+        let mut curr = self.head.load(Ordering::SeqCst, handle);
+        while let Some(curr_ref) = unsafe { curr.as_ref(handle.pool) } {
+            if let Some(runner) = TaskNode::<T>::runner(curr_ref.state.load(Ordering::SeqCst)) {
+                if runner == handle.tid {
+                    self.run_task(curr, true, handle);
+                } else {
+                    let _ = self.take_over(curr, runner, handle);
+                }
+            }
+            curr = curr_ref.next.load(Ordering::SeqCst, handle);
+        }
+        while let Some(node) = self.claim(handle) {
+            self.run_task(node, false, handle);
+        }
+
+        handle.rec.store(rec, Ordering::Relaxed);
+        handle.local_max_time.store(local_max_time);
+    }
+
+    /// Wait until the task of `node` finishes, retire it and return its output
+    ///
+    /// If the worker running the task is not live, the task is taken over and resumed in the meantime.
+    /// The record should not be used after it returns.
+    pub fn wait(&self, node: PPtr<TaskNode<T>>, handle: &Handle) -> T::Output {
+        let node_ref = unsafe { node.deref(handle.pool) };
+        let backoff = Backoff::new();
+        loop {
+            if let Some(output) = node_ref.output() {
+                let output = output.clone();
+                let _ = node_ref
+                    .state
+                    .fetch_or(TaskNode::<T>::RETIRED, Ordering::SeqCst);
+                persist_obj(&node_ref.state, true);
+                self.retire_front(handle);
+                return output;
+            }
+            if let Some(runner) = TaskNode::<T>::runner(node_ref.state.load(Ordering::SeqCst)) {
+                if self.take_over(PShared::from(node), runner, handle) {
+                    continue;
+                }
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Claim the oldest pending task for the worker of `handle.tid`
+    fn claim<'g>(&self, handle: &'g Handle) -> Option<PShared<'g, TaskNode<T>>> {
+        let pool = handle.pool;
+        let mut cursor = self.cursor.load(Ordering::SeqCst);
+        loop {
+            let curr = unsafe { PShared::<TaskNode<T>>::from_usize(cursor) };
+            let next = unsafe { curr.deref(pool) }
+                .next
+                .load(Ordering::SeqCst, handle);
+            let next_ref = unsafe { next.as_ref(pool) }?;
+            let claimed = next_ref
+                .state
+                .compare_exchange(0, handle.tid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+
+            // `next` is claimed either way, so move the cursor past it.
+            cursor = match self.cursor.compare_exchange(
+                cursor,
+                next.into_usize(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => next.into_usize(),
+                Err(e) => e,
+            };
+
+            if claimed {
+                persist_obj(&next_ref.state, true);
+                return Some(next);
+            }
+        }
+    }
+
+    /// Resume the task of `node` claimed by `runner` with the tid of `runner`, if no live thread holds the tid
+    ///
+    /// Return whether the tid has been taken over.
+    fn take_over(&self, node: PShared<'_, TaskNode<T>>, runner: usize, handle: &Handle) -> bool {
+        let pool = handle.pool;
+        if runner == handle.tid || !pool.try_acquire_tid(runner) {
+            return false;
+        }
+
+        // The task is resumed unless it has finished before the tid is taken.
+        let node_ref = unsafe { node.deref(pool) };
+        if node_ref.state.load(Ordering::SeqCst) == runner {
+            self.run_task(node, true, &Handle::new(runner, pool.pin(), pool));
+        }
+        pool.release_tid(runner);
+        true
+    }
+
+    fn run_task(&self, mut node: PShared<'_, TaskNode<T>>, resumed: bool, handle: &Handle) {
+        let node_ref = unsafe { node.deref_mut(handle.pool) };
+
+        // The memento of the task has nothing to do with what the worker ran before.
+        handle.rec.store(resumed, Ordering::Relaxed);
+        handle.local_max_time.store(Timestamp::from(0));
+
+        let task = node_ref.task.as_ref().unwrap();
+        let output = task.run(&mut node_ref.mmt, handle);
+        node_ref.output = output;
+        persist_obj(&node_ref.output, true);
+        node_ref
+            .state
+            .store(handle.tid | TaskNode::<T>::FINISHED, Ordering::SeqCst);
+        persist_obj(&node_ref.state, true);
+    }
+
+    /// Unlink retired tasks at the front, which are freed once no thread refers to them
+    fn retire_front(&self, handle: &Handle) {
+        let (guard, pool) = (&handle.guard, handle.pool);
+        loop {
+            let head = self.head.load(Ordering::SeqCst, handle);
+            let next = unsafe { head.deref(pool) }
+                .next
+                .load(Ordering::SeqCst, handle);
+            let next_ref = some_or!(unsafe { next.as_ref(pool) }, return);
+            if next_ref.state.load(Ordering::SeqCst) & TaskNode::<T>::RETIRED == 0 {
+                return;
+            }
+
+            // Neither tail nor cursor is left at the record to free.
+            let tail = self.tail.load(Ordering::SeqCst, handle);
+            if tail == head {
+                let _ = self.tail.cas_non_detectable(tail, next, handle);
+            }
+            let _ = self.cursor.compare_exchange(
+                head.into_usize(),
+                next.into_usize(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+
+            if self.head.cas_non_detectable(head, next, handle).is_ok() {
+                unsafe { guard.defer_pdestroy(head, pool) };
+            }
+        }
+    }
+}
+
+impl<T: Task> Inspect for Scheduler<T> {
+    fn summary(&self, pool: &PoolHandle) -> String {
+        let guard = unsafe { epoch::unprotected() };
+        let (mut pending, mut running, mut finished) = (0, 0, 0);
+        let head = some_or!(
+            self.head.load_raw(guard),
+            return "scheduler of unknown tasks (head is being changed)".to_owned()
+        );
+        let mut curr = some_or!(
+            unsafe { head.deref(pool) }.next.load_raw(guard),
+            return "scheduler of unknown tasks (a task is being submitted)".to_owned()
+        );
+        while let Some(curr_ref) = unsafe { curr.as_ref(pool) } {
+            match curr_ref.state.load(Ordering::SeqCst) {
+                0 => pending += 1,
+                state if state & TaskNode::<T>::FINISHED != 0 => finished += 1,
+                _ => running += 1,
+            }
+            curr = some_or!(
+                curr_ref.next.load_raw(guard),
+                return "scheduler of unknown tasks (a task is being submitted)".to_owned()
+            );
+        }
+        format!("scheduler of {pending} pending, {running} running, {finished} finished tasks")
+    }
+}
+
+unsafe impl<T: Task> Send for Scheduler<T> {}
+unsafe impl<T: Task> Sync for Scheduler<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pmem::RootObj;
+    use crate::test_utils::tests::*;
+
+    const NR_THREAD: usize = 2;
+    #[cfg(not(feature = "pmcheck"))]
+    const NR_COUNT: usize = 1_000;
+    #[cfg(feature = "pmcheck")]
+    const NR_COUNT: usize = 5;
+
+    #[derive(Debug, Collectable)]
+    struct Job {
+        value: TestValue,
+    }
+
+    #[derive(Debug, Default, Memento, Collectable)]
+    struct JobMmt {
+        value: Checkpoint<TestValue>,
+    }
+
+    impl Task for Job {
+        type Memento = JobMmt;
+        type Output = TestValue;
+
+        fn run(&self, mmt: &mut JobMmt, handle: &Handle) -> TestValue {
+            mmt.value.checkpoint(|| self.value, handle)
+        }
+    }
+
+    struct Jobs {
+        submits: [Submit<Job>; NR_COUNT],
+    }
+
+    impl Memento for Jobs {
+        fn clear(&mut self) {
+            for submit in &mut self.submits {
+                submit.clear();
+            }
+        }
+    }
+
+    impl Default for Jobs {
+        fn default() -> Self {
+            Self {
+                submits: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Jobs {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for submit in &mut m.submits {
+                Submit::filter(submit, tid, gc, pool);
+            }
+        }
+    }
+
+    // Each worker submits its jobs and runs any jobs, and then reports the outputs of its jobs.
+    impl RootObj<Jobs> for TestRootObj<Scheduler<Job>> {
+        fn run(&self, jobs: &mut Jobs, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+
+            // All jobs are submitted before the worker runs.
+            #[allow(clippy::needless_collect)]
+            let nodes = (0..NR_COUNT)
+                .map(|seq| {
+                    let job = Job {
+                        value: TestValue::new(handle.tid, seq),
+                    };
+                    self.obj
+                        .submit(job, &mut jobs.submits[seq], handle)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            self.obj.run_worker(handle);
+
+            for (seq, node) in nodes.into_iter().enumerate() {
+                testee.report(seq, self.obj.wait(node, handle));
+            }
+        }
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn submit_run() {
+        const FILE_NAME: &str = "scheduler";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<Scheduler<Job>>, Jobs>(FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT);
+    }
+
+    // A task claimed by a worker that is not live is taken over by its waiter, and retired after it finishes.
+    #[test]
+    fn take_over() {
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        let pool = get_dummy_handle(FILE_SIZE).unwrap();
+        let (h1, h2) = (
+            Handle::new(1, pool.pin(), pool),
+            Handle::new(2, pool.pin(), pool),
+        );
+        let scheduler = pool.alloc::<Scheduler<Job>>();
+        let scheduler = unsafe {
+            std::ptr::write(scheduler.deref_mut(pool), Scheduler::pdefault(&h1));
+            scheduler.deref(pool)
+        };
+
+        let job = Job {
+            value: TestValue::new(1, 0),
+        };
+        let node = scheduler.submit(job, &mut Submit::default(), &h1).unwrap();
+
+        // The worker of tid 2 claims the task and stops before running it.
+        assert_eq!(scheduler.claim(&h2).unwrap().as_ptr(), node);
+        assert!(scheduler.claim(&h2).is_none());
+
+        assert_eq!(scheduler.wait(node, &h1), TestValue::new(1, 0));
+        assert_eq!(scheduler.head.load(Ordering::SeqCst, &h1).as_ptr(), node);
+    }
+}
@@ -1,7 +1,7 @@
 //! Persistent Stack
 
 use crate::ploc::Handle;
-use crate::pmem::alloc::Collectable;
+use crate::pmem::alloc::{AllocError, Collectable};
 use crate::*;
 
 /// Failure of stack operations
@@ -20,7 +20,9 @@ where
     type Pop: Memento;
 
     /// Push
-    fn push(&self, value: T, mmt: &mut Self::Push, handle: &Handle);
+    ///
+    /// Fail if the pool is out of memory.
+    fn push(&self, value: T, mmt: &mut Self::Push, handle: &Handle) -> Result<(), AllocError>;
 
     /// Pop
     fn pop(&self, mmt: &mut Self::Pop, handle: &Handle) -> Option<T>;
@@ -103,9 +105,10 @@ pub(crate) mod tests {
 
             // push; pop;
             for seq in 0..COUNT {
-                let _ = self
+                assert!(self
                     .obj
-                    .push(TestValue::new(tid, seq), &mut push_pop.pushes[seq], handle);
+                    .push(TestValue::new(tid, seq), &mut push_pop.pushes[seq], handle)
+                    .is_ok());
                 let res = self.obj.pop(&mut push_pop.pops[seq], handle);
 
                 assert!(res.is_some(), "{tid} {seq}");
@@ -5,9 +5,10 @@ use core::sync::atomic::Ordering;
 use etrace::some_or;
 
 use super::stack::*;
-use crate::pepoch::{PAtomic, PDestroyable, POwned, PShared};
+use crate::pepoch::{self as epoch, PAtomic, PDestroyable, POwned, PShared};
 use crate::ploc::{Cas, Checkpoint, DetectableCASAtomic, Handle};
-use crate::pmem::alloc::Collectable;
+use crate::pmem::alloc::{AllocError, Collectable};
+use crate::pmem::inspect::Inspect;
 use crate::pmem::ll::*;
 use crate::pmem::GarbageCollection;
 use crate::pmem::PoolHandle;
@@ -160,21 +161,30 @@ impl<T: Clone + Collectable> TreiberStack<T> {
     }
 
     /// Push
-    pub fn push(&self, value: T, push: &mut Push<T>, handle: &Handle) {
+    ///
+    /// Fail if the pool is out of memory. The failure is checkpointed, so it is also returned after a crash.
+    pub fn push(&self, value: T, push: &mut Push<T>, handle: &Handle) -> Result<(), AllocError> {
         let (guard, pool) = (&handle.guard, handle.pool);
         let node = push
             .node
             .checkpoint(
-                || {
-                    let node = POwned::new(Node::from(value), pool);
-                    persist_obj(unsafe { node.deref(pool) }, true);
-                    PAtomic::from(node)
+                || match POwned::try_new(Node::from(value), pool) {
+                    Ok(node) => {
+                        persist_obj(unsafe { node.deref(pool) }, true);
+                        PAtomic::from(node)
+                    }
+                    Err(AllocError) => PAtomic::null(),
                 },
                 handle,
             )
             .load(Ordering::Relaxed, guard);
 
+        if node.is_null() {
+            return Err(AllocError);
+        }
+
         while self.try_push(node, &mut push.try_push, handle).is_err() {}
+        Ok(())
     }
 
     /// Try pop
@@ -197,7 +207,7 @@ impl<T: Clone + Collectable> TreiberStack<T> {
         self.top
             .cas(top, next, &mut try_pop.delete, handle)
             .map(|_| unsafe {
-                guard.defer_pdestroy(top);
+                guard.defer_pdestroy(top, pool);
                 Some(top_ref.data.clone())
             })
             .map_err(|_| TryFail)
@@ -213,6 +223,22 @@ impl<T: Clone + Collectable> TreiberStack<T> {
     }
 }
 
+impl<T: Clone + Collectable> Inspect for TreiberStack<T> {
+    fn summary(&self, pool: &PoolHandle) -> String {
+        let guard = unsafe { epoch::unprotected() };
+        let mut len = 0;
+        let mut curr = some_or!(
+            self.top.load_raw(guard),
+            return "stack of unknown elements (top is being changed)".to_owned()
+        );
+        while let Some(curr_ref) = unsafe { curr.as_ref(pool) } {
+            len += 1;
+            curr = curr_ref.next.load(Ordering::SeqCst, guard);
+        }
+        format!("stack of {len} elements")
+    }
+}
+
 unsafe impl<T: Clone + Collectable + Send + Sync> Send for TreiberStack<T> {}
 
 impl<T: Clone + Collectable> Stack<T> for TreiberStack<T> {
@@ -220,7 +246,7 @@ impl<T: Clone + Collectable> Stack<T> for TreiberStack<T> {
     type Pop = Pop<T>;
 
     #[inline]
-    fn push(&self, value: T, push: &mut Self::Push, handle: &Handle) {
+    fn push(&self, value: T, push: &mut Self::Push, handle: &Handle) -> Result<(), AllocError> {
         self.push(value, push, handle)
     }
 
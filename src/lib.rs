@@ -32,6 +32,7 @@
 #![feature(update_panic_count)] // to simulate thread crash
 #![feature(rt)] // to simulate thread crash
 #![feature(backtrace)] // to debug test
+#![feature(associated_type_defaults)] // to default `RootObj::Output` to `()`
 #![allow(clippy::type_complexity)]
 #![allow(clippy::too_many_arguments)]
 #![recursion_limit = "512"]
@@ -21,7 +21,7 @@ use core::sync::atomic::Ordering;
 use super::Guard;
 use crate::impl_left_bits;
 use crate::ploc::Handle;
-use crate::pmem::{global_pool, pool::PoolHandle, ptr::PPtr, Collectable, GarbageCollection};
+use crate::pmem::{pool::PoolHandle, ptr::PPtr, AllocError, Collectable, GarbageCollection};
 use crate::PDefault;
 use crossbeam_epoch::unprotected;
 use crossbeam_utils::atomic::AtomicConsume;
@@ -129,14 +129,14 @@ pub(crate) const POS_DESC_BITS: u32 = POS_AUX_BITS + NR_AUX_BITS;
 pub(crate) const NR_DESC_BITS: u32 = 1;
 impl_left_bits!(desc_bits, POS_DESC_BITS, NR_DESC_BITS, usize);
 
-// tid bits: 0b0011111111100000000000000000000000000000000000000000000000000000 in 64-bit
+// tid bits: 0b0011111111111100000000000000000000000000000000000000000000000000 in 64-bit
 const POS_TID_BITS: u32 = POS_DESC_BITS + NR_DESC_BITS;
-const NR_TID_BITS: u32 = 9;
+pub(crate) const NR_TID_BITS: u32 = 12;
 impl_left_bits!(tid_bits, POS_TID_BITS, NR_TID_BITS, usize);
 
-// high bits: 0b0000000000011111111111111000000000000000000000000000000000000000 in 64-bit
+// high bits: 0b0000000000000011111111111000000000000000000000000000000000000000 in 64-bit
 const POS_HIGH_BITS: u32 = POS_TID_BITS + NR_TID_BITS;
-const NR_HIGH_BITS: u32 = 14;
+const NR_HIGH_BITS: u32 = 11;
 impl_left_bits!(high_bits, POS_HIGH_BITS, NR_HIGH_BITS, usize);
 
 /// Cut as the length of high tag
@@ -225,6 +225,7 @@ fn decompose_tag<T: ?Sized + Pointable>(data: usize) -> (usize, usize, usize, us
 ///
 /// // Assume there are PoolHandle, `pool`
 /// let o = POwned::<[MaybeUninit<i32>]>::init(10, &pool); // allocating [i32; 10]
+/// unsafe { o.destroy(&pool) };
 /// ```
 pub trait Pointable {
     /// The alignment of pointer.
@@ -238,7 +239,20 @@ pub trait Pointable {
     /// # Safety
     ///
     /// The result should be a multiple of `ALIGN`.
-    unsafe fn init(init: Self::Init, pool: &PoolHandle) -> usize;
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is out of memory.
+    unsafe fn init(init: Self::Init, pool: &PoolHandle) -> usize {
+        Self::try_init(init, pool).expect("Out of memory")
+    }
+
+    /// Initializes a with the given initializer in the pool, or returns `AllocError` if the pool is out of memory.
+    ///
+    /// # Safety
+    ///
+    /// The result should be a multiple of `ALIGN`.
+    unsafe fn try_init(init: Self::Init, pool: &PoolHandle) -> Result<usize, AllocError>;
 
     /// Dereferences the given offset in the pool.
     ///
@@ -279,12 +293,12 @@ impl<T> Pointable for T {
 
     type Init = T;
 
-    unsafe fn init(init: Self::Init, pool: &PoolHandle) -> usize {
-        let ptr = pool.alloc::<T>();
+    unsafe fn try_init(init: Self::Init, pool: &PoolHandle) -> Result<usize, AllocError> {
+        let ptr = pool.try_alloc::<T>()?;
 
         let t = ptr.deref_mut(pool);
         std::ptr::write(t as *mut T, init);
-        ptr.into_offset()
+        Ok(ptr.into_offset())
     }
 
     unsafe fn deref(offset: usize, pool: &PoolHandle) -> &Self {
@@ -338,17 +352,14 @@ impl<T> Pointable for [MaybeUninit<T>] {
 
     type Init = usize;
 
-    unsafe fn init(len: Self::Init, pool: &PoolHandle) -> usize {
+    unsafe fn try_init(len: Self::Init, pool: &PoolHandle) -> Result<usize, AllocError> {
         let size = mem::size_of::<PArray<T>>() + mem::size_of::<MaybeUninit<T>>() * len;
         let align = mem::align_of::<PArray<T>>();
         let layout = alloc::Layout::from_size_align(size, align).unwrap();
-        let ptr = pool.alloc_layout::<PArray<T>>(layout);
-        if ptr.is_null() {
-            alloc::handle_alloc_error(layout);
-        }
+        let ptr = pool.try_alloc_layout::<PArray<T>>(layout)?;
         let p = ptr.deref_mut(pool);
         p.len = len;
-        ptr.into_offset()
+        Ok(ptr.into_offset())
     }
 
     unsafe fn deref(offset: usize, pool: &PoolHandle) -> &Self {
@@ -405,6 +416,12 @@ impl<T> PAtomic<T> {
     pub fn new(init: T, pool: &PoolHandle) -> PAtomic<T> {
         Self::init(init, pool)
     }
+
+    /// Allocates `value` on the persistent heap and returns a new atomic pointer pointing to it,
+    /// or returns `AllocError` if the pool is out of memory.
+    pub fn try_new(init: T, pool: &PoolHandle) -> Result<PAtomic<T>, AllocError> {
+        Self::try_init(init, pool)
+    }
 }
 
 impl<T: ?Sized + Pointable> PAtomic<T> {
@@ -426,6 +443,12 @@ impl<T: ?Sized + Pointable> PAtomic<T> {
         Self::from(POwned::init(init, pool))
     }
 
+    /// Allocates `value` on the persistent heap and returns a new atomic pointer pointing to it,
+    /// or returns `AllocError` if the pool is out of memory.
+    pub fn try_init(init: T::Init, pool: &PoolHandle) -> Result<PAtomic<T>, AllocError> {
+        POwned::try_init(init, pool).map(Self::from)
+    }
+
     /// Returns a new atomic pointer pointing to the tagged pointer `data`.
     fn from_usize(data: usize) -> Self {
         Self {
@@ -985,9 +1008,8 @@ impl<T: ?Sized + Pointable> PAtomic<T> {
 
     /// Takes ownership of the pointee.
     ///
-    /// This consumes the atomic and converts it into [`POwned`]. As [`PAtomic`] doesn't have a
-    /// destructor and doesn't drop the pointee while [`POwned`] does, this is suitable for
-    /// destructors of data structures.
+    /// This consumes the atomic and converts it into [`POwned`], which can drop the pointee in its
+    /// pool with [`POwned::destroy`]. This is suitable for destructors of data structures.
     ///
     /// # Panics
     ///
@@ -1003,16 +1025,19 @@ impl<T: ?Sized + Pointable> PAtomic<T> {
     /// ```rust
     /// # use std::mem;
     /// # use memento::pepoch::PAtomic;
+    /// # use memento::pmem::pool::PoolHandle;
     /// struct DataStructure {
     ///     ptr: PAtomic<usize>,
     /// }
     ///
-    /// impl Drop for DataStructure {
-    ///     fn drop(&mut self) {
+    /// impl DataStructure {
+    ///     fn destroy(&mut self, pool: &PoolHandle) {
     ///         // By now the DataStructure lives only in our thread and we are sure we don't hold
     ///         // any Shared or & to it ourselves.
     ///         unsafe {
-    ///             drop(mem::replace(&mut self.ptr, PAtomic::null()).into_owned());
+    ///             mem::replace(&mut self.ptr, PAtomic::null())
+    ///                 .into_owned()
+    ///                 .destroy(pool);
     ///         }
     ///     }
     /// }
@@ -1088,9 +1113,7 @@ impl<T: ?Sized + Pointable> From<POwned<T>> for PAtomic<T> {
     /// let a = PAtomic::<i32>::from(POwned::new(1234, &pool));
     /// ```
     fn from(owned: POwned<T>) -> Self {
-        let data = owned.data;
-        mem::forget(owned);
-        Self::from_usize(data)
+        Self::from_usize(owned.into_usize())
     }
 }
 
@@ -1131,9 +1154,18 @@ impl<T: Collectable> Collectable for PAtomic<T> {
         let guard = unsafe { unprotected() };
 
         let mut ptr = s.load(Ordering::Relaxed, guard);
+        if let Some(fsck) = &mut pool.fsck {
+            if !fsck.check_tag(ptr.into_usize(), ptr.desc_bit(), ptr.tid()) {
+                return;
+            }
+            if !ptr.is_null() && !fsck.check_ptr(&pool.allocator, ptr.as_ptr().into_offset()) {
+                return;
+            }
+        }
         if !ptr.is_null() {
-            let t_ref = unsafe { ptr.deref_mut(pool) };
-            T::mark(t_ref, tid, gc);
+            // Detach the reference from `pool` so that `pool` can be handed over to `mark`.
+            let t_ref = unsafe { &mut *(ptr.deref_mut(pool) as *mut T) };
+            T::mark(t_ref, tid, gc, pool);
         }
     }
 }
@@ -1159,10 +1191,15 @@ pub trait Pointer<T: ?Sized + Pointable> {
 
 /// An owned heap-allocated object.
 ///
-/// This type is very similar to `Box<T>`.
+/// This type is very similar to `Box<T>`, except that dropping it does not drop the object, since
+/// it does not know the pool of the object. Every `POwned` should either be handed over to the pool
+/// (e.g. stored into a [`PAtomic`] with `PAtomic::from`) or destroyed in its pool with
+/// [`POwned::destroy`], including on early-return and error paths. An object dropped otherwise
+/// leaks until the recovery GC of the pool finds it unreachable.
 ///
 /// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
 /// least significant bits of the address.
+#[must_use = "a dropped `POwned` leaks until recovery; store it or `destroy` it"]
 pub struct POwned<T: ?Sized + Pointable> {
     data: usize,
     _marker: PhantomData<T>,
@@ -1171,9 +1208,7 @@ pub struct POwned<T: ?Sized + Pointable> {
 impl<T: ?Sized + Pointable> Pointer<T> for POwned<T> {
     #[inline]
     fn into_usize(self) -> usize {
-        let data = self.data;
-        mem::forget(self);
-        data
+        self.data
     }
 
     /// Returns a new pointer pointing to the tagged pointer `data`.
@@ -1220,6 +1255,7 @@ impl<T> POwned<T> {
     /// // Assume there is PoolHandle, `pool`
     /// let mut ptr = pool.alloc::<usize>();
     /// let o = unsafe { POwned::from_ptr(ptr) };
+    /// unsafe { o.destroy(&pool) };
     /// ```
     pub unsafe fn from_ptr(ptr: PPtr<T>) -> POwned<T> {
         let offset = ptr.into_offset();
@@ -1240,10 +1276,17 @@ impl<T> POwned<T> {
     ///
     /// // Assume there is PoolHandle, `pool`
     /// let o = POwned::new(1234, &pool);
+    /// unsafe { o.destroy(&pool) };
     /// ```
     pub fn new(init: T, pool: &PoolHandle) -> POwned<T> {
         Self::init(init, pool)
     }
+
+    /// Allocates `value` on the persistent heap and returns a new owned pointer pointing to it,
+    /// or returns `AllocError` if the pool is out of memory.
+    pub fn try_new(init: T, pool: &PoolHandle) -> Result<POwned<T>, AllocError> {
+        Self::try_init(init, pool)
+    }
 }
 
 impl<T: ?Sized + Pointable> POwned<T> {
@@ -1260,11 +1303,18 @@ impl<T: ?Sized + Pointable> POwned<T> {
     ///
     /// // Assume there is PoolHandle, `pool`
     /// let o = POwned::<i32>::init(1234, &pool);
+    /// unsafe { o.destroy(&pool) };
     /// ```
     pub fn init(init: T::Init, pool: &PoolHandle) -> POwned<T> {
         unsafe { Self::from_usize(T::init(init, pool)) }
     }
 
+    /// Allocates `value` on the persistent heap and returns a new owned pointer pointing to it,
+    /// or returns `AllocError` if the pool is out of memory.
+    pub fn try_init(init: T::Init, pool: &PoolHandle) -> Result<POwned<T>, AllocError> {
+        unsafe { T::try_init(init, pool).map(|offset| Self::from_usize(offset)) }
+    }
+
     /// Converts the owned pointer into a [`PShared`].
     ///
     /// # Examples
@@ -1298,7 +1348,9 @@ impl<T: ?Sized + Pointable> POwned<T> {
     /// use memento::pepoch::POwned;
     ///
     /// // Assume there is PoolHandle, `pool`
-    /// assert_eq!(POwned::new(1234, &pool).tag(), 0);
+    /// let o = POwned::new(1234, &pool);
+    /// assert_eq!(o.tag(), 0);
+    /// unsafe { o.destroy(&pool) };
     /// ```
     pub fn tag(&self) -> usize {
         let (_, _, _, _, _, tag) = decompose_tag::<T>(self.data);
@@ -1346,6 +1398,7 @@ impl<T: ?Sized + Pointable> POwned<T> {
     /// assert_eq!(o.tag(), 0);
     /// let o = o.with_tag(2);
     /// assert_eq!(o.tag(), 2);
+    /// unsafe { o.destroy(&pool) };
     /// ```
     pub fn with_tag(self, tag: usize) -> POwned<T> {
         let data = self.into_usize();
@@ -1376,6 +1429,16 @@ impl<T: ?Sized + Pointable> POwned<T> {
         unsafe { Self::from_usize(compose_high_tag(tag, data)) }
     }
 
+    /// Drops the object in `pool`.
+    ///
+    /// # Safety
+    ///
+    /// pool should be correct
+    pub unsafe fn destroy(self, pool: &PoolHandle) {
+        let (_, _, _, _, offset, _) = decompose_tag::<T>(self.into_usize());
+        T::drop(offset, pool);
+    }
+
     /// deref absolute addr based on pool
     ///
     /// # Safety
@@ -1437,15 +1500,6 @@ impl<T: ?Sized + Pointable> POwned<T> {
     }
 }
 
-impl<T: ?Sized + Pointable> Drop for POwned<T> {
-    fn drop(&mut self) {
-        let (_, _, _, _, offset, _) = decompose_tag::<T>(self.data);
-        unsafe {
-            T::drop(offset, global_pool().unwrap());
-        }
-    }
-}
-
 impl<T: ?Sized + Pointable> fmt::Debug for POwned<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (aux_bit, desc_bit, tid, high_tag, offset, tag) = decompose_tag::<T>(self.data);
@@ -1470,8 +1524,8 @@ impl<T: Clone> POwned<T> {
 
 impl<T: Collectable> Collectable for POwned<T> {
     fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
-        let item = unsafe { (*s).deref_mut(pool) };
-        T::mark(item, tid, gc);
+        let item = unsafe { &mut *((*s).deref_mut(pool) as *mut T) };
+        T::mark(item, tid, gc, pool);
     }
 }
 
@@ -1770,7 +1824,7 @@ impl<'g, T: ?Sized + Pointable> PShared<'g, T> {
     /// unsafe {
     ///     let guard = &epoch::unprotected();
     ///     let p = a.load(SeqCst, guard);
-    ///     drop(p.into_owned());
+    ///     p.into_owned().destroy(&pool);
     /// }
     /// ```
     pub unsafe fn into_owned(self) -> POwned<T> {
@@ -5,11 +5,15 @@ pub mod atomic;
 pub use self::atomic::{PAtomic, POwned, PShared};
 pub use crossbeam_epoch::{pin, unprotected, Guard};
 
+use crate::pmem::pool::PoolHandle;
+
 /// A trait to allow the crossbeam's Guard to handle PAtomic pointers as well
 pub trait PDestroyable {
     /// Stores a destructor for an persistent object so that it can be deallocated and dropped at some point
     /// after all currently pinned threads get unpinned.
     ///
+    /// The object is deallocated in `pool`, which it should belong to.
+    ///
     /// This method first stores the destructor into the thread-local (or handle-local) cache. If
     /// this cache becomes full, some destructors are moved into the global cache. At the same
     /// time, some destructors from both local and global caches may get executed in order to
@@ -38,7 +42,7 @@ pub trait PDestroyable {
     /// ```ignore
     /// // Assume there is PoolHandle, `pool`
     /// let shared = POwned::new(7i32, &pool).into_shared(guard);
-    /// guard.defer_pdestroy(shared); // `Shared` is not `Send`!
+    /// guard.defer_pdestroy(shared, pool); // `Shared` is not `Send`!
     /// ```
     ///
     /// While `Shared` is not `Send`, it's safe for another thread to call the destructor, because
@@ -77,11 +81,11 @@ pub trait PDestroyable {
     ///     // The persistent object `p` is pointing to is now unreachable.
     ///     // Defer its deallocation until all currently pinned threads get unpinned.
     ///     unsafe {
-    ///         guard.defer_pdestroy(p);
+    ///         guard.defer_pdestroy(p, pool);
     ///     }
     /// }
     /// ```
-    unsafe fn defer_pdestroy<T>(&self, ptr: PShared<'_, T>);
+    unsafe fn defer_pdestroy<T>(&self, ptr: PShared<'_, T>, pool: &'static PoolHandle);
 }
 
 impl PDestroyable for Guard {
@@ -89,7 +93,11 @@ impl PDestroyable for Guard {
     ///
     /// This function is stable if the `ptr` is stable.
     /// Because even if the drop of the same `ptr` is deferred twice, it will be de-duplicated by crossbeam-persistency.
-    unsafe fn defer_pdestroy<T>(&self, ptr: PShared<'_, T>) {
-        self.defer_unchecked(move || ptr.into_owned(), Some(ptr.as_ptr().into_offset()));
+    unsafe fn defer_pdestroy<T>(&self, ptr: PShared<'_, T>, pool: &'static PoolHandle) {
+        let ptr = ptr.as_ptr();
+        self.defer_unchecked(
+            move || pool.free(ptr),
+            Some(pool.start() + ptr.into_offset()),
+        );
     }
 }
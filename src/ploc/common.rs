@@ -10,7 +10,13 @@ use crossbeam_epoch::Guard;
 use super::{CasHelpArr, CasHelpDescArr, CasInfo};
 use crate::pmem::{lfence, rdtscp, PoolHandle};
 
-pub(crate) const NR_MAX_THREADS: usize = 511;
+/// Upper bound of the maximum number of threads of a pool (`Pool::create_with_max_threads`)
+///
+/// Tids are stored in the tid bits of `PAtomic`, which bound it.
+pub const NR_MAX_THREADS: usize = (1 << crate::pepoch::atomic::NR_TID_BITS) - 1;
+
+/// Maximum number of threads of a pool created by `Pool::create`
+pub const DEFAULT_MAX_THREADS: usize = 511;
 #[allow(warnings)]
 pub(crate) mod ordo {
     use std::{
@@ -233,7 +239,16 @@ pub struct Handle {
 
 impl Handle {
     /// Create new handle
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tid` is larger than the maximum number of threads of `pool`.
     pub fn new(tid: usize, guard: Guard, pool: &'static PoolHandle) -> Self {
+        assert!(
+            tid <= pool.max_threads(),
+            "tid {tid} exceeds the maximum number of threads of the pool ({})",
+            pool.max_threads()
+        );
         Self {
             tid,
             local_max_time: LocalMaxTime::default(),
@@ -244,10 +259,12 @@ impl Handle {
     }
 
     /// Repin the guard so that deferred destory and persist can be executed
+    ///
+    /// It is also where `PoolHandle::snapshot` stops the thread.
     pub fn repin_guard(&self) {
         self.pool.clear_mmt(self.tid);
         let guard = unsafe { &mut std::ptr::read(&self.guard) };
-        guard.repin_after(|| {});
+        guard.repin_after(|| self.pool.quiesce(self.tid));
     }
 }
 
@@ -1,5 +1,6 @@
 //! General SMO
 
+use std::ptr;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use atomic::fence;
@@ -10,18 +11,17 @@ use std::ops::Deref;
 
 use crate::{
     impl_left_bits,
-    pepoch::{
-        atomic::{CompareExchangeError, Pointer},
-        PAtomic, PShared,
-    },
+    pepoch::{atomic::Pointer, unprotected, Guard, PAtomic, PShared},
     pmem::{ll::persist_obj, sfence, Collectable, GarbageCollection, PoolHandle},
     Memento, PDefault,
 };
 
-use super::{Handle, Timestamp, NR_MAX_THREADS};
+use super::detectable_cas_u64::Word;
+use super::mwcas::{help_mwcas, is_mwcas, mwcas_descriptor_ptr};
+use super::{Handle, Timestamp};
 
 #[derive(Debug, Clone, Copy)]
-struct CasTimestamp(u64);
+pub(crate) struct CasTimestamp(u64);
 
 impl From<CasTimestamp> for u64 {
     #[inline]
@@ -33,7 +33,7 @@ impl From<CasTimestamp> for u64 {
 impl CasTimestamp {
     /// 62-bit timestamp with parity and failure bit
     #[inline]
-    fn new(parity: bool, fail: bool, ts: Timestamp) -> Self {
+    pub(crate) fn new(parity: bool, fail: bool, ts: Timestamp) -> Self {
         Self::encode(parity, fail, ts)
     }
 
@@ -62,7 +62,7 @@ impl CasTimestamp {
 
     #[inline]
     /// Decompose Timestamp into parity and failure flag and timestamp
-    fn decode(&self) -> (bool, bool, Timestamp) {
+    pub(crate) fn decode(&self) -> (bool, bool, Timestamp) {
         (
             (self.0 & Self::parity_bits())
                 .rotate_left(Self::POS_PARITY_BITS + Self::NR_PARITY_BITS)
@@ -79,18 +79,27 @@ pub(crate) struct CasOwn(CachePadded<AtomicU64>);
 
 impl CasOwn {
     #[inline]
-    fn load(&self) -> CasTimestamp {
+    pub(crate) fn load(&self) -> CasTimestamp {
         CasTimestamp(self.0.load(Ordering::Relaxed))
     }
 
     #[inline]
-    fn store(&self, t: CasTimestamp) {
+    pub(crate) fn store(&self, t: CasTimestamp) {
         self.0.store(t.into(), Ordering::Relaxed);
     }
 
     pub(crate) fn clear(&self) {
         self.store(CasTimestamp(0));
     }
+
+    /// Raw value, which is kept by a clean shutdown instead of being recovered from mementos
+    pub(crate) fn to_raw(&self) -> u64 {
+        self.load().into()
+    }
+
+    pub(crate) fn restore(&self, raw: u64) {
+        self.store(CasTimestamp(raw));
+    }
 }
 
 #[derive(Debug)]
@@ -111,7 +120,7 @@ impl Default for CasHelp {
 
 impl CasHelp {
     #[inline]
-    fn load(&self, parity: bool) -> Timestamp {
+    pub(crate) fn load(&self, parity: bool) -> Timestamp {
         Timestamp::from(self.inner[parity as usize].load(Ordering::SeqCst))
     }
 
@@ -152,13 +161,360 @@ struct CasHelpDescriptorInner {
     seq: AtomicUsize, // sequence of help descriptor
 }
 
+impl CasHelpDescriptor {
+    /// Sequence number of the help descriptor
+    pub(crate) fn seq(&self) -> usize {
+        self.0.seq.load(Ordering::SeqCst)
+    }
+
+    /// Tagged new value of the CAS to be helped
+    pub(crate) fn tmp_new(&self) -> usize {
+        self.0.tmp_new.load(Ordering::SeqCst)
+    }
+
+    /// Set the descriptor to help the CAS of `tmp_new`, and return the new sequence number
+    pub(crate) fn register(&self, tmp_new: usize) -> usize {
+        let new_seq = self.0.seq.load(Ordering::SeqCst) + 1;
+        self.0.seq.store(new_seq, Ordering::SeqCst);
+        // TODO: Add fence here after relaxing.
+        // persist ordering is guaranteed because it lies in same cache line.
+        self.0.tmp_new.store(tmp_new, Ordering::SeqCst);
+        persist_obj(self, false);
+        new_seq
+    }
+}
+
+/// Word of a location of detectable CAS, whose tags are kept in its head
+///
+/// The head is tagged in the same way for every width of word (see `Word`), so that the helping protocol of
+/// `DetectableLoc` and the help descriptors are shared by all of them.
+pub(crate) trait DetectableWord: Copy + Eq {
+    /// Tagged head of the word (e.g. the upper half of a double-width word)
+    fn head(self) -> Word;
+
+    /// The word whose head is replaced with `head`
+    fn with_head(self, head: Word) -> Self;
+
+    /// Whether the word is returned by a load without helping
+    #[inline]
+    fn is_clean(self) -> bool {
+        self.head().is_clean()
+    }
+
+    /// The word without tags
+    #[inline]
+    fn untagged(self) -> Self {
+        self.with_head(Word(self.head().value()))
+    }
+}
+
+impl<N> DetectableWord for PShared<'_, N> {
+    #[inline]
+    fn head(self) -> Word {
+        Word(self.into_usize() as u64)
+    }
+
+    #[inline]
+    fn with_head(self, head: Word) -> Self {
+        unsafe { PShared::from_usize(head.0 as usize) }
+    }
+
+    // A pointer of a non-detectable CAS in progress is returned as it is.
+    #[inline]
+    fn is_clean(self) -> bool {
+        self.tid() == 0
+    }
+}
+
+/// Location of detectable CAS
+///
+/// The helping protocol (registering and finalizing help descriptors) and the recovery of a CAS are implemented
+/// here once for every width of word.
+pub(crate) trait DetectableLoc<'g> {
+    /// Word of the location
+    type Word: DetectableWord;
+
+    /// Load the word without helping
+    fn load_word(&self, handle: &'g Handle) -> Self::Word;
+
+    /// CAS the word, returning the current word on failure
+    fn cas_word(
+        &self,
+        old: Self::Word,
+        new: Self::Word,
+        handle: &'g Handle,
+    ) -> Result<(), Self::Word>;
+
+    /// Persist the location
+    fn persist(&self, fence: bool);
+
+    /// Help an operation other than detectable CAS found in the location (e.g. a multi-word CAS), and return the
+    /// current word, or `None` if there is no such operation
+    #[inline]
+    fn help_other(&self, _word: Self::Word, _handle: &'g Handle) -> Option<Self::Word> {
+        None
+    }
+
+    // Location State (of the head)
+    // - [parity: 0 | desc: 0 | tid: 0     | data]: `data` is a value and it guarantees to be persisted.
+    // - [parity: p | desc: 0 | tid: non-0 | data]: `data` is a value that may not be persisted. The `tid` wants a help in the context of parity `p`.
+    // - [parity: 1 | desc: 0 | tid: 0     | data]: `data` is a value that may not be persisted. Someone wants a help but it doesn't need to be announced. (non-detectable way)
+    // - [parity: 0 | desc: 1 | tid: non-0 | data]: `data` is a sequence number of `tid`'s descriptor.
+    #[inline]
+    fn load_help(&self, mut old: Self::Word, handle: &'g Handle) -> Self::Word {
+        let exec_info = &handle.pool.exec_info;
+        loop {
+            // return if old is clean
+            if old.is_clean() {
+                return old;
+            }
+
+            if let Some(cur) = self.help_other(old, handle) {
+                old = cur;
+                continue;
+            }
+
+            let t_cur = 'chk: loop {
+                // get checkpoint timestamp
+                let t_cur = {
+                    let wait1 = exec_info.exec_time();
+                    loop {
+                        let now = exec_info.exec_time();
+                        if wait1 + exec_info.tsc_offset < now {
+                            break now;
+                        }
+                    }
+                };
+
+                // start spin loop
+                loop {
+                    let cur = self.load_word(handle);
+
+                    // return if cur is clean. (previous chk timestamp is useless.)
+                    if cur.is_clean() {
+                        return cur;
+                    }
+
+                    // if old was changed, new spin loop needs to be started.
+                    if old != cur {
+                        old = cur;
+                        break;
+                    }
+
+                    // if patience is over, I have to help it.
+                    let wait2 = exec_info.exec_time();
+
+                    cfg_if! {
+                        if #[cfg(not(feature = "tcrash"))] {
+                            let patience = Timestamp::from(40_000);
+                        } else {
+                            let patience = handle.pool.exec_info.tsc_offset;
+                        }
+                    };
+
+                    if wait2 > t_cur + patience {
+                        break 'chk t_cur;
+                    }
+                }
+            };
+            // Persist the word before registering help descriptor
+            self.persist(false);
+
+            // If non-detectable CAS is proceeded, just CAS to clean word w/o help announcement.
+            if old.head().is_non_detectable_cas() {
+                match self.cas_word(old, old.untagged(), handle) {
+                    Ok(()) => return old.untagged(),
+                    Err(e) => old = e,
+                };
+
+                continue;
+            }
+
+            // Register my help descriptor if there is no descriptor yet.
+            if old.head().desc_bit() == 0 {
+                #[cfg(feature = "pmcheck")]
+                sfence(); // To pass the false positive of PSan.
+
+                match self.register_help(old, handle) {
+                    Ok(desc) => {
+                        old = desc;
+                    }
+                    Err(e) => {
+                        old = e;
+                        if old.head().desc_bit() == 0 {
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            // Finalize the help descriptor.
+            match self.finalize_help(old, t_cur, handle) {
+                Ok(clean) => {
+                    return clean;
+                }
+                Err(e) => {
+                    old = e;
+                }
+            }
+        }
+    }
+
+    /// Register my help descriptor for the word `old`, and return the word holding it
+    #[inline]
+    fn register_help(&self, old: Self::Word, handle: &'g Handle) -> Result<Self::Word, Self::Word> {
+        assert!(old.head().desc_bit() == 0);
+        let my_tid = handle.tid;
+
+        // 1. Set my descriptor, which keeps the tagged head
+        let my_new_seq =
+            handle.pool.exec_info.cas_info.help_desc[my_tid].register(old.head().0 as usize);
+
+        // 2. Register my descriptor
+        // representation: [ aux_bit: 0, desc_bit: 1, tid: help leader, payload: sequence of help leader ]
+        let desc = old.with_head(
+            Word(my_new_seq as u64)
+                .with_aux_bit(0)
+                .with_desc_bit(1)
+                .with_tid(my_tid),
+        );
+        self.cas_word(old, desc, handle).map(|_| desc)
+    }
+
+    /// Finalize the help descriptor at time `t_cur`.
+    #[inline]
+    fn finalize_help(
+        &self,
+        old: Self::Word,
+        t_cur: Timestamp,
+        handle: &'g Handle,
+    ) -> Result<Self::Word, Self::Word> {
+        assert!(old.head().desc_bit() == 1);
+        let cas_info = &handle.pool.exec_info.cas_info;
+
+        // `old.tid` is leader of help.
+        let leader = old.head().tid();
+        let winner_tmp_new = Word(cas_info.help_desc[leader].tmp_new() as u64);
+
+        // Check if the sequence value on the word and descriptor are the same.
+        if old.head().value() != cas_info.help_desc[leader].seq() as u64 {
+            // This help has already done.
+            return Err(self.load_word(handle));
+        }
+
+        let winner_tid = winner_tmp_new.tid();
+        let winner_parity = winner_tmp_new.aux_bit() != 0;
+        let winner_new = old.with_head(Word(winner_tmp_new.value()));
+
+        // CAS winner thread's pcheckpoint
+        if !cas_info.help_succ(winner_tid, winner_parity, t_cur) {
+            return Err(self.load_word(handle));
+        }
+
+        // help word to be clean.
+        let res = self.cas_word(old, winner_new, handle);
+        self.persist(true); // persist before return
+        res.map(|_| winner_new)
+    }
+
+    /// Second cas of a detectable CAS that has installed `tmp_new` and checkpointed its success at `t`
+    #[inline]
+    fn clean(&self, tmp_new: Self::Word, t: Timestamp, handle: &'g Handle) {
+        // By inserting a word with tid removed, it prevents further helping.
+        if let Err(cur) = self.cas_word(tmp_new, tmp_new.untagged(), handle) {
+            if cur.head().desc_bit() == 1 {
+                // Fianlize the help if there is help descriptor.
+                let _ = self.finalize_help(cur, t, handle);
+            } else {
+                // In case of CAS failure, sfence is required for synchronous flush.
+                sfence()
+            };
+        }
+    }
+
+    /// Result of the last detectable CAS of a memento with buffers `buf`, if it has been done (e.g. before a crash)
+    ///
+    /// Return the index of the buffer keeping the result, as `Err` if the CAS failed. `new` is the word set by the
+    /// CAS, or `None` if it is not known (e.g. by a fetch-and-update).
+    #[inline]
+    fn cas_result<C>(
+        &self,
+        new: Option<Self::Word>,
+        buf: &mut [CachePadded<CasInner<C>>; 2],
+        handle: &'g Handle,
+    ) -> Option<Result<usize, usize>> {
+        let (tid, exec_info) = (handle.tid, &handle.pool.exec_info);
+
+        let (stale, latest) = stale_latest_idx(buf);
+
+        let (_, f_mmt, t_mmt) = buf[latest].checkpoint.decode();
+        let t_local = handle.local_max_time.load();
+
+        let (p_own, _, t_own) = exec_info.cas_info.own[tid].load().decode();
+
+        if t_mmt > t_local {
+            if f_mmt {
+                // failed
+                handle.local_max_time.store(t_mmt);
+                return Some(Err(latest));
+            }
+
+            // already successful
+            if t_mmt >= t_own {
+                exec_info.cas_info.own[tid].store(buf[latest].checkpoint);
+
+                // The word tagged with my tid and parity is what I set.
+                let cur = match new {
+                    Some(new) => new.with_head(new.head().with_aux_bit(p_own as _).with_tid(tid)),
+                    None => self.load_word(handle),
+                };
+                let head = cur.head();
+                if head.desc_bit() == 0 && head.tid() == tid && head.aux_bit() == p_own as u64 {
+                    let _ = self.cas_word(cur, cur.untagged(), handle);
+                }
+            }
+
+            handle.local_max_time.store(t_mmt);
+            return Some(Ok(latest));
+        }
+
+        // Finalize the CAS
+        let _ = self.load_help(self.load_word(handle), handle);
+
+        let t_help = exec_info.cas_info.help[tid].load(!p_own);
+        if t_own >= t_help {
+            return None;
+        }
+
+        // Success because the checkpoint written by the helper is higher than the last CAS
+        // Since the value of location has already been changed, I just need to finalize my checkpoint.
+        let _ = buf[stale].checkpoint_succ(!p_own, handle);
+        sfence();
+
+        Some(Ok(stale))
+    }
+}
+
 /// Detectable CAS Atomic pointer
-#[derive(Debug, Collectable)]
+#[derive(Debug)]
 pub struct DetectableCASAtomic<N: Collectable> {
     /// Atomic pointer
     pub inner: PAtomic<N>,
 }
 
+impl<N: Collectable> Collectable for DetectableCASAtomic<N> {
+    fn filter(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        let cur = s.inner.load(Ordering::Relaxed, unsafe { unprotected() });
+        if is_mwcas(cur) {
+            // The values of the location are traced through the descriptor of the multi-word CAS.
+            let mut desc = PAtomic::from(unsafe { mwcas_descriptor_ptr(cur) });
+            PAtomic::filter(&mut desc, tid, gc, pool);
+        } else {
+            PAtomic::filter(&mut s.inner, tid, gc, pool);
+        }
+    }
+}
+
 impl<N: Collectable> Default for DetectableCASAtomic<N> {
     fn default() -> Self {
         Self {
@@ -198,7 +554,7 @@ impl<N: Collectable> DetectableCASAtomic<N> {
             handle.rec.store(false, Ordering::Relaxed);
         }
 
-        let (stale, _) = mmt.stale_latest_idx();
+        let (stale, _) = stale_latest_idx(&mmt.buf);
         let (p_own, _, _) = pool.exec_info.cas_info.own[tid].load().decode();
         let tmp_new = new.with_aux_bit((!p_own) as _).with_tid(tid);
 
@@ -219,7 +575,7 @@ impl<N: Collectable> DetectableCASAtomic<N> {
                     continue;
                 }
 
-                mmt.buf[stale].checkpoint_fail(cur, handle);
+                mmt.buf[stale].checkpoint_fail(PAtomic::from(cur), handle);
                 return Err(cur);
             }
 
@@ -229,81 +585,92 @@ impl<N: Collectable> DetectableCASAtomic<N> {
             // Checkpoint success
             let t = mmt.buf[stale].checkpoint_succ(!p_own, handle);
 
-            // 2. Second cas
-            // By inserting a pointer with tid removed, it prevents further helping.
-            if let Err(e) = self.inner.compare_exchange(
-                tmp_new,
-                new.with_aux_bit(0).with_tid(0),
-                Ordering::SeqCst,
-                Ordering::SeqCst,
-                guard,
-            ) {
-                if e.current.desc_bit() == 1 {
-                    // Fianlize the help if there is help descriptor.
-                    let _ = self.finalize_help(e.current, t, handle);
-                } else {
-                    // In case of CAS failure, sfence is required for synchronous flush.
-                    sfence()
-                };
-            }
+            self.clean(tmp_new, t, handle);
             return Ok(());
         }
     }
 
-    #[inline]
-    fn cas_result<'g>(
+    /// Swap the location with `new` unconditionally, and return the pointer it displaced
+    ///
+    /// The displaced pointer is recorded in the memento before each attempt, so the same pointer is returned after
+    /// a crash, and the caller can retire it (e.g. by `Guard::defer_pdestroy`) as if it had not crashed.
+    pub fn swap<'g>(
         &'g self,
         new: PShared<'_, N>,
-        mmt: &mut Cas<N>,
+        mmt: &mut Swap<N>,
         handle: &'g Handle,
-    ) -> Option<Result<(), PShared<'_, N>>> {
-        let (tid, guard, exec_info) = (handle.tid, &handle.guard, &handle.pool.exec_info);
-
-        let (stale, latest) = mmt.stale_latest_idx();
-
-        let (_, f_mmt, t_mmt) = mmt.buf[latest].checkpoint.decode();
-        let t_local = handle.local_max_time.load();
-
-        let (p_own, _, t_own) = exec_info.cas_info.own[tid].load().decode();
-
-        if t_mmt > t_local {
-            if f_mmt {
-                // failed
-                handle.local_max_time.store(t_mmt);
-                let cur = mmt.buf[latest].fail_current.load(Ordering::Relaxed, guard);
-                return Some(Err(cur));
+    ) -> PShared<'g, N> {
+        let (tid, guard, pool) = (handle.tid, &handle.guard, handle.pool);
+        if handle.rec.load(Ordering::Relaxed) {
+            if let Some(old) = self.swap_result(new, mmt, handle) {
+                return old;
             }
+            handle.rec.store(false, Ordering::Relaxed);
+        }
 
-            // already successful
-            if t_mmt >= t_own {
-                handle.pool.exec_info.cas_info.own[tid].store(mmt.buf[latest].checkpoint);
-                let _ = self.inner.compare_exchange(
-                    new.with_aux_bit(p_own as _).with_tid(tid),
-                    new.with_aux_bit(0).with_tid(0),
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
-                    guard,
-                );
+        let (stale, _) = stale_latest_idx(&mmt.buf);
+        let (p_own, _, _) = pool.exec_info.cas_info.own[tid].load().decode();
+        let tmp_new = new.with_aux_bit((!p_own) as _).with_tid(tid);
+        let mut old = self.load(Ordering::SeqCst, handle);
+
+        loop {
+            // The displaced pointer is persisted before the swap, to be returned after a crash.
+            mmt.buf[stale].fail_current.store(old, Ordering::Relaxed);
+            persist_obj(&mmt.buf[stale].fail_current, true);
+
+            if let Err(e) =
+                self.inner
+                    .compare_exchange(old, tmp_new, Ordering::SeqCst, Ordering::SeqCst, guard)
+            {
+                old = self.load_help(e.current, handle);
+                continue;
             }
 
-            handle.local_max_time.store(t_mmt);
-            return Some(Ok(()));
-        }
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
 
-        // Finalize the CAS
-        let _ = self.load(Ordering::SeqCst, handle);
+            // Checkpoint success
+            let t = mmt.buf[stale].checkpoint_succ(!p_own, handle);
 
-        let t_help = exec_info.cas_info.help[tid].load(!p_own);
-        if t_own >= t_help {
-            return None;
+            self.clean(tmp_new, t, handle);
+            return old;
         }
+    }
 
-        // Success because the checkpoint written by the helper is higher than the last CAS
-        // Since the value of location has already been changed, I just need to finalize my checkpoint.
-        let _ = mmt.buf[stale].checkpoint_succ(!p_own, handle);
-        sfence();
+    #[inline]
+    fn swap_result<'g>(
+        &'g self,
+        new: PShared<'_, N>,
+        mmt: &mut Swap<N>,
+        handle: &'g Handle,
+    ) -> Option<PShared<'g, N>> {
+        // `new` is only compared with the location.
+        let new = unsafe { PShared::from_usize(new.into_usize()) };
+        let res = DetectableLoc::cas_result(self, Some(new), &mut mmt.buf, handle)?;
+        let i = res.unwrap_or_else(|i| i);
+        Some(
+            mmt.buf[i]
+                .fail_current
+                .load(Ordering::Relaxed, &handle.guard),
+        )
+    }
 
-        Some(Ok(()))
+    #[inline]
+    fn cas_result<'g>(
+        &'g self,
+        new: PShared<'_, N>,
+        mmt: &mut Cas<N>,
+        handle: &'g Handle,
+    ) -> Option<Result<(), PShared<'_, N>>> {
+        // `new` is only compared with the location.
+        let new = unsafe { PShared::from_usize(new.into_usize()) };
+        let res = DetectableLoc::cas_result(self, Some(new), &mut mmt.buf, handle)?;
+        let cur = |i: usize| {
+            mmt.buf[i]
+                .fail_current
+                .load(Ordering::Relaxed, &handle.guard)
+        };
+        Some(res.map(|_| ()).map_err(cur))
     }
 
     /// Compare And Set (Non-detectable ver.)
@@ -363,9 +730,12 @@ impl<N: Collectable> DetectableCASAtomic<N> {
         }
     }
 
-    #[inline]
-    fn is_non_detectable_cas(ptr: PShared<'_, N>) -> bool {
-        ptr.aux_bit() == 1 && ptr.tid() == 0
+    /// Load without helping, for inspection of a pool which is not running
+    ///
+    /// Return `None` if the location holds the sequence number of a help descriptor instead of a pointer.
+    pub(crate) fn load_raw<'g>(&self, guard: &'g Guard) -> Option<PShared<'g, N>> {
+        let cur = self.inner.load(Ordering::SeqCst, guard);
+        (cur.desc_bit() == 0).then(|| cur.with_tid(0).with_aux_bit(0))
     }
 
     /// Load
@@ -374,248 +744,94 @@ impl<N: Collectable> DetectableCASAtomic<N> {
         let cur = self.inner.load(ord, &handle.guard);
         self.load_help(cur, handle)
     }
+}
 
-    // Location State
-    // - [parity: 0 | desc: 0 | tid: 0     | data]: `data` is a ptr and it guarantees to be persisted.
-    // - [parity: p | desc: 0 | tid: non-0 | data]: `data` is a ptr that may not be persisted. The `tid` wants a help in the context of parity `p`.
-    // - [parity: 1 | desc: 0 | tid: 0     | data]: `data` is a ptr that may not be persisted. Someone wants a help but it doesn't need to be announced. (non-detectable way)
-    // - [parity: 0 | desc: 1 | tid: non-0 | data]: `data` is a sequence number of `tid`'s descriptor.
-    #[inline]
-    fn load_help<'g>(&self, mut old: PShared<'g, N>, handle: &'g Handle) -> PShared<'g, N> {
-        let (exec_info, guard) = (&handle.pool.exec_info, &handle.guard);
-        loop {
-            // return if old is clean
-            if old.tid() == 0 {
-                return old;
-            }
-
-            let t_cur = 'chk: loop {
-                // get checkpoint timestamp
-                let t_cur = {
-                    let wait1 = exec_info.exec_time();
-                    loop {
-                        let now = exec_info.exec_time();
-                        if wait1 + exec_info.tsc_offset < now {
-                            break now;
-                        }
-                    }
-                };
-
-                // start spin loop
-                loop {
-                    let cur = self.inner.load(Ordering::SeqCst, guard);
-
-                    // return if cur is clean. (previous chk timestamp is useless.)
-                    if cur.tid() == 0 {
-                        return cur;
-                    }
-
-                    // if old was changed, new spin loop needs to be started.
-                    if old != cur {
-                        old = cur;
-                        break;
-                    }
-
-                    // if patience is over, I have to help it.
-                    let wait2 = exec_info.exec_time();
-
-                    cfg_if! {
-                        if #[cfg(not(feature = "tcrash"))] {
-                            let patience = Timestamp::from(40_000);
-                        } else {
-                            let patience = handle.pool.exec_info.tsc_offset;
-                        }
-                    };
-
-                    if wait2 > t_cur + patience {
-                        break 'chk t_cur;
-                    }
-                }
-            };
-            // Persist the pointer before registering help descriptor
-            persist_obj(&self.inner, false);
-
-            // If non-detectable CAS is proceeded, just CAS to clean ptr w/o help announcement.
-            if Self::is_non_detectable_cas(old) {
-                match self.inner.compare_exchange(
-                    old,
-                    old.with_aux_bit(0).with_tid(0),
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
-                    guard,
-                ) {
-                    Ok(clean_ptr) => return clean_ptr,
-                    Err(e) => old = e.current,
-                };
-
-                continue;
-            }
-
-            // Register my help descriptor if there is no descriptor yet.
-            if old.desc_bit() == 0 {
-                #[cfg(feature = "pmcheck")]
-                sfence(); // To pass the false positive of PSan.
+impl<'g, N: Collectable + 'g> DetectableLoc<'g> for DetectableCASAtomic<N> {
+    type Word = PShared<'g, N>;
 
-                match self.register_help(old, handle) {
-                    Ok(desc) => {
-                        old = desc;
-                    }
-                    Err(e) => {
-                        old = e.current;
-                        if old.desc_bit() == 0 {
-                            continue;
-                        }
-                    }
-                }
-            }
-
-            // Finalize the help descriptor.
-            match self.finalize_help(old, t_cur, handle) {
-                Ok(clean_ptr) => {
-                    return clean_ptr;
-                }
-                Err(e) => {
-                    old = e;
-                }
-            }
-        }
+    #[inline]
+    fn load_word(&self, handle: &'g Handle) -> Self::Word {
+        self.inner.load(Ordering::SeqCst, &handle.guard)
     }
 
     #[inline]
-    fn register_help<'g>(
+    fn cas_word(
         &self,
-        old: PShared<'g, N>,
+        old: Self::Word,
+        new: Self::Word,
         handle: &'g Handle,
-    ) -> Result<PShared<'g, N>, CompareExchangeError<'g, N, PShared<'g, N>>> {
-        assert!(old.desc_bit() == 0);
-        let (my_tid, my_help_desc) = (
-            handle.tid,
-            &*handle.pool.exec_info.cas_info.help_desc[handle.tid].0,
-        );
-
-        // 1. Set my descriptor
-        let my_new_seq = my_help_desc.seq.load(Ordering::SeqCst) + 1;
-        my_help_desc.seq.store(my_new_seq, Ordering::SeqCst);
-        // TODO: Add fence here after relaxing.
-        // persist ordering is guaranteed because it lies in same cache line.
-        my_help_desc
-            .tmp_new
-            .store(old.into_usize(), Ordering::SeqCst);
-        persist_obj(my_help_desc, false);
-
-        // 2. Register my descriptor
-        // representation: [ aux_bit: 0, desc_bit: 1, tid: help leader, payload: sequence of help leader ]
-        self.inner.compare_exchange(
-            old,
-            unsafe { PShared::from_usize(my_new_seq) }
-                .with_aux_bit(0)
-                .with_desc_bit(1)
-                .with_tid(my_tid),
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-            &handle.guard,
-        )
+    ) -> Result<(), Self::Word> {
+        self.inner
+            .compare_exchange(old, new, Ordering::SeqCst, Ordering::SeqCst, &handle.guard)
+            .map(|_| ())
+            .map_err(|e| e.current)
     }
 
-    /// Finalize the help descriptor at time `t_cur`.
     #[inline]
-    fn finalize_help<'g>(
-        &self,
-        old: PShared<'g, N>,
-        t_cur: Timestamp,
-        handle: &'g Handle,
-    ) -> Result<PShared<'g, N>, PShared<'g, N>> {
-        assert!(old.desc_bit() == 1);
-        let (cas_info, guard) = (&handle.pool.exec_info.cas_info, &handle.guard);
-
-        let winner_tmp_new = unsafe {
-            PShared::<N>::from_usize(
-                cas_info.help_desc[old.tid()] // `old.tid` is leader of help.
-                    .0
-                    .tmp_new
-                    .load(Ordering::SeqCst),
-            )
-        };
-
-        // Check if the sequence value on the ptr and descriptor are the same.
-        let seq = old
-            .with_aux_bit(0)
-            .with_desc_bit(0)
-            .with_tid(0)
-            .into_usize();
-        if seq != cas_info.help_desc[old.tid()].0.seq.load(Ordering::SeqCst) {
-            // This help has already done.
-            return Err(self.inner.load(Ordering::SeqCst, &handle.guard));
-        }
-
-        let winner_tid = winner_tmp_new.tid();
-        let winner_parity = winner_tmp_new.aux_bit() != 0;
-        let winner_new = winner_tmp_new.with_aux_bit(0).with_desc_bit(0).with_tid(0);
-
-        // CAS winner thread's pcheckpoint
-        let t_help = cas_info.help[winner_tid].load(winner_parity);
-        if t_cur <= t_help
-            || cas_info.help[winner_tid]
-                .compare_exchange(winner_parity, t_help, t_cur)
-                .is_err()
-        {
-            return Err(self.inner.load(Ordering::SeqCst, &handle.guard));
-        }
-        persist_obj(
-            &*cas_info.help[winner_tid].inner[winner_parity as usize],
-            false,
-        );
+    fn persist(&self, fence: bool) {
+        persist_obj(&self.inner, fence);
+    }
 
-        // help pointer to be clean.
-        let res =
-            self.inner
-                .compare_exchange(old, winner_new, Ordering::SeqCst, Ordering::SeqCst, guard);
-        persist_obj(&self.inner, true); // persist before return
-        res.map_err(|e| e.current)
+    #[inline]
+    fn help_other(&self, word: Self::Word, handle: &'g Handle) -> Option<Self::Word> {
+        // Finish the multi-word CAS of which descriptor is in the location.
+        is_mwcas(word).then(|| help_mwcas(self, word, handle))
     }
 }
 
 unsafe impl<N: Collectable + Send + Sync> Send for DetectableCASAtomic<N> {}
 unsafe impl<N: Collectable> Sync for DetectableCASAtomic<N> {}
 
+/// Per-thread entries of a pool (i.e. `max_threads + 1` entries indexed by tid), allocated when the pool is created
 #[derive(Debug)]
-pub(crate) struct CasHelpArr([CasHelp; NR_MAX_THREADS + 1]);
+#[repr(transparent)]
+pub(crate) struct PerThread<T>([T]);
 
-impl Default for CasHelpArr {
-    fn default() -> Self {
-        Self(array_init::array_init(|_| CasHelp::default()))
-    }
-}
-
-impl Deref for CasHelpArr {
-    type Target = [CasHelp; NR_MAX_THREADS + 1];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl<T: Default> PerThread<T> {
+    /// Initialize `len` entries at `ptr` and refer to them
+    ///
+    /// # Safety
+    ///
+    /// `ptr` should be a block of `len` entries in the pool.
+    pub(crate) unsafe fn init(ptr: *mut T, len: usize) -> &'static Self {
+        for i in 0..len {
+            ptr.add(i).write(T::default());
+        }
+        let arr = Self::from_raw(ptr, len);
+        persist_obj(arr, true);
+        arr
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct CasHelpDescArr([CasHelpDescriptor; NR_MAX_THREADS + 1]);
-
-impl Default for CasHelpDescArr {
-    fn default() -> Self {
-        Self(array_init::array_init(|_| Default::default()))
+impl<T> PerThread<T> {
+    /// Refer to `len` entries at `ptr`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` should be a block of `len` initialized entries in the pool.
+    pub(crate) unsafe fn from_raw(ptr: *const T, len: usize) -> &'static Self {
+        &*(ptr::slice_from_raw_parts(ptr, len) as *const Self)
     }
 }
 
-impl Deref for CasHelpDescArr {
-    type Target = [CasHelpDescriptor; NR_MAX_THREADS + 1];
+impl<T> Deref for PerThread<T> {
+    type Target = [T];
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+/// Per-thread last time receiving CAS helping, stored at `RootIdx::CASHelpArr`
+pub(crate) type CasHelpArr = PerThread<CasHelp>;
+
+/// Per-thread help descriptors, stored at `RootIdx::CASHelpDescArr`
+pub(crate) type CasHelpDescArr = PerThread<CasHelpDescriptor>;
+
 #[derive(Debug)]
 pub(crate) struct CasInfo {
     /// Per-thread CAS self-successful time
-    pub(crate) own: [CasOwn; NR_MAX_THREADS + 1],
+    pub(crate) own: Vec<CasOwn>,
 
     /// Per-thread Last time receiving CAS helping
     pub(crate) help: &'static CasHelpArr,
@@ -627,12 +843,29 @@ pub(crate) struct CasInfo {
 impl CasInfo {
     pub(crate) fn new(help: &'static CasHelpArr, help_desc: &'static CasHelpDescArr) -> Self {
         Self {
-            own: array_init::array_init(|_| CasOwn::default()),
+            own: (0..help.len()).map(|_| CasOwn::default()).collect(),
             help,
             help_desc,
         }
     }
 
+    /// Checkpoint at `t_cur` that the CAS of `tid` with `parity` has succeeded, on behalf of `tid`
+    ///
+    /// Return `false` if it has already been checkpointed at `t_cur` or later.
+    #[inline]
+    pub(crate) fn help_succ(&self, tid: usize, parity: bool, t_cur: Timestamp) -> bool {
+        let t_help = self.help[tid].load(parity);
+        if t_cur <= t_help
+            || self.help[tid]
+                .compare_exchange(parity, t_help, t_cur)
+                .is_err()
+        {
+            return false;
+        }
+        persist_obj(&*self.help[tid].inner[parity as usize], false);
+        true
+    }
+
     pub(crate) fn max_ts(&self) -> Timestamp {
         let m = self
             .own
@@ -650,7 +883,7 @@ impl CasInfo {
 /// Compare and Set memento
 #[derive(Debug)]
 pub struct Cas<N: Collectable> {
-    buf: [CachePadded<CasInner<N>>; 2],
+    buf: [CachePadded<CasInner<PAtomic<N>>>; 2],
 }
 
 impl<N: Collectable> Default for Cas<N> {
@@ -676,27 +909,58 @@ impl<N: Collectable> Collectable for Cas<N> {
     }
 }
 
-impl<N: Collectable> Cas<N> {
-    #[inline]
-    fn stale_latest_idx(&self) -> (usize, usize) {
-        let t0 = self.buf[0].checkpoint.decode().2;
-        let t1 = self.buf[1].checkpoint.decode().2;
+/// Swap memento
+///
+/// A buffer keeps the pointer displaced by a swap in `fail_current`, which is traced until the memento is cleared.
+#[derive(Debug)]
+pub struct Swap<N: Collectable> {
+    buf: [CachePadded<CasInner<PAtomic<N>>>; 2],
+}
 
-        if t0 < t1 {
-            (0, 1)
-        } else {
-            (1, 0)
+impl<N: Collectable> Default for Swap<N> {
+    fn default() -> Self {
+        Self {
+            buf: [Default::default(), Default::default()],
         }
     }
 }
 
+impl<N: Collectable> Memento for Swap<N> {
+    #[inline]
+    fn clear(&mut self) {
+        self.buf[0].clear();
+        self.buf[1].clear();
+    }
+}
+
+impl<N: Collectable> Collectable for Swap<N> {
+    fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        Collectable::filter(&mut mmt.buf[0], tid, gc, pool);
+        Collectable::filter(&mut mmt.buf[1], tid, gc, pool);
+    }
+}
+
+/// Indexes of the stale and latest buffer of a CAS memento
+#[inline]
+pub(crate) fn stale_latest_idx<C>(buf: &[CachePadded<CasInner<C>>; 2]) -> (usize, usize) {
+    let t0 = buf[0].checkpoint.decode().2;
+    let t1 = buf[1].checkpoint.decode().2;
+
+    if t0 < t1 {
+        (0, 1)
+    } else {
+        (1, 0)
+    }
+}
+
+/// Buffer of a CAS memento, where `C` is the current value of the location in case of failure
 #[derive(Debug)]
-struct CasInner<N: Collectable> {
-    checkpoint: CasTimestamp,
-    fail_current: PAtomic<N>,
+pub(crate) struct CasInner<C> {
+    pub(crate) checkpoint: CasTimestamp,
+    pub(crate) fail_current: C,
 }
 
-impl<N: Collectable> Memento for CasInner<N> {
+impl<C: Default + Collectable> Memento for CasInner<C> {
     #[inline]
     fn clear(&mut self) {
         self.checkpoint = CasTimestamp::new(false, false, Timestamp::from(0));
@@ -705,7 +969,7 @@ impl<N: Collectable> Memento for CasInner<N> {
     }
 }
 
-impl<N: Collectable> Default for CasInner<N> {
+impl<C: Default> Default for CasInner<C> {
     fn default() -> Self {
         Self {
             checkpoint: CasTimestamp(0),
@@ -714,7 +978,7 @@ impl<N: Collectable> Default for CasInner<N> {
     }
 }
 
-impl<N: Collectable> Collectable for CasInner<N> {
+impl<C: Collectable> Collectable for CasInner<C> {
     fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         // Among CAS clients, those with max checkpoint are recorded
         let (_, f_mmt, t_mmt) = mmt.checkpoint.decode();
@@ -732,9 +996,9 @@ impl<N: Collectable> Collectable for CasInner<N> {
     }
 }
 
-impl<N: Collectable> CasInner<N> {
+impl<C> CasInner<C> {
     #[inline]
-    fn checkpoint_succ(&mut self, parity: bool, handle: &Handle) -> Timestamp {
+    pub(crate) fn checkpoint_succ(&mut self, parity: bool, handle: &Handle) -> Timestamp {
         let t = handle.pool.exec_info.exec_time();
         let ts_succ = CasTimestamp::new(parity, false, t);
 
@@ -747,10 +1011,10 @@ impl<N: Collectable> CasInner<N> {
     }
 
     #[inline]
-    fn checkpoint_fail(&mut self, current: PShared<'_, N>, handle: &Handle) {
+    pub(crate) fn checkpoint_fail(&mut self, current: C, handle: &Handle) {
         let t = handle.pool.exec_info.exec_time();
         let ts_fail = CasTimestamp::new(false, true, t);
-        self.fail_current.store(current, Ordering::Relaxed);
+        self.fail_current = current;
         fence(Ordering::Release);
         self.checkpoint = ts_fail;
         persist_obj(self, true);
@@ -958,6 +1222,61 @@ pub(crate) mod test {
         );
     }
 
+    // Each thread swaps its nodes in, starting from a null location, and collects the values of the nodes they displaced.
+    // Every node is either displaced by exactly one swap or left in the location, and only one swap displaces null.
+    #[test]
+    fn swap() {
+        use crate::test_utils::thread;
+
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        let pool = get_dummy_handle(FILE_SIZE).unwrap();
+        let loc = pool.alloc::<DetectableCASAtomic<Node<TestValue>>>();
+        let loc = unsafe {
+            std::ptr::write(loc.deref_mut(pool), DetectableCASAtomic::default());
+            loc.deref(pool)
+        };
+
+        let handles = (1..=NR_THREAD)
+            .map(|tid| {
+                thread::spawn(move || {
+                    let handle = Handle::new(tid, pool.pin(), pool);
+                    (0..NR_COUNT)
+                        .map(|seq| {
+                            let node = POwned::new(
+                                Node {
+                                    data: TestValue::new(tid, seq),
+                                },
+                                pool,
+                            );
+                            persist_obj(unsafe { node.deref(pool) }, true);
+                            let node = node.into_shared(&handle.guard);
+
+                            let old = loc.swap(node, &mut super::Swap::default(), &handle);
+                            unsafe { old.as_ref(pool) }.map(|old| old.data)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut vals = handles
+            .iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let handle = Handle::new(1, pool.pin(), pool);
+        let last = loc.load(Ordering::SeqCst, &handle);
+        vals.push(unsafe { last.as_ref(pool) }.map(|last| last.data));
+        vals.sort();
+
+        let mut expected = (1..=NR_THREAD)
+            .flat_map(|tid| (0..NR_COUNT).map(move |seq| Some(TestValue::new(tid, seq))))
+            .collect::<Vec<_>>();
+        expected.push(None);
+        expected.sort();
+        assert_eq!(vals, expected);
+    }
+
     /// Test function pmcheck
     #[cfg(feature = "pmcheck")]
     pub(crate) fn dcas(pool_postfix: &str) {
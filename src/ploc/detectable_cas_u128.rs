@@ -0,0 +1,382 @@
+//! Detectable CAS on a double-width (128-bit) word
+//!
+//! `DetectableCASU128` is a 16-byte aligned cell CASed with `cmpxchg16b`, e.g. for a pointer and a counter or a pair
+//! of pointers updated together. The tags of `DetectableCASU64` (parity, descriptor and tid bits) are at the top of
+//! the upper half, and a help descriptor replaces the upper half only. Hence the value of the cell is bounded by
+//! `DetectableCASU128::MAX`, and it gets the same helping and detectability as the other detectable CASes.
+//!
+//! It is available only on x86-64, and panics on a CPU without `cmpxchg16b`.
+
+use std::arch::asm;
+use std::cell::UnsafeCell;
+use std::sync::atomic::Ordering;
+
+use crossbeam_utils::CachePadded;
+
+use super::detectable_cas::{stale_latest_idx, CasInner, DetectableLoc, DetectableWord};
+use super::detectable_cas_u64::Word;
+use super::Handle;
+use crate::{
+    pmem::{ll::persist_obj, sfence, Collectable, GarbageCollection, PoolHandle},
+    Memento, PDefault,
+};
+
+/// Tagged cell of `DetectableCASU128`, whose upper half is tagged as a `Word`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Word128(u128);
+
+impl Word128 {
+    #[inline]
+    fn hi(self) -> Word {
+        Word((self.0 >> 64) as u64)
+    }
+
+    #[inline]
+    fn with_hi(self, hi: Word) -> Self {
+        Self((self.0 & u64::MAX as u128) | ((hi.0 as u128) << 64))
+    }
+
+    #[inline]
+    fn value(self) -> u128 {
+        self.0 & DetectableCASU128::MAX
+    }
+
+    #[inline]
+    fn with_aux_bit(self, aux_bit: u64) -> Self {
+        self.with_hi(self.hi().with_aux_bit(aux_bit))
+    }
+
+    #[inline]
+    fn with_tid(self, tid: usize) -> Self {
+        self.with_hi(self.hi().with_tid(tid))
+    }
+}
+
+impl DetectableWord for Word128 {
+    #[inline]
+    fn head(self) -> Word {
+        self.hi()
+    }
+
+    #[inline]
+    fn with_head(self, head: Word) -> Self {
+        self.with_hi(head)
+    }
+}
+
+/// `lock cmpxchg16b` on `dst`, which returns the previous value as `Ok` if it was `old`
+///
+/// # Panics
+///
+/// Panics if the CPU does not support `cmpxchg16b`. `dst` should be aligned to 16 bytes, which is checked in debug builds.
+#[inline]
+unsafe fn cmpxchg16b(dst: *mut u128, old: u128, new: u128) -> Result<u128, u128> {
+    // The detection is cached by `std`.
+    assert!(
+        is_x86_feature_detected!("cmpxchg16b"),
+        "cmpxchg16b is not supported"
+    );
+    debug_assert!(dst as usize % 16 == 0, "{dst:p} is not aligned to 16 bytes");
+    let (prev_lo, prev_hi): (u64, u64);
+    let succ: u8;
+
+    // `rbx` cannot be an operand, so the lower half of `new` is swapped into it around the instruction.
+    asm!(
+        "xchg {new_lo}, rbx",
+        "lock cmpxchg16b xmmword ptr [{dst}]",
+        "sete {succ}",
+        "mov rbx, {new_lo}",
+        dst = in(reg) dst,
+        new_lo = inout(reg) new as u64 => _,
+        succ = out(reg_byte) succ,
+        in("rcx") (new >> 64) as u64,
+        inout("rax") old as u64 => prev_lo,
+        inout("rdx") (old >> 64) as u64 => prev_hi,
+        options(nostack),
+    );
+
+    let prev = ((prev_hi as u128) << 64) | prev_lo as u128;
+    if succ != 0 {
+        Ok(prev)
+    } else {
+        Err(prev)
+    }
+}
+
+/// Detectable CAS Atomic double-width word
+///
+/// The cell is always accessed with `lock cmpxchg16b`, which is sequentially consistent.
+#[derive(Debug, Default)]
+#[repr(C, align(16))]
+pub struct DetectableCASU128 {
+    inner: UnsafeCell<u128>,
+}
+
+unsafe impl Send for DetectableCASU128 {}
+unsafe impl Sync for DetectableCASU128 {}
+
+impl Collectable for DetectableCASU128 {
+    fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {}
+}
+
+impl PDefault for DetectableCASU128 {
+    fn pdefault(_: &Handle) -> Self {
+        Default::default()
+    }
+}
+
+impl DetectableCASU128 {
+    /// Maximum value of the cell
+    pub const MAX: u128 = u128::MAX >> Word::NR_TAG_BITS;
+
+    /// Create a cell of `value`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` exceeds `DetectableCASU128::MAX`.
+    pub fn new(value: u128) -> Self {
+        assert!(value <= Self::MAX, "value {value} is too large");
+        Self {
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    fn compare_exchange(&self, old: u128, new: u128) -> Result<u128, Word128> {
+        unsafe { cmpxchg16b(self.inner.get(), old, new) }.map_err(Word128)
+    }
+
+    /// Load the cell without helping
+    ///
+    /// There is no 16-byte atomic load on x86-64, so the cell is read by a CAS from 0 to 0. `lock cmpxchg16b` writes
+    /// the cache line even if it fails, but it writes back the value it has read, so the cell is never changed.
+    /// Hence it does not affect what is persisted or in which order: the line may be written back at any time
+    /// anyway (e.g. by a cache eviction), and only a value already stored in the cell can be written back. As with
+    /// any load, the value is not guaranteed to be persisted, and a caller relying on it persists the cell itself
+    /// (e.g. `load_help` before registering a help descriptor).
+    #[inline]
+    fn load_raw(&self) -> Word128 {
+        Word128(unsafe { cmpxchg16b(self.inner.get(), 0, 0) }.unwrap_or_else(|cur| cur))
+    }
+
+    /// Compare And Set
+    ///
+    /// # Panics
+    ///
+    /// Panics if `old` or `new` exceeds `DetectableCASU128::MAX`.
+    pub fn cas(
+        &self,
+        old: u128,
+        new: u128,
+        mmt: &mut CasU128,
+        handle: &Handle,
+    ) -> Result<(), u128> {
+        assert!(
+            old <= Self::MAX && new <= Self::MAX,
+            "value {old} or {new} is too large"
+        );
+        let (tid, pool) = (handle.tid, handle.pool);
+        if handle.rec.load(Ordering::Relaxed) {
+            if let Some(res) = self.cas_result(Some(Word128(new)), &mut mmt.buf, handle) {
+                return res.map(|_| ()).map_err(|i| mmt.buf[i].fail_current);
+            }
+            handle.rec.store(false, Ordering::Relaxed);
+        }
+
+        let (stale, _) = stale_latest_idx(&mmt.buf);
+        let (p_own, _, _) = pool.exec_info.cas_info.own[tid].load().decode();
+        let tmp_new = Word128(new).with_aux_bit((!p_own) as _).with_tid(tid);
+
+        loop {
+            // 1. First cas
+            let res = self.compare_exchange(old, tmp_new.0);
+
+            if let Err(e) = res {
+                let cur = self.load_help(e, handle).value();
+                if cur == old {
+                    // retry for the property of strong CAS
+                    continue;
+                }
+
+                mmt.buf[stale].checkpoint_fail(cur, handle);
+                return Err(cur);
+            }
+
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
+
+            // Checkpoint success
+            let t = mmt.buf[stale].checkpoint_succ(!p_own, handle);
+
+            self.clean(tmp_new, t, handle);
+            return Ok(());
+        }
+    }
+
+    /// Compare And Set (Non-detectable ver.)
+    ///
+    /// Used when the recovery is not critical (e.g. helping CAS).
+    /// WARN: The return value is not stable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `old` or `new` exceeds `DetectableCASU128::MAX`.
+    pub fn cas_non_detectable(&self, old: u128, new: u128, handle: &Handle) -> Result<(), u128> {
+        assert!(
+            old <= Self::MAX && new <= Self::MAX,
+            "value {old} or {new} is too large"
+        );
+        let tmp_new = Word128(new).with_aux_bit(1);
+
+        loop {
+            // 1. First cas
+            let res = self.compare_exchange(old, tmp_new.0);
+
+            if let Err(e) = res {
+                let cur = self.load_help(e, handle).value();
+                if cur == old {
+                    // retry for the property of strong CAS
+                    continue;
+                }
+
+                return Err(cur);
+            }
+
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
+
+            // 2. Second cas
+            // By inserting a value with aux bit removed, it prevents further helping.
+            let _ = self.compare_exchange(tmp_new.0, new).map_err(|_| {
+                // In case of CAS failure, sfence is required for synchronous flush.
+                sfence()
+            });
+
+            return Ok(());
+        }
+    }
+
+    /// Load
+    #[inline]
+    pub fn load(&self, handle: &Handle) -> u128 {
+        let cur = self.load_raw();
+        self.load_help(cur, handle).value()
+    }
+}
+
+impl DetectableLoc<'_> for DetectableCASU128 {
+    type Word = Word128;
+
+    #[inline]
+    fn load_word(&self, _: &Handle) -> Word128 {
+        self.load_raw()
+    }
+
+    #[inline]
+    fn cas_word(&self, old: Word128, new: Word128, _: &Handle) -> Result<(), Word128> {
+        self.compare_exchange(old.0, new.0).map(|_| ())
+    }
+
+    #[inline]
+    fn persist(&self, fence: bool) {
+        persist_obj(&self.inner, fence);
+    }
+}
+
+/// Compare and Set memento of `DetectableCASU128`
+#[derive(Debug, Default)]
+pub struct CasU128 {
+    buf: [CachePadded<CasInner<u128>>; 2],
+}
+
+impl Memento for CasU128 {
+    #[inline]
+    fn clear(&mut self) {
+        self.buf[0].clear();
+        self.buf[1].clear();
+    }
+}
+
+impl Collectable for CasU128 {
+    fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        Collectable::filter(&mut mmt.buf[0], tid, gc, pool);
+        Collectable::filter(&mut mmt.buf[1], tid, gc, pool);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ploc::Checkpoint;
+    use crate::pmem::RootObj;
+    use crate::test_utils::tests::*;
+
+    const NR_THREAD: usize = 3;
+    #[cfg(not(feature = "pmcheck"))]
+    const NR_COUNT: usize = 10_000;
+    #[cfg(feature = "pmcheck")]
+    const NR_COUNT: usize = 10;
+
+    struct Updates {
+        upds: [(CasU128, Checkpoint<u128>, CasU128); NR_COUNT],
+    }
+
+    impl Memento for Updates {
+        fn clear(&mut self) {
+            for upd in &mut self.upds {
+                upd.0.clear();
+                upd.1.clear();
+                upd.2.clear();
+            }
+        }
+    }
+
+    impl Default for Updates {
+        fn default() -> Self {
+            Self {
+                upds: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Updates {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for upd in &mut m.upds {
+                Collectable::filter(upd, tid, gc, pool);
+            }
+        }
+    }
+
+    // Each thread sets both halves of the cell from 0 to its value, and swaps it back to 0.
+    impl RootObj<Updates> for TestRootObj<DetectableCASU128> {
+        fn run(&self, mmt: &mut Updates, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+            let loc = &self.obj;
+
+            for seq in 0..NR_COUNT {
+                let (set, old, swap) = &mut mmt.upds[seq];
+
+                let value = TestValue::new(handle.tid, seq).into_usize() as u128;
+                while loc.cas(0, (value << 64) | value, set, handle).is_err() {}
+
+                // Only the thread that has set the cell swaps it.
+                let old = old.checkpoint(|| loc.load(handle), handle);
+                assert_eq!(old >> 64, old & u64::MAX as u128);
+                assert!(loc.cas(old, 0, swap, handle).is_ok());
+
+                testee.report(seq, TestValue::from_usize(old as usize));
+            }
+        }
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn detectable_cas_u128() {
+        const FILE_NAME: &str = "detectable_cas_u128";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<DetectableCASU128>, Updates>(
+            FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT,
+        );
+    }
+}
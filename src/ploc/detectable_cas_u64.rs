@@ -0,0 +1,515 @@
+//! Detectable CAS on an integer word
+//!
+//! `DetectableCASU64` tags its word in the same way as `DetectableCASAtomic` tags a pointer (parity, descriptor and
+//! tid bits), and shares the CAS information of the pool (e.g. help descriptors) with it. Hence the value of the word
+//! is bounded by `DetectableCASU64::MAX`.
+//!
+//! Besides CAS, the word supports a detectable fetch-and-update (e.g. `fetch_add`), which retries with a single
+//! `FetchUpdate` memento that records the previous value before each attempt.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crossbeam_utils::CachePadded;
+
+use super::detectable_cas::{stale_latest_idx, CasInner, DetectableLoc, DetectableWord};
+use super::Handle;
+use crate::{
+    impl_left_bits,
+    pmem::{ll::persist_obj, sfence, Collectable, GarbageCollection, PoolHandle},
+    Memento, PDefault,
+};
+
+/// Tagged word of `DetectableCASU64`, which is also the upper half of `DetectableCASU128`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Word(pub(crate) u64);
+
+impl Word {
+    // Same as the tags of `PAtomic` so that a help descriptor is read in the same way for both.
+    const POS_AUX_BITS: u32 = 0;
+    const NR_AUX_BITS: u32 = 1;
+    impl_left_bits!(aux_bits, Self::POS_AUX_BITS, Self::NR_AUX_BITS, u64);
+
+    const POS_DESC_BITS: u32 = Self::POS_AUX_BITS + Self::NR_AUX_BITS;
+    const NR_DESC_BITS: u32 = 1;
+    impl_left_bits!(desc_bits, Self::POS_DESC_BITS, Self::NR_DESC_BITS, u64);
+
+    const POS_TID_BITS: u32 = Self::POS_DESC_BITS + Self::NR_DESC_BITS;
+    const NR_TID_BITS: u32 = crate::pepoch::atomic::NR_TID_BITS;
+    impl_left_bits!(tid_bits, Self::POS_TID_BITS, Self::NR_TID_BITS, u64);
+
+    pub(crate) const NR_TAG_BITS: u32 = Self::NR_AUX_BITS + Self::NR_DESC_BITS + Self::NR_TID_BITS;
+
+    #[inline]
+    pub(crate) fn aux_bit(self) -> u64 {
+        (self.0 & Self::aux_bits()).rotate_left(Self::POS_AUX_BITS + Self::NR_AUX_BITS)
+    }
+
+    #[inline]
+    pub(crate) fn desc_bit(self) -> u64 {
+        (self.0 & Self::desc_bits()).rotate_left(Self::POS_DESC_BITS + Self::NR_DESC_BITS)
+    }
+
+    #[inline]
+    pub(crate) fn tid(self) -> usize {
+        (self.0 & Self::tid_bits()).rotate_left(Self::POS_TID_BITS + Self::NR_TID_BITS) as usize
+    }
+
+    #[inline]
+    pub(crate) fn value(self) -> u64 {
+        self.0 & DetectableCASU64::MAX
+    }
+
+    #[inline]
+    pub(crate) fn with_aux_bit(self, aux_bit: u64) -> Self {
+        let aux = Self::aux_bits() & aux_bit.rotate_right(Self::POS_AUX_BITS + Self::NR_AUX_BITS);
+        Self(aux | (!Self::aux_bits() & self.0))
+    }
+
+    #[inline]
+    pub(crate) fn with_desc_bit(self, desc_bit: u64) -> Self {
+        let desc =
+            Self::desc_bits() & desc_bit.rotate_right(Self::POS_DESC_BITS + Self::NR_DESC_BITS);
+        Self(desc | (!Self::desc_bits() & self.0))
+    }
+
+    #[inline]
+    pub(crate) fn with_tid(self, tid: usize) -> Self {
+        let tid =
+            Self::tid_bits() & (tid as u64).rotate_right(Self::POS_TID_BITS + Self::NR_TID_BITS);
+        Self(tid | (!Self::tid_bits() & self.0))
+    }
+
+    /// Whether the value is persisted and no one wants a help
+    #[inline]
+    pub(crate) fn is_clean(self) -> bool {
+        self.tid() == 0 && self.aux_bit() == 0
+    }
+
+    #[inline]
+    pub(crate) fn is_non_detectable_cas(self) -> bool {
+        self.aux_bit() == 1 && self.tid() == 0
+    }
+}
+
+/// Detectable CAS Atomic word
+#[derive(Debug, Default)]
+pub struct DetectableCASU64 {
+    inner: AtomicU64,
+}
+
+impl Collectable for DetectableCASU64 {
+    fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {}
+}
+
+impl PDefault for DetectableCASU64 {
+    fn pdefault(_: &Handle) -> Self {
+        Default::default()
+    }
+}
+
+impl DetectableCASU64 {
+    /// Maximum value of the word
+    pub const MAX: u64 = u64::MAX >> Word::NR_TAG_BITS;
+
+    /// Create a word of `value`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` exceeds `DetectableCASU64::MAX`.
+    pub fn new(value: u64) -> Self {
+        assert!(value <= Self::MAX, "value {value} is too large");
+        Self {
+            inner: AtomicU64::new(value),
+        }
+    }
+
+    /// Compare And Set
+    ///
+    /// # Panics
+    ///
+    /// Panics if `old` or `new` exceeds `DetectableCASU64::MAX`.
+    pub fn cas(&self, old: u64, new: u64, mmt: &mut CasU64, handle: &Handle) -> Result<(), u64> {
+        assert!(
+            old <= Self::MAX && new <= Self::MAX,
+            "value {old} or {new} is too large"
+        );
+        let (tid, pool) = (handle.tid, handle.pool);
+        if handle.rec.load(Ordering::Relaxed) {
+            if let Some(res) = self.cas_result(Some(Word(new)), &mut mmt.buf, handle) {
+                return res.map(|_| ()).map_err(|i| mmt.buf[i].fail_current);
+            }
+            handle.rec.store(false, Ordering::Relaxed);
+        }
+
+        let (stale, _) = stale_latest_idx(&mmt.buf);
+        let (p_own, _, _) = pool.exec_info.cas_info.own[tid].load().decode();
+        let tmp_new = Word(new).with_aux_bit((!p_own) as _).with_tid(tid);
+
+        loop {
+            // 1. First cas
+            let res =
+                self.inner
+                    .compare_exchange(old, tmp_new.0, Ordering::SeqCst, Ordering::SeqCst);
+
+            if let Err(e) = res {
+                let cur = self.load_help(Word(e), handle).value();
+                if cur == old {
+                    // retry for the property of strong CAS
+                    continue;
+                }
+
+                mmt.buf[stale].checkpoint_fail(cur, handle);
+                return Err(cur);
+            }
+
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
+
+            // Checkpoint success
+            let t = mmt.buf[stale].checkpoint_succ(!p_own, handle);
+
+            self.clean(tmp_new, t, handle);
+            return Ok(());
+        }
+    }
+
+    /// Fetch the value and update it to what `f` returns for it, unless `f` returns `None`
+    ///
+    /// Return the previous value, as `Err` if `f` returned `None`. `f` may be called more than once under
+    /// contention. The update is applied exactly once, and the same previous value is returned after a crash.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` returns a value exceeding `DetectableCASU64::MAX`.
+    pub fn fetch_update<F>(
+        &self,
+        mut f: F,
+        mmt: &mut FetchUpdate,
+        handle: &Handle,
+    ) -> Result<u64, u64>
+    where
+        F: FnMut(u64) -> Option<u64>,
+    {
+        let (tid, pool) = (handle.tid, handle.pool);
+        if handle.rec.load(Ordering::Relaxed) {
+            if let Some(ret) = self.fetch_update_result(mmt, handle) {
+                return ret;
+            }
+            handle.rec.store(false, Ordering::Relaxed);
+        }
+
+        let (stale, _) = stale_latest_idx(&mmt.buf);
+        let (p_own, _, _) = pool.exec_info.cas_info.own[tid].load().decode();
+        let mut old = self.load(Ordering::SeqCst, handle);
+
+        loop {
+            let new = match f(old) {
+                Some(new) => new,
+                None => {
+                    mmt.buf[stale].checkpoint_fail(old, handle);
+                    return Err(old);
+                }
+            };
+            assert!(new <= Self::MAX, "value {new} is too large");
+
+            // The previous value is persisted before the update, to be returned after a crash.
+            mmt.buf[stale].fail_current = old;
+            persist_obj(&mmt.buf[stale].fail_current, true);
+
+            let tmp_new = Word(new).with_aux_bit((!p_own) as _).with_tid(tid);
+            if let Err(e) =
+                self.inner
+                    .compare_exchange(old, tmp_new.0, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                old = self.load_help(Word(e), handle).value();
+                continue;
+            }
+
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
+
+            // Checkpoint success
+            let t = mmt.buf[stale].checkpoint_succ(!p_own, handle);
+
+            self.clean(tmp_new, t, handle);
+            return Ok(old);
+        }
+    }
+
+    /// Add `val` to the value, wrapping around at `DetectableCASU64::MAX`, and return the previous value
+    pub fn fetch_add(&self, val: u64, mmt: &mut FetchUpdate, handle: &Handle) -> u64 {
+        self.fetch_update(|old| Some(old.wrapping_add(val) & Self::MAX), mmt, handle)
+            .unwrap()
+    }
+
+    #[inline]
+    fn fetch_update_result(
+        &self,
+        mmt: &mut FetchUpdate,
+        handle: &Handle,
+    ) -> Option<Result<u64, u64>> {
+        self.cas_result(None, &mut mmt.buf, handle).map(|res| {
+            res.map(|i| mmt.buf[i].fail_current)
+                .map_err(|i| mmt.buf[i].fail_current)
+        })
+    }
+
+    /// Compare And Set (Non-detectable ver.)
+    ///
+    /// Used when the recovery is not critical (e.g. helping CAS).
+    /// WARN: The return value is not stable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `old` or `new` exceeds `DetectableCASU64::MAX`.
+    pub fn cas_non_detectable(&self, old: u64, new: u64, handle: &Handle) -> Result<(), u64> {
+        assert!(
+            old <= Self::MAX && new <= Self::MAX,
+            "value {old} or {new} is too large"
+        );
+        let tmp_new = Word(new).with_aux_bit(1);
+
+        loop {
+            // 1. First cas
+            let res =
+                self.inner
+                    .compare_exchange(old, tmp_new.0, Ordering::SeqCst, Ordering::SeqCst);
+
+            if let Err(e) = res {
+                let cur = self.load_help(Word(e), handle).value();
+                if cur == old {
+                    // retry for the property of strong CAS
+                    continue;
+                }
+
+                return Err(cur);
+            }
+
+            // If successful, persist the location
+            persist_obj(&self.inner, true);
+
+            // 2. Second cas
+            // By inserting a value with aux bit removed, it prevents further helping.
+            let _ = self
+                .inner
+                .compare_exchange(tmp_new.0, new, Ordering::SeqCst, Ordering::SeqCst)
+                .map_err(|_| {
+                    // In case of CAS failure, sfence is required for synchronous flush.
+                    sfence()
+                });
+
+            return Ok(());
+        }
+    }
+
+    /// Load
+    #[inline]
+    pub fn load(&self, ord: Ordering, handle: &Handle) -> u64 {
+        let cur = Word(self.inner.load(ord));
+        self.load_help(cur, handle).value()
+    }
+}
+
+impl DetectableWord for Word {
+    #[inline]
+    fn head(self) -> Word {
+        self
+    }
+
+    #[inline]
+    fn with_head(self, head: Word) -> Self {
+        head
+    }
+}
+
+impl DetectableLoc<'_> for DetectableCASU64 {
+    type Word = Word;
+
+    #[inline]
+    fn load_word(&self, _: &Handle) -> Word {
+        Word(self.inner.load(Ordering::SeqCst))
+    }
+
+    #[inline]
+    fn cas_word(&self, old: Word, new: Word, _: &Handle) -> Result<(), Word> {
+        self.inner
+            .compare_exchange(old.0, new.0, Ordering::SeqCst, Ordering::SeqCst)
+            .map(|_| ())
+            .map_err(Word)
+    }
+
+    #[inline]
+    fn persist(&self, fence: bool) {
+        persist_obj(&self.inner, fence);
+    }
+}
+
+/// Compare and Set memento of `DetectableCASU64`
+#[derive(Debug, Default)]
+pub struct CasU64 {
+    buf: [CachePadded<CasInner<u64>>; 2],
+}
+
+impl Memento for CasU64 {
+    #[inline]
+    fn clear(&mut self) {
+        self.buf[0].clear();
+        self.buf[1].clear();
+    }
+}
+
+impl Collectable for CasU64 {
+    fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        Collectable::filter(&mut mmt.buf[0], tid, gc, pool);
+        Collectable::filter(&mut mmt.buf[1], tid, gc, pool);
+    }
+}
+
+/// Fetch-and-update memento of `DetectableCASU64`
+///
+/// A buffer keeps the previous value of an update in `fail_current`, whether the update succeeded or not.
+#[derive(Debug, Default)]
+pub struct FetchUpdate {
+    buf: [CachePadded<CasInner<u64>>; 2],
+}
+
+impl Memento for FetchUpdate {
+    #[inline]
+    fn clear(&mut self) {
+        self.buf[0].clear();
+        self.buf[1].clear();
+    }
+}
+
+impl Collectable for FetchUpdate {
+    fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        Collectable::filter(&mut mmt.buf[0], tid, gc, pool);
+        Collectable::filter(&mut mmt.buf[1], tid, gc, pool);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ploc::Checkpoint;
+    use crate::pmem::RootObj;
+    use crate::test_utils::tests::*;
+
+    const NR_THREAD: usize = 3;
+    #[cfg(not(feature = "pmcheck"))]
+    const NR_COUNT: usize = 10_000;
+    #[cfg(feature = "pmcheck")]
+    const NR_COUNT: usize = 10;
+
+    struct Updates {
+        upds: [(CasU64, Checkpoint<u64>, CasU64); NR_COUNT],
+    }
+
+    impl Memento for Updates {
+        fn clear(&mut self) {
+            for upd in &mut self.upds {
+                upd.0.clear();
+                upd.1.clear();
+                upd.2.clear();
+            }
+        }
+    }
+
+    impl Default for Updates {
+        fn default() -> Self {
+            Self {
+                upds: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Updates {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for upd in &mut m.upds {
+                Collectable::filter(upd, tid, gc, pool);
+            }
+        }
+    }
+
+    // Each thread sets the word from 0 to its value, and swaps it back to 0.
+    impl RootObj<Updates> for TestRootObj<DetectableCASU64> {
+        fn run(&self, mmt: &mut Updates, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+            let loc = &self.obj;
+
+            for seq in 0..NR_COUNT {
+                let (set, old, swap) = &mut mmt.upds[seq];
+
+                let value = TestValue::new(handle.tid, seq).into_usize() as u64;
+                while loc.cas(0, value, set, handle).is_err() {}
+
+                // Only the thread that has set the word swaps it.
+                let old = old.checkpoint(|| loc.load(Ordering::SeqCst, handle), handle);
+                assert!(loc.cas(old, 0, swap, handle).is_ok());
+
+                testee.report(seq, TestValue::from_usize(old as usize));
+            }
+        }
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn detectable_cas_u64() {
+        const FILE_NAME: &str = "detectable_cas_u64";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<DetectableCASU64>, Updates>(
+            FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT,
+        );
+    }
+
+    struct Adds {
+        adds: [FetchUpdate; NR_COUNT],
+    }
+
+    impl Memento for Adds {
+        fn clear(&mut self) {
+            for add in &mut self.adds {
+                add.clear();
+            }
+        }
+    }
+
+    impl Default for Adds {
+        fn default() -> Self {
+            Self {
+                adds: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Adds {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for add in &mut m.adds {
+                FetchUpdate::filter(add, tid, gc, pool);
+            }
+        }
+    }
+
+    // The counter is incremented exactly `NR_THREAD * NR_COUNT` times iff each previous value is returned once.
+    impl RootObj<Adds> for TestRootObj<DetectableCASU64> {
+        fn run(&self, mmt: &mut Adds, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+            let counter = &self.obj;
+
+            for seq in 0..NR_COUNT {
+                let old = counter.fetch_add(1, &mut mmt.adds[seq], handle) as usize;
+                testee.report(seq, TestValue::new(old % NR_THREAD + 1, old / NR_THREAD));
+            }
+        }
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn fetch_add() {
+        const FILE_NAME: &str = "fetch_add";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<DetectableCASU64>, Adds>(FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT);
+    }
+}
@@ -0,0 +1,228 @@
+//! Detectable fork/join
+//!
+//! A `Fork` memento owns the memento of a child, which is run on another thread with its own tid. The spawn is
+//! checkpointed, and the output of the child is persisted when it returns. If a crash occurs, the parent calls
+//! `fork_join` again in recovery mode: a child that had finished is not run again, and a child that had been
+//! spawned is resumed in recovery mode with the same tid, since the detectable CASes in its memento are bound to it.
+//! The tid of a child is reserved in the pool before the spawn is checkpointed, so it is never given to a root memento.
+//!
+//! The child is run in a scope of threads, whose `catch_unwind` cannot be unwound through by a thread killed by
+//! `pthread_exit`. Hence the parent should not be killed by the thread crash test (`tcrash`) during `fork_join`.
+
+use std::mem;
+use std::sync::atomic::Ordering;
+
+use crossbeam_utils::thread;
+
+use super::{Checkpoint, Handle};
+use crate::pmem::alloc::{Collectable, GarbageCollection};
+use crate::pmem::ll::persist_obj;
+use crate::pmem::PoolHandle;
+use crate::Memento;
+
+/// Fork memento
+#[derive(Debug)]
+pub struct Fork<M: Memento, O: Clone + Default + Collectable> {
+    spawn: Checkpoint<bool>,
+
+    /// Tid of the child, which is persisted before the spawn is checkpointed
+    tid: usize,
+    child: M,
+
+    /// Whether the child has returned, i.e. `output` is persisted
+    finished: bool,
+    output: O,
+}
+
+impl<M: Memento, O: Clone + Default + Collectable> Default for Fork<M, O> {
+    fn default() -> Self {
+        Self {
+            spawn: Default::default(),
+            tid: 0,
+            child: Default::default(),
+            finished: false,
+            output: Default::default(),
+        }
+    }
+}
+
+impl<M: Memento, O: Clone + Default + Collectable> Memento for Fork<M, O> {}
+
+impl<M: Memento, O: Clone + Default + Collectable> Collectable for Fork<M, O> {
+    fn filter(fork: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        // The memento of the child is traced with its tid, which recovers the CAS information of the tid.
+        let child_tid = if fork.tid == 0 { tid } else { fork.tid };
+
+        Checkpoint::filter(&mut fork.spawn, tid, gc, pool);
+        M::filter(&mut fork.child, child_tid, gc, pool);
+        if fork.finished {
+            O::filter(&mut fork.output, tid, gc, pool);
+        }
+    }
+}
+
+unsafe impl<M: Memento, O: Clone + Default + Collectable> Send for Fork<M, O> {}
+unsafe impl<M: Memento, O: Clone + Default + Collectable> Sync for Fork<M, O> {}
+
+impl<M: Memento, O: Clone + Default + Collectable> Fork<M, O> {
+    /// Run `child` with the memento of the child on another thread of `tid`, run `parent` meanwhile, and join
+    ///
+    /// Return the results of `parent` and `child`. The child has its own `Handle`, so it should not call
+    /// `Handle::repin_guard`, which is for root mementos. `PoolHandle::snapshot` does not stop the child.
+    ///
+    /// # Panics
+    ///
+    /// * Panics if `tid` is the tid of the parent or of a root memento, or exceeds the maximum number of threads
+    ///   of the pool. Once forked, `tid` is reserved for children, and `PoolHandle::add_memento` does not give it
+    ///   to a root memento.
+    /// * Panics if `tid` is used by another thread (e.g. the child of another fork) while the child is run.
+    /// * Panics with the payload of a panic of `child`. The parent resumes the child when it is run again
+    ///   (e.g. by `ExecConfig::retry`).
+    #[allow(box_pointers)]
+    pub fn fork_join<C, P, R>(&mut self, tid: usize, child: C, parent: P, handle: &Handle) -> (R, O)
+    where
+        C: Fn(&mut M, &Handle) -> O + Sync,
+        P: FnOnce() -> R,
+    {
+        let pool = handle.pool;
+        assert!(
+            tid != handle.tid && tid > pool.nr_memento() && tid <= pool.max_threads(),
+            "tid {tid} cannot be used by a child"
+        );
+        pool.reserve_fork_tid(tid);
+
+        let _ = self.spawn.checkpoint(
+            || {
+                self.tid = tid;
+                self.finished = false;
+                persist_obj(&self.tid, false);
+                persist_obj(&self.finished, true);
+                true
+            },
+            handle,
+        );
+        let resumed = handle.rec.load(Ordering::Relaxed);
+        let tid = self.tid;
+        assert!(
+            pool.try_acquire_tid(tid),
+            "tid {tid} is used by another thread"
+        );
+
+        let (fork, child) = (self as *mut Self as usize, &child);
+        let joined = thread::scope(|scope| {
+            // Spawn the child unless it had finished before a crash
+            let spawned = (!self.finished).then(|| {
+                scope.spawn(move |_| {
+                    let fork = unsafe { (fork as *mut Self).as_mut().unwrap() };
+                    let handle = Handle::new(tid, unsafe { pool.old_guard(tid) }, pool);
+                    handle.rec.store(resumed, Ordering::Relaxed);
+
+                    // A panic is caught to be raised by the parent. The handle is not dropped as if the thread crashed.
+                    #[cfg(not(feature = "tcrash"))]
+                    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                        child(&mut fork.child, &handle)
+                    }));
+                    #[cfg(feature = "tcrash")]
+                    let res: std::thread::Result<O> = Ok(child(&mut fork.child, &handle));
+                    match res {
+                        Ok(output) => {
+                            fork.output = output;
+                            persist_obj(&fork.output, true);
+                            fork.finished = true;
+                            persist_obj(&fork.finished, true);
+                            None
+                        }
+                        Err(payload) => {
+                            mem::forget(handle);
+                            Some(payload)
+                        }
+                    }
+                })
+            });
+
+            let res = parent();
+
+            // Join
+            let panic = spawned.and_then(|spawned| spawned.join().ok().flatten());
+            (res, panic)
+        });
+        pool.release_tid(tid);
+
+        let (res, panic) = joined.unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
+        assert!(self.finished, "child {tid} did not return");
+        (res, self.output.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pmem::RootObj;
+    use crate::test_utils::tests::*;
+
+    const NR_THREAD: usize = 2;
+    #[cfg(not(feature = "pmcheck"))]
+    const NR_COUNT: usize = 1_000;
+    #[cfg(feature = "pmcheck")]
+    const NR_COUNT: usize = 5;
+
+    struct Forks {
+        forks: [Fork<Checkpoint<TestValue>, TestValue>; NR_COUNT],
+    }
+
+    impl Memento for Forks {
+        fn clear(&mut self) {
+            for fork in &mut self.forks {
+                fork.clear();
+            }
+        }
+    }
+
+    impl Default for Forks {
+        fn default() -> Self {
+            Self {
+                forks: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Forks {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for fork in &mut m.forks {
+                Fork::filter(fork, tid, gc, pool);
+            }
+        }
+    }
+
+    // Each root memento forks children of tid `NR_THREAD + tid` one by one, and reports their outputs.
+    impl RootObj<Forks> for TestRootObj<DummyRootObj> {
+        fn run(&self, forks: &mut Forks, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+
+            for seq in 0..NR_COUNT {
+                let (parent, child) = forks.forks[seq].fork_join(
+                    NR_THREAD + handle.tid,
+                    |chk, child_handle| {
+                        chk.checkpoint(|| TestValue::new(handle.tid, seq), child_handle)
+                    },
+                    || seq,
+                    handle,
+                );
+                assert_eq!(parent, seq);
+                testee.report(seq, child);
+            }
+        }
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn fork_join() {
+        const FILE_NAME: &str = "fork";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<DummyRootObj>, Forks>(FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT);
+    }
+}
@@ -369,7 +369,7 @@ impl<N: Node + Collectable> SMOAtomic<N> {
         // defer_persist() does not break history in post-crash: if the next accessor is `Insert`, it will persist the location.
         // e.g. A --(defer per)--> B --(defer per)--> null --(per)--> C
         guard.defer_persist(&self.inner);
-        unsafe { guard.defer_pdestroy(old) }
+        unsafe { guard.defer_pdestroy(old, pool) }
 
         Ok(old)
     }
@@ -403,7 +403,7 @@ impl<N: Node + Collectable> SMOAtomic<N> {
             .inner
             .compare_exchange(old, new, Ordering::SeqCst, Ordering::SeqCst, guard);
         guard.defer_persist(&self.inner);
-        unsafe { guard.defer_pdestroy(old) };
+        unsafe { guard.defer_pdestroy(old, pool) };
         Some(true)
     }
 }
@@ -3,9 +3,19 @@
 pub mod checkpoint;
 pub mod common;
 pub mod detectable_cas;
+#[cfg(target_arch = "x86_64")]
+pub mod detectable_cas_u128;
+pub mod detectable_cas_u64;
+pub mod fork;
 pub mod insert_delete;
+pub mod mwcas;
 
 pub use checkpoint::*;
 pub use common::*;
 pub use detectable_cas::*;
+#[cfg(target_arch = "x86_64")]
+pub use detectable_cas_u128::*;
+pub use detectable_cas_u64::*;
+pub use fork::*;
 pub use insert_delete::*;
+pub use mwcas::*;
@@ -0,0 +1,556 @@
+//! Detectable multi-word CAS
+//!
+//! A multi-word CAS on `DetectableCASAtomic` locations allocates a descriptor in the pool and installs it in the
+//! locations in the order of their addresses, decides the status, and replaces the descriptor with the new (or old)
+//! values. An installed descriptor is tagged in the same way as a help descriptor but with the aux bit set, i.e.
+//! `[aux: 1 | desc: 1 | tid: owner | descriptor]`. A thread that finds a descriptor in a location (e.g. by a load or
+//! a CAS of `DetectableCASAtomic`) runs the multi-word CAS in place of its owner, as in PMwCAS: it installs the
+//! rest of the descriptor, decides the status by CAS, and replaces the descriptor in all the locations. Thus no
+//! thread waits for the owner (or its recovery after a crash).
+//!
+//! A descriptor is installed in two steps, as in RDCSS: the old value is replaced with the descriptor marked as
+//! pending for the location, which is replaced with the descriptor only if the status is not decided yet (or with
+//! the old value back otherwise). It prevents a late helper from installing a decided descriptor again in a
+//! location that has got back its old value. The mark is kept in the low bits of the descriptor pointer.
+//!
+//! The memento checkpoints the descriptor before installing it and the result after replacing it, so that the
+//! result is reported exactly once after a crash. The descriptor is retired once the result is checkpointed.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Checkpoint, DetectableCASAtomic, Handle};
+use crate::pepoch::{atomic::Pointer, PAtomic, PDestroyable, POwned, PShared};
+use crate::pmem::{
+    ll::persist_obj, sfence, AsPPtr, Collectable, GarbageCollection, PPtr, PoolHandle,
+};
+use crate::Memento;
+
+/// Maximum number of locations of a multi-word CAS
+pub const MWCAS_MAX_WORDS: usize = 4;
+
+const UNDECIDED: usize = 0;
+const SUCCEEDED: usize = 1;
+const FAILED: usize = 2;
+
+/// Low bits of a descriptor pointer marking the descriptor as pending for a location (index + 1, up to
+/// `MWCAS_MAX_WORDS`)
+const PENDING_MASK: usize = 0b111;
+const _: () = assert!(std::mem::align_of::<AtomicUsize>() > PENDING_MASK);
+
+/// Descriptor of a multi-word CAS
+#[derive(Debug)]
+pub(crate) struct MwcasDescriptor<N: Collectable> {
+    status: AtomicUsize,
+    len: usize,
+
+    /// Locations sorted by their addresses
+    words: [MwcasWord<N>; MWCAS_MAX_WORDS],
+}
+
+#[derive(Debug)]
+struct MwcasWord<N: Collectable> {
+    loc: PPtr<DetectableCASAtomic<N>>,
+    old: PAtomic<N>,
+    new: PAtomic<N>,
+}
+
+impl<N: Collectable> Default for MwcasWord<N> {
+    fn default() -> Self {
+        Self {
+            loc: PPtr::null(),
+            old: PAtomic::null(),
+            new: PAtomic::null(),
+        }
+    }
+}
+
+impl<N: Collectable> Collectable for MwcasDescriptor<N> {
+    fn filter(desc: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        // Locations are traced by their owners. Both the old and the new values may be reachable once the
+        // descriptor is replaced.
+        for word in &mut desc.words[..desc.len] {
+            PAtomic::filter(&mut word.old, tid, gc, pool);
+            PAtomic::filter(&mut word.new, tid, gc, pool);
+        }
+    }
+}
+
+impl<N: Collectable> MwcasDescriptor<N> {
+    fn new(
+        words: &[(&DetectableCASAtomic<N>, PShared<'_, N>, PShared<'_, N>)],
+        pool: &PoolHandle,
+    ) -> Self {
+        assert!(
+            !words.is_empty() && words.len() <= MWCAS_MAX_WORDS,
+            "{} locations cannot be CASed at once",
+            words.len()
+        );
+
+        let mut sorted = words.to_vec();
+        sorted.sort_by_key(|(loc, _, _)| *loc as *const DetectableCASAtomic<N>);
+        assert!(
+            sorted.windows(2).all(|w| !std::ptr::eq(w[0].0, w[1].0)),
+            "a location is CASed twice"
+        );
+
+        let mut desc = Self {
+            status: AtomicUsize::new(UNDECIDED),
+            len: sorted.len(),
+            words: Default::default(),
+        };
+        for (word, (loc, old, new)) in desc.words.iter_mut().zip(sorted) {
+            *word = MwcasWord {
+                loc: unsafe { loc.as_pptr(pool) },
+                old: PAtomic::from(old),
+                new: PAtomic::from(new),
+            };
+        }
+        desc
+    }
+
+    /// Install the descriptor, decide the status, and replace the descriptor, where `tagged` is the descriptor
+    /// installed in the locations
+    ///
+    /// It is run by the owner and by any thread that finds the descriptor in a location, and continues from
+    /// where it stopped before a crash.
+    fn run(&self, tagged: PShared<'_, N>, handle: &Handle) -> bool {
+        // 1. Install
+        if self.status.load(Ordering::SeqCst) == UNDECIDED {
+            let installed = (0..self.len).all(|i| self.install(i, tagged, handle));
+            sfence();
+
+            // 2. Decide
+            let status = if installed { SUCCEEDED } else { FAILED };
+            let _ =
+                self.status
+                    .compare_exchange(UNDECIDED, status, Ordering::SeqCst, Ordering::SeqCst);
+        }
+
+        // Persist the status before the locations are replaced
+        let succ = self.status.load(Ordering::SeqCst) == SUCCEEDED;
+        persist_obj(&self.status, true);
+
+        // 3. Replace
+        for i in 0..self.len {
+            self.replace(i, tagged, succ, handle);
+        }
+        sfence();
+        succ
+    }
+
+    /// Install the descriptor in the location of the `i`th word, unless the status is decided
+    ///
+    /// Return false if the location does not hold the old value. The location is persisted if the descriptor is
+    /// installed.
+    fn install(&self, i: usize, tagged: PShared<'_, N>, handle: &Handle) -> bool {
+        let (pool, guard) = (handle.pool, &handle.guard);
+        let word = &self.words[i];
+        let loc = unsafe { word.loc.deref(pool) };
+        let old = word.old.load(Ordering::Relaxed, guard);
+        let pending = pending(tagged, i);
+
+        loop {
+            if self.status.load(Ordering::SeqCst) != UNDECIDED {
+                // Decided by another thread, so the result is not used.
+                return false;
+            }
+
+            let cur = match loc.inner.compare_exchange(
+                old,
+                pending,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                guard,
+            ) {
+                Ok(_) => pending,
+                Err(e) => e.current,
+            };
+
+            if cur == tagged {
+                // Installed by another thread (or before a crash), which may not have persisted it yet
+                persist_obj(&loc.inner, false);
+                return true;
+            }
+            if cur == pending {
+                self.complete(i, tagged, handle);
+                continue;
+            }
+            if loc.load(Ordering::SeqCst, handle) != old {
+                return false;
+            }
+        }
+    }
+
+    /// Replace the descriptor pending for the location of the `i`th word with the descriptor if the status is not
+    /// decided, or with the old value otherwise
+    fn complete(&self, i: usize, tagged: PShared<'_, N>, handle: &Handle) {
+        let (pool, guard) = (handle.pool, &handle.guard);
+        let word = &self.words[i];
+        let loc = unsafe { word.loc.deref(pool) };
+
+        let val = if self.status.load(Ordering::SeqCst) == UNDECIDED {
+            tagged
+        } else {
+            persist_obj(&self.status, true);
+            word.old.load(Ordering::Relaxed, guard)
+        };
+        let _ = loc.inner.compare_exchange(
+            pending(tagged, i),
+            val,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+            guard,
+        );
+        persist_obj(&loc.inner, false);
+    }
+
+    /// Replace the descriptor in the location of the `i`th word with its new value if `succ`, or old value
+    /// otherwise, after the status is decided
+    fn replace(&self, i: usize, tagged: PShared<'_, N>, succ: bool, handle: &Handle) {
+        let (pool, guard) = (handle.pool, &handle.guard);
+        let word = &self.words[i];
+        let loc = unsafe { word.loc.deref(pool) };
+        let val = if succ { &word.new } else { &word.old };
+        let val = val.load(Ordering::Relaxed, guard);
+
+        // The descriptor may still be pending for the location if a helper has stopped (e.g. by a crash) before
+        // it is installed. It is not installed any more.
+        self.complete(i, tagged, handle);
+        let _ = loc
+            .inner
+            .compare_exchange(tagged, val, Ordering::SeqCst, Ordering::SeqCst, guard);
+        persist_obj(&loc.inner, false);
+    }
+}
+
+/// `tagged` marked as pending for the location of the `i`th word
+#[inline]
+fn pending<N>(tagged: PShared<'_, N>, i: usize) -> PShared<'_, N> {
+    unsafe { PShared::from_usize(tagged.into_usize() | (i + 1)) }
+}
+
+/// Whether `word` is a descriptor of a multi-word CAS, installed or pending
+#[inline]
+pub(crate) fn is_mwcas<N>(word: PShared<'_, N>) -> bool {
+    word.aux_bit() == 1 && word.desc_bit() == 1
+}
+
+/// Pointer to the descriptor of a multi-word CAS found in a location as `word`
+///
+/// # Safety
+///
+/// `word` should be a descriptor of a multi-word CAS (`is_mwcas`).
+#[inline]
+pub(crate) unsafe fn mwcas_descriptor_ptr<N: Collectable>(
+    word: PShared<'_, N>,
+) -> PShared<'_, MwcasDescriptor<N>> {
+    let ptr = word
+        .with_aux_bit(0)
+        .with_desc_bit(0)
+        .with_tid(0)
+        .into_usize()
+        & !PENDING_MASK;
+    PShared::from_usize(ptr)
+}
+
+/// Help the multi-word CAS of which descriptor is found in `loc` as `word`, and return the current value of `loc`
+///
+/// It runs the multi-word CAS to the end, deciding its status if it is not decided yet.
+pub(crate) fn help_mwcas<'g, N: Collectable>(
+    loc: &DetectableCASAtomic<N>,
+    word: PShared<'_, N>,
+    handle: &'g Handle,
+) -> PShared<'g, N> {
+    let tagged = unsafe { PShared::<N>::from_usize(word.into_usize() & !PENDING_MASK) };
+    let desc = unsafe { mwcas_descriptor_ptr(word).deref(handle.pool) };
+    let _ = desc.run(tagged, handle);
+    loc.inner.load(Ordering::SeqCst, &handle.guard)
+}
+
+/// Progress of a multi-word CAS: its descriptor, and its result once the descriptor is replaced
+#[derive(Debug)]
+struct MwcasProgress<N: Collectable> {
+    desc: PAtomic<MwcasDescriptor<N>>,
+    result: Option<bool>,
+}
+
+impl<N: Collectable> Default for MwcasProgress<N> {
+    fn default() -> Self {
+        Self {
+            desc: PAtomic::null(),
+            result: None,
+        }
+    }
+}
+
+impl<N: Collectable> Clone for MwcasProgress<N> {
+    fn clone(&self) -> Self {
+        Self {
+            desc: self.desc.clone(),
+            result: self.result,
+        }
+    }
+}
+
+impl<N: Collectable> Collectable for MwcasProgress<N> {
+    fn filter(progress: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        // The descriptor is retired once the result is checkpointed.
+        if progress.result.is_none() {
+            PAtomic::filter(&mut progress.desc, tid, gc, pool);
+        }
+    }
+}
+
+/// Multi-word Compare and Set memento
+#[derive(Debug)]
+pub struct Mwcas<N: Collectable> {
+    progress: Checkpoint<MwcasProgress<N>>,
+}
+
+impl<N: Collectable> Default for Mwcas<N> {
+    fn default() -> Self {
+        Self {
+            progress: Default::default(),
+        }
+    }
+}
+
+impl<N: Collectable> Memento for Mwcas<N> {
+    #[inline]
+    fn clear(&mut self) {
+        self.progress.clear();
+    }
+}
+
+impl<N: Collectable> Collectable for Mwcas<N> {
+    fn filter(mmt: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        Checkpoint::filter(&mut mmt.progress, tid, gc, pool);
+    }
+}
+
+impl<N: Collectable> DetectableCASAtomic<N> {
+    /// Multi-word Compare And Set
+    ///
+    /// Set each location of `words` (location, old, new) to its new value if all of them are their old values.
+    /// Return whether they are set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `words` is empty, has more than `MWCAS_MAX_WORDS` locations, or has a location twice.
+    pub fn mwcas(
+        words: &[(&Self, PShared<'_, N>, PShared<'_, N>)],
+        mmt: &mut Mwcas<N>,
+        handle: &Handle,
+    ) -> bool {
+        let (tid, pool, guard) = (handle.tid, handle.pool, &handle.guard);
+
+        let progress = mmt.progress.checkpoint(
+            || {
+                let desc = POwned::new(MwcasDescriptor::new(words, pool), pool);
+                persist_obj(unsafe { desc.deref(pool) }, true);
+                MwcasProgress {
+                    desc: PAtomic::from(desc),
+                    result: None,
+                }
+            },
+            handle,
+        );
+        if let Some(res) = progress.result {
+            return res;
+        }
+
+        let desc = progress.desc.load(Ordering::Relaxed, guard);
+        let tagged = unsafe { PShared::<N>::from_usize(desc.into_usize()) }
+            .with_aux_bit(1)
+            .with_desc_bit(1)
+            .with_tid(tid);
+        let res = unsafe { desc.deref(pool) }.run(tagged, handle);
+
+        let _ = mmt.progress.checkpoint(
+            || MwcasProgress {
+                desc: PAtomic::null(),
+                result: Some(res),
+            },
+            handle,
+        );
+        unsafe { guard.defer_pdestroy(desc, pool) };
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ploc::detectable_cas::test::Node;
+    use crate::pmem::RootObj;
+    use crate::test_utils::tests::*;
+    use crate::PDefault;
+
+    const NR_THREAD: usize = 3;
+    #[cfg(not(feature = "pmcheck"))]
+    const NR_COUNT: usize = 10_000;
+    #[cfg(feature = "pmcheck")]
+    const NR_COUNT: usize = 10;
+
+    /// Two locations which are null or hold the same node
+    #[derive(Debug, Default)]
+    struct Pair {
+        locs: [DetectableCASAtomic<Node<TestValue>>; 2],
+    }
+
+    impl Collectable for Pair {
+        fn filter(pair: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for loc in &mut pair.locs {
+                DetectableCASAtomic::filter(loc, tid, gc, pool);
+            }
+        }
+    }
+
+    impl PDefault for Pair {
+        fn pdefault(_: &Handle) -> Self {
+            Default::default()
+        }
+    }
+
+    struct Updates {
+        nodes: [Checkpoint<PAtomic<Node<TestValue>>>; NR_COUNT],
+        upds: [(
+            Mwcas<Node<TestValue>>,
+            Checkpoint<PAtomic<Node<TestValue>>>,
+            Mwcas<Node<TestValue>>,
+        ); NR_COUNT],
+    }
+
+    impl Memento for Updates {
+        fn clear(&mut self) {
+            for i in 0..NR_COUNT {
+                self.nodes[i].clear();
+                self.upds[i].0.clear();
+                self.upds[i].1.clear();
+                self.upds[i].2.clear();
+            }
+        }
+    }
+
+    impl Default for Updates {
+        fn default() -> Self {
+            Self {
+                nodes: array_init::array_init(|_| Default::default()),
+                upds: array_init::array_init(|_| Default::default()),
+            }
+        }
+    }
+
+    impl Collectable for Updates {
+        fn filter(m: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+            for i in 0..NR_COUNT {
+                Collectable::filter(&mut m.nodes[i], tid, gc, pool);
+                Collectable::filter(&mut m.upds[i], tid, gc, pool);
+            }
+        }
+    }
+
+    // Each thread sets both locations from null to its node, and sets them back to null to get the node.
+    impl RootObj<Updates> for TestRootObj<Pair> {
+        fn run(&self, mmt: &mut Updates, handle: &Handle) {
+            let testee = unsafe { TESTER.as_ref().unwrap().testee(true, handle) };
+            let [a, b] = &self.obj.locs;
+
+            for seq in 0..NR_COUNT {
+                let node = mmt.nodes[seq]
+                    .checkpoint(
+                        || {
+                            let node = POwned::new(
+                                Node {
+                                    data: TestValue::new(handle.tid, seq),
+                                },
+                                handle.pool,
+                            );
+                            persist_obj(unsafe { node.deref(handle.pool) }, true);
+                            PAtomic::from(node)
+                        },
+                        handle,
+                    )
+                    .load(Ordering::Relaxed, &handle.guard);
+
+                let (set, old, unset) = &mut mmt.upds[seq];
+                let null = PShared::null();
+                while !DetectableCASAtomic::mwcas(&[(a, null, node), (b, null, node)], set, handle)
+                {
+                }
+
+                // Only the thread that has set the locations sets them back.
+                let old = old
+                    .checkpoint(|| PAtomic::from(b.load(Ordering::SeqCst, handle)), handle)
+                    .load(Ordering::Relaxed, &handle.guard);
+                assert_eq!(a.load(Ordering::SeqCst, handle), old);
+                assert!(DetectableCASAtomic::mwcas(
+                    &[(b, old, null), (a, old, null)],
+                    unset,
+                    handle
+                ));
+
+                let val = unsafe { std::ptr::read(&old.deref(handle.pool).data) };
+                testee.report(seq, val);
+            }
+        }
+    }
+
+    // A multi-word CAS whose owner has stopped after installing its descriptor in a location is finished by a load
+    // of the location.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn help_undecided() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let pool = get_dummy_handle(FILE_SIZE).unwrap();
+        let handle = Handle::new(1, pool.pin(), pool);
+        let guard = &handle.guard;
+
+        let pair = pool.alloc::<Pair>();
+        let pair = unsafe {
+            std::ptr::write(pair.deref_mut(pool), Pair::default());
+            pair.deref(pool)
+        };
+        let [a, b] = &pair.locs;
+        let null = PShared::null();
+        let node = POwned::new(
+            Node {
+                data: TestValue::new(1, 0),
+            },
+            pool,
+        )
+        .into_shared(guard);
+
+        let desc = POwned::new(
+            MwcasDescriptor::new(&[(a, null, node), (b, null, node)], pool),
+            pool,
+        )
+        .into_shared(guard);
+        let tagged = unsafe { PShared::<Node<TestValue>>::from_usize(desc.into_usize()) }
+            .with_aux_bit(1)
+            .with_desc_bit(1)
+            .with_tid(2);
+        let desc = unsafe { desc.deref(pool) };
+        assert!(desc.install(0, tagged, &handle));
+        assert_eq!(b.load(Ordering::SeqCst, &handle), null);
+
+        assert_eq!(a.load(Ordering::SeqCst, &handle), node);
+        assert_eq!(desc.status.load(Ordering::SeqCst), SUCCEEDED);
+        assert_eq!(b.load(Ordering::SeqCst, &handle), node);
+
+        // The owner finds that the multi-word CAS has succeeded.
+        assert!(desc.run(tagged, &handle));
+        assert_eq!(a.inner.load(Ordering::SeqCst, guard), node);
+        assert_eq!(b.inner.load(Ordering::SeqCst, guard), node);
+    }
+
+    // We should enlarge stack size for the test (e.g. `RUST_MIN_STACK=1073741824 cargo test`)
+    #[test]
+    fn mwcas() {
+        const FILE_NAME: &str = "mwcas";
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        run_test::<TestRootObj<Pair>, Updates>(FILE_NAME, FILE_SIZE, NR_THREAD, NR_COUNT);
+    }
+}
@@ -0,0 +1,811 @@
+//! Pure-Rust allocator over a memory-mapped file
+//!
+//! # Layout of pool file
+//!
+//! ```text
+//! | header (magic, size, root table) | chunk metadata | chunk 0 | chunk 1 | ... |
+//! ```
+//!
+//! - The heap is divided into chunks of `CHUNK_SIZE`. A chunk holds blocks of one size class,
+//!   or is a part of a large block spanning several chunks.
+//! - Only the root table and the metadata of chunks are persistent. Free lists live in DRAM and
+//!   are rebuilt by mark-and-sweep whenever the pool is opened (`recover`), unless they were kept
+//!   in a large block by a clean shutdown.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::{fmt, mem, ptr, slice};
+
+use etrace::some_or;
+use libc::{c_char, c_int, c_ulong, c_void};
+
+use super::{write_snapshot, Collectable, PAllocator};
+use crate::pmem::ll::{persist, persist_obj};
+use crate::pmem::pool::NR_ROOTS;
+use crate::pmem::{ClassStats, PoolHandle, PoolStats, RecoveryStats, RootIdx};
+
+/// Magic number of pool file ("MMAPPOOL")
+const MAGIC: u64 = u64::from_le_bytes(*b"MMAPPOOL");
+
+const PAGE_SIZE: usize = 4096;
+
+/// Size of chunk
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Size of the smallest size class (cache line)
+const MIN_BLOCK_SIZE: usize = 64;
+
+/// Size of the largest size class. Larger blocks take contiguous chunks.
+const MAX_BLOCK_SIZE: usize = CHUNK_SIZE / 2;
+
+/// Size classes are powers of two from `MIN_BLOCK_SIZE` to `MAX_BLOCK_SIZE`,
+/// so that a block is aligned to its size.
+const NR_CLASSES: usize = (MAX_BLOCK_SIZE / MIN_BLOCK_SIZE).trailing_zeros() as usize + 1;
+
+/// Maximum size of pool (1TB, same as Ralloc)
+const MAX_POOL_SIZE: usize = 1 << 40;
+
+/// Offset of chunk metadata. The metadata is sized for `MAX_POOL_SIZE` so that the pool can grow.
+const META_OFFSET: usize = align_up(mem::size_of::<Header>(), PAGE_SIZE);
+
+/// Offset of the first chunk
+const HEAP_OFFSET: usize = align_up(
+    META_OFFSET + MAX_POOL_SIZE / CHUNK_SIZE * mem::size_of::<ChunkMeta>(),
+    CHUNK_SIZE,
+);
+
+/// Minimum size of pool
+const MIN_POOL_SIZE: usize = HEAP_OFFSET + 8 * CHUNK_SIZE;
+
+// Classes of chunk. Small size classes are `1..=NR_CLASSES`.
+const CHUNK_FREE: u32 = 0;
+const CHUNK_LARGE: u32 = u32::MAX;
+const CHUNK_CONT: u32 = u32::MAX - 1;
+
+const fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) / align * align
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    size: AtomicU64,
+
+    /// Number of chunks ever used
+    nr_chunks: AtomicU64,
+
+    /// Number of live blocks
+    ///
+    /// It is not persisted on every update, so it is exact only after a process crash. It is used only
+    /// for statistics of recovery.
+    live_blocks: AtomicU64,
+
+    /// Offsets of roots (0 if not set)
+    roots: [AtomicU64; NR_ROOTS],
+
+    /// Offset of the free lists kept by a clean shutdown (0 if not kept)
+    kept: AtomicU64,
+}
+
+/// Persistent metadata of a chunk
+///
+/// - Small: `class` is the size class, `len` is 1
+/// - Large: `class` is `CHUNK_LARGE`, `len` is the number of chunks of the block
+/// - Continuation of large: `class` is `CHUNK_CONT`, `len` is the distance from the first chunk
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ChunkMeta {
+    class: u32,
+    len: u32,
+}
+
+type RootFilter = unsafe extern "C" fn(*mut c_char, usize, &mut GarbageCollection);
+
+/// Marking state of the recovery-time mark-and-sweep
+pub struct GarbageCollection {
+    pool: *mut PoolHandle,
+
+    /// Offsets of reachable blocks
+    marked: HashSet<usize>,
+
+    /// Marked but not yet filtered objects
+    pending: Vec<(*mut c_char, usize, RootFilter)>,
+}
+
+pub(crate) struct MmapAllocator {
+    base: *mut u8,
+    len: usize,
+
+    /// Number of chunks that fit in the pool
+    capacity: usize,
+
+    /// Free blocks of each size class (offsets)
+    free_blocks: Vec<Mutex<Vec<usize>>>,
+
+    /// Free chunks (indices)
+    free_chunks: Mutex<BTreeSet<usize>>,
+
+    filters: Mutex<Vec<Option<RootFilter>>>,
+
+    /// Result of the last recovery
+    last_gc: Mutex<Option<RecoveryStats>>,
+}
+
+unsafe impl Send for MmapAllocator {}
+unsafe impl Sync for MmapAllocator {}
+
+// `RootFilter` does not implement `Debug`, so implement it manually.
+impl fmt::Debug for GarbageCollection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GarbageCollection")
+            .field("marked", &self.marked.len())
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for MmapAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapAllocator")
+            .field("base", &self.base)
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MmapAllocator {
+    unsafe fn map(file: &File, filesize: usize) -> Result<Self, Error> {
+        let base = libc::mmap(
+            ptr::null_mut(),
+            filesize,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        if base == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Self {
+            base: base as *mut u8,
+            len: filesize,
+            capacity: (filesize - HEAP_OFFSET) / CHUNK_SIZE,
+            free_blocks: (0..NR_CLASSES).map(|_| Mutex::new(Vec::new())).collect(),
+            free_chunks: Mutex::new(BTreeSet::new()),
+            filters: Mutex::new(vec![None; NR_ROOTS]),
+            last_gc: Mutex::new(None),
+        })
+    }
+
+    fn check_size(filesize: usize) -> Result<(), Error> {
+        if !(MIN_POOL_SIZE..=MAX_POOL_SIZE).contains(&filesize) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Pool size should be in {MIN_POOL_SIZE}..={MAX_POOL_SIZE}."),
+            ));
+        }
+        Ok(())
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.base as *const Header) }
+    }
+
+    fn meta(&self, chunk: usize) -> *mut ChunkMeta {
+        unsafe { (self.base.add(META_OFFSET) as *mut ChunkMeta).add(chunk) }
+    }
+
+    fn chunk_offset(chunk: usize) -> usize {
+        HEAP_OFFSET + chunk * CHUNK_SIZE
+    }
+
+    /// Size class of `sz`, or `None` if it needs a large block
+    fn size_class(sz: usize) -> Option<u32> {
+        if sz > MAX_BLOCK_SIZE {
+            return None;
+        }
+        let sz = sz.max(MIN_BLOCK_SIZE).next_power_of_two();
+        Some((sz / MIN_BLOCK_SIZE).trailing_zeros() + 1)
+    }
+
+    fn block_size(class: u32) -> usize {
+        MIN_BLOCK_SIZE << (class - 1)
+    }
+
+    /// Offset of the block containing `off`, or `None` if `off` is not in an allocated chunk
+    fn block_start(&self, off: usize) -> Option<usize> {
+        if off < HEAP_OFFSET || off >= self.len {
+            return None;
+        }
+        let chunk = (off - HEAP_OFFSET) / CHUNK_SIZE;
+        let meta = unsafe { *self.meta(chunk) };
+        match meta.class {
+            CHUNK_FREE => None,
+            CHUNK_LARGE => Some(Self::chunk_offset(chunk)),
+            CHUNK_CONT => Some(Self::chunk_offset(chunk - meta.len as usize)),
+            class => {
+                let start = Self::chunk_offset(chunk);
+                let bsize = Self::block_size(class);
+                Some(start + (off - start) / bsize * bsize)
+            }
+        }
+    }
+
+    /// Take `n` contiguous chunks for `class` and return the index of the first one.
+    fn alloc_chunks(&self, n: usize, class: u32) -> Option<usize> {
+        let mut free = self.free_chunks.lock().unwrap();
+        let header = self.header();
+        let nr_chunks = header.nr_chunks.load(Ordering::Relaxed) as usize;
+
+        // Reuse free chunks first (first-fit), otherwise take chunks never used
+        let mut run = (0, 0);
+        for &chunk in free.iter() {
+            run = if run.1 > 0 && run.0 + run.1 == chunk {
+                (run.0, run.1 + 1)
+            } else {
+                (chunk, 1)
+            };
+            if run.1 == n {
+                break;
+            }
+        }
+        let first = if run.1 == n {
+            for chunk in run.0..run.0 + n {
+                let _ = free.remove(&chunk);
+            }
+            run.0
+        } else if nr_chunks + n <= self.capacity {
+            nr_chunks
+        } else {
+            return None;
+        };
+
+        // Record metadata before the chunks are used
+        for i in 0..n {
+            let meta = if i == 0 {
+                ChunkMeta {
+                    class,
+                    len: n as u32,
+                }
+            } else {
+                ChunkMeta {
+                    class: CHUNK_CONT,
+                    len: i as u32,
+                }
+            };
+            unsafe { self.meta(first + i).write(meta) };
+        }
+        persist(self.meta(first), n * mem::size_of::<ChunkMeta>(), true);
+
+        if first + n > nr_chunks {
+            header
+                .nr_chunks
+                .store((first + n) as u64, Ordering::Relaxed);
+            persist_obj(&header.nr_chunks, true);
+        }
+        Some(first)
+    }
+
+    fn alloc_block(&self, class: u32) -> Option<usize> {
+        let mut free = self.free_blocks[class as usize - 1].lock().unwrap();
+        if let Some(off) = free.pop() {
+            return Some(off);
+        }
+
+        // Carve a new chunk into blocks
+        let chunk = self.alloc_chunks(1, class)?;
+        let start = Self::chunk_offset(chunk);
+        let bsize = Self::block_size(class);
+        free.extend((1..CHUNK_SIZE / bsize).rev().map(|i| start + i * bsize));
+        Some(start)
+    }
+
+    /// Rebuild free lists with the blocks not marked as reachable
+    fn sweep(&self, marked: &HashSet<usize>) {
+        let nr_chunks = self.header().nr_chunks.load(Ordering::Relaxed) as usize;
+        let mut free_chunks = BTreeSet::new();
+        let mut free_blocks = vec![Vec::new(); NR_CLASSES];
+
+        for chunk in 0..nr_chunks {
+            let meta = unsafe { *self.meta(chunk) };
+            let start = Self::chunk_offset(chunk);
+            let is_free = match meta.class {
+                CHUNK_FREE => true,
+                CHUNK_LARGE => !marked.contains(&start),
+                CHUNK_CONT => {
+                    // Alive only if it still belongs to a reachable large block
+                    let head = chunk - meta.len as usize;
+                    let head_meta = unsafe { *self.meta(head) };
+                    !(head_meta.class == CHUNK_LARGE
+                        && head_meta.len > meta.len
+                        && marked.contains(&Self::chunk_offset(head)))
+                }
+                class => {
+                    let bsize = Self::block_size(class);
+                    let blocks = (0..CHUNK_SIZE / bsize)
+                        .map(|i| start + i * bsize)
+                        .filter(|off| !marked.contains(off))
+                        .collect::<Vec<_>>();
+                    if blocks.len() == CHUNK_SIZE / bsize {
+                        true
+                    } else {
+                        free_blocks[class as usize - 1].extend(blocks.into_iter().rev());
+                        false
+                    }
+                }
+            };
+            if is_free {
+                let _ = free_chunks.insert(chunk);
+            }
+        }
+
+        *self.free_chunks.lock().unwrap() = free_chunks;
+        for (list, blocks) in self.free_blocks.iter().zip(free_blocks) {
+            *list.lock().unwrap() = blocks;
+        }
+    }
+}
+
+impl PAllocator for MmapAllocator {
+    unsafe fn open(filepath: *const c_char, filesize: u64) -> Result<Self, Error> {
+        let filesize = filesize as usize;
+        let path = std::ffi::CStr::from_ptr(filepath).to_str().unwrap();
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() != filesize as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Pool should be opened with its size.",
+            ));
+        }
+
+        let alloc = Self::map(&file, filesize)?;
+        let header = alloc.header();
+        if header.magic.load(Ordering::Relaxed) != MAGIC
+            || header.size.load(Ordering::Relaxed) != filesize as u64
+        {
+            alloc.close(alloc.mmapped_addr(), filesize);
+            return Err(Error::new(ErrorKind::InvalidData, "Not a pool file."));
+        }
+        Ok(alloc)
+    }
+
+    unsafe fn create(filepath: *const c_char, filesize: u64) -> Result<Self, Error> {
+        let filesize = filesize as usize;
+        Self::check_size(filesize)?;
+
+        let path = std::ffi::CStr::from_ptr(filepath).to_str().unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.set_len(filesize as u64)?;
+
+        // The file is zero-filled, so only size and magic need to be written. Magic is written last.
+        let alloc = Self::map(&file, filesize)?;
+        let header = alloc.header();
+        header.size.store(filesize as u64, Ordering::Relaxed);
+        persist_obj(&header.size, true);
+        header.magic.store(MAGIC, Ordering::Relaxed);
+        persist_obj(&header.magic, true);
+        Ok(alloc)
+    }
+
+    unsafe fn grow(filepath: *const c_char, filesize: u64) -> Result<(), Error> {
+        Self::check_size(filesize as usize)?;
+
+        let path = std::ffi::CStr::from_ptr(filepath).to_str().unwrap();
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut buf = [0u8; 16];
+        file.read_exact_at(&mut buf, 0)?;
+        let (magic, size) = buf.split_at(8);
+        if u64::from_le_bytes(magic.try_into().unwrap()) != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a pool file."));
+        }
+        if u64::from_le_bytes(size.try_into().unwrap()) > filesize {
+            return Err(Error::new(ErrorKind::InvalidInput, "Pool cannot shrink."));
+        }
+
+        // Extend the file before recording its size
+        file.set_len(filesize)?;
+        file.sync_all()?;
+        file.write_all_at(&filesize.to_le_bytes(), 8)?;
+        file.sync_all()
+    }
+
+    fn mmapped_addr(&self) -> usize {
+        self.base as usize
+    }
+
+    unsafe fn close(&self, start: usize, len: usize) {
+        let _ = libc::msync(self.base as *mut c_void, self.len, libc::MS_SYNC);
+        let _ = libc::munmap(self.base as *mut c_void, self.len);
+    }
+
+    unsafe fn recover(pool: &mut PoolHandle) -> c_int {
+        let pool_ptr = pool as *mut PoolHandle;
+        let alloc = &(*pool_ptr).allocator;
+
+        // Free lists kept by a clean shutdown are stale, and their block is reclaimed by the sweep.
+        let header = alloc.header();
+        if header.kept.load(Ordering::Relaxed) != 0 {
+            header.kept.store(0, Ordering::Relaxed);
+            persist_obj(&header.kept, true);
+        }
+
+        let mut gc = GarbageCollection {
+            pool: pool_ptr,
+            marked: HashSet::new(),
+            pending: Vec::new(),
+        };
+
+        // Mark
+        let filters = alloc.filters.lock().unwrap().clone();
+        for (i, filter) in filters.iter().enumerate() {
+            let root = alloc.get_root(i as u64);
+            if root.is_null() {
+                continue;
+            }
+            match filter {
+                Some(filter) => filter(root as *mut c_char, i, &mut gc),
+                // Like Ralloc, a root without filter is still reachable itself
+                None => {
+                    let block = alloc.block_start(root as usize - alloc.base as usize);
+                    let _ = gc.marked.insert(block.unwrap());
+                }
+            }
+        }
+        while let Some((ptr, tid, filter)) = gc.pending.pop() {
+            filter(ptr, tid, &mut gc);
+        }
+
+        // Sweep
+        alloc.sweep(&gc.marked);
+
+        let header = alloc.header();
+        let reachable = gc.marked.len();
+        let live_before = header.live_blocks.swap(reachable as u64, Ordering::Relaxed);
+        *alloc.last_gc.lock().unwrap() = Some(RecoveryStats {
+            reachable,
+            reclaimed: (live_before as usize).saturating_sub(reachable),
+        });
+        1
+    }
+
+    unsafe fn keep_for_shutdown(&self) -> bool {
+        // Each list is written as its length and entries: free chunks, and then free blocks of each size class.
+        // Taking chunks for them does not lengthen the lists.
+        let len = 1
+            + self.free_chunks.lock().unwrap().len()
+            + self
+                .free_blocks
+                .iter()
+                .map(|list| 1 + list.lock().unwrap().len())
+                .sum::<usize>();
+        let n = align_up(len * mem::size_of::<u64>(), CHUNK_SIZE) / CHUNK_SIZE;
+        let chunk = some_or!(self.alloc_chunks(n, CHUNK_LARGE), return false);
+
+        let mut kept = Vec::with_capacity(len);
+        {
+            let free_chunks = self.free_chunks.lock().unwrap();
+            kept.push(free_chunks.len() as u64);
+            kept.extend(free_chunks.iter().map(|&chunk| chunk as u64));
+        }
+        for list in &self.free_blocks {
+            let list = list.lock().unwrap();
+            kept.push(list.len() as u64);
+            kept.extend(list.iter().map(|&off| off as u64));
+        }
+        let off = Self::chunk_offset(chunk);
+        let dst = self.base.add(off) as *mut u64;
+        ptr::copy_nonoverlapping(kept.as_ptr(), dst, kept.len());
+        persist(dst, kept.len() * mem::size_of::<u64>(), true);
+
+        let header = self.header();
+        persist_obj(&header.live_blocks, false);
+        header.kept.store(off as u64, Ordering::Relaxed);
+        persist_obj(&header.kept, true);
+        true
+    }
+
+    unsafe fn restore_after_shutdown(&self) -> bool {
+        let header = self.header();
+        let off = header.kept.load(Ordering::Relaxed) as usize;
+        if off == 0 {
+            return false;
+        }
+
+        let mut next = self.base.add(off) as *const u64;
+        let mut take = || {
+            let len = *next as usize;
+            let list = slice::from_raw_parts(next.add(1), len);
+            next = next.add(1 + len);
+            list.iter().map(|&entry| entry as usize)
+        };
+        let mut free_chunks = take().collect::<BTreeSet<_>>();
+        for list in &self.free_blocks {
+            *list.lock().unwrap() = take().collect();
+        }
+
+        // Free the chunks of the lists
+        header.kept.store(0, Ordering::Relaxed);
+        persist_obj(&header.kept, true);
+        let chunk = (off - HEAP_OFFSET) / CHUNK_SIZE;
+        let meta = *self.meta(chunk);
+        self.meta(chunk).write(ChunkMeta {
+            class: CHUNK_FREE,
+            len: 0,
+        });
+        persist_obj(&*self.meta(chunk), true);
+        free_chunks.extend(chunk..chunk + meta.len as usize);
+        *self.free_chunks.lock().unwrap() = free_chunks;
+        true
+    }
+
+    fn exists(filepath: &str) -> bool {
+        Path::new(filepath).exists()
+    }
+
+    fn remove(filepath: &str) -> Result<(), Error> {
+        let _ = fs::remove_file(filepath);
+        Ok(())
+    }
+
+    fn copy(from: &str, to: &str) -> Result<(), Error> {
+        let _ = fs::copy(from, to)?;
+        Ok(())
+    }
+
+    unsafe fn snapshot(&self, to: &str, last: Range<usize>) -> Result<(), Error> {
+        // Free lists are rebuilt whenever the pool is opened, so the mapping is all.
+        let bytes = slice::from_raw_parts(self.base, self.len);
+        write_snapshot(&[(to.to_owned(), self.base as usize, bytes)], last)
+    }
+
+    unsafe fn set_root(&self, ptr: *mut c_void, i: u64) -> *mut c_void {
+        let off = if ptr.is_null() {
+            0
+        } else {
+            ptr as usize - self.base as usize
+        };
+        let root = &self.header().roots[i as usize];
+        let old = root.swap(off as u64, Ordering::SeqCst);
+        persist_obj(root, true);
+
+        if old == 0 {
+            ptr::null_mut()
+        } else {
+            self.base.add(old as usize) as *mut c_void
+        }
+    }
+
+    unsafe fn get_root(&self, i: u64) -> *mut c_void {
+        let off = self.header().roots[i as usize].load(Ordering::SeqCst);
+        if off == 0 {
+            ptr::null_mut()
+        } else {
+            self.base.add(off as usize) as *mut c_void
+        }
+    }
+
+    unsafe fn malloc(&self, sz: c_ulong) -> *mut c_void {
+        let sz = sz as usize;
+        let off = match Self::size_class(sz) {
+            Some(class) => self.alloc_block(class),
+            None => self
+                .alloc_chunks(align_up(sz, CHUNK_SIZE) / CHUNK_SIZE, CHUNK_LARGE)
+                .map(Self::chunk_offset),
+        };
+        let off = some_or!(off, return ptr::null_mut());
+        let _ = self.header().live_blocks.fetch_add(1, Ordering::Relaxed);
+        self.base.add(off) as *mut c_void
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void, _len: usize) {
+        let off = ptr as usize - self.base as usize;
+        let chunk = (off - HEAP_OFFSET) / CHUNK_SIZE;
+        let meta = *self.meta(chunk);
+        match meta.class {
+            CHUNK_LARGE => {
+                let mut free = self.free_chunks.lock().unwrap();
+                self.meta(chunk).write(ChunkMeta {
+                    class: CHUNK_FREE,
+                    len: 0,
+                });
+                persist_obj(&*self.meta(chunk), true);
+                free.extend(chunk..chunk + meta.len as usize);
+            }
+            CHUNK_FREE | CHUNK_CONT => panic!("free of a block not allocated: {off}"),
+            class => self.free_blocks[class as usize - 1]
+                .lock()
+                .unwrap()
+                .push(self.block_start(off).unwrap()),
+        }
+        let _ = self.header().live_blocks.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> PoolStats {
+        let nr_chunks = self.header().nr_chunks.load(Ordering::Relaxed) as usize;
+        let mut large = ClassStats::default();
+        let mut class_chunks = vec![0; NR_CLASSES];
+        for chunk in 0..nr_chunks {
+            let meta = unsafe { *self.meta(chunk) };
+            match meta.class {
+                CHUNK_FREE | CHUNK_CONT => (),
+                CHUNK_LARGE => {
+                    large.live_blocks += 1;
+                    large.live_bytes += meta.len as usize * CHUNK_SIZE;
+                }
+                class => class_chunks[class as usize - 1] += 1,
+            }
+        }
+
+        let mut fragmented = 0;
+        let mut classes = vec![large];
+        for (i, nr) in class_chunks.into_iter().enumerate() {
+            let block_size = Self::block_size(i as u32 + 1);
+            let nr_free = self.free_blocks[i].lock().unwrap().len();
+            let live_blocks = (nr * CHUNK_SIZE / block_size).saturating_sub(nr_free);
+            fragmented += nr_free * block_size;
+            classes.push(ClassStats {
+                block_size,
+                live_blocks,
+                live_bytes: live_blocks * block_size,
+            });
+        }
+        classes.retain(|c| c.live_blocks > 0);
+
+        let heap_size = self.capacity * CHUNK_SIZE;
+        let used = classes.iter().map(|c| c.live_bytes).sum::<usize>();
+        PoolStats {
+            heap_size,
+            used,
+            free: heap_size - used,
+            fragmented,
+            classes,
+            recovery: *self.last_gc.lock().unwrap(),
+        }
+    }
+
+    unsafe fn block_of(&self, ptr: *const c_void) -> Option<usize> {
+        let off = (ptr as usize).checked_sub(self.base as usize)?;
+        self.block_start(off)
+            .map(|block| self.base as usize + block)
+    }
+
+    unsafe fn with_gc_stub<R>(f: impl FnOnce(&mut GarbageCollection) -> R) -> R {
+        let mut gc = GarbageCollection {
+            pool: ptr::null_mut(),
+            marked: HashSet::new(),
+            pending: Vec::new(),
+        };
+        f(&mut gc)
+    }
+
+    unsafe fn set_root_filter<T: Collectable>(&self, i: u64) {
+        unsafe extern "C" fn root_filter<T: Collectable>(
+            ptr: *mut c_char,
+            tid: usize,
+            gc: &mut GarbageCollection,
+        ) {
+            let pool = gc.pool;
+            T::mark(
+                &mut *(ptr as *mut T),
+                tid.wrapping_sub(RootIdx::MementoStart as usize),
+                gc,
+                &mut *pool,
+            );
+        }
+
+        self.filters.lock().unwrap()[i as usize] = Some(root_filter::<T>);
+    }
+
+    unsafe fn mark<T: Collectable>(
+        s: &mut T,
+        tid: usize,
+        gc: &mut GarbageCollection,
+        pool: &mut PoolHandle,
+    ) {
+        let ptr = s as *mut T as *mut c_char;
+        let off = some_or!((ptr as usize).checked_sub(pool.start()), return);
+        let block = some_or!(pool.allocator.block_start(off), return);
+        if gc.marked.insert(block) {
+            gc.pending.push((ptr, tid, T::filter_inner));
+        }
+    }
+
+    unsafe extern "C" fn filter_inner<T: Collectable>(
+        ptr: *mut T,
+        tid: usize,
+        gc: &mut GarbageCollection,
+    ) {
+        let pool = gc.pool;
+        T::filter(&mut *ptr, tid, gc, &mut *pool);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::tests::get_dummy_handle;
+
+    #[test]
+    fn size_class() {
+        assert_eq!(MmapAllocator::size_class(1), Some(1));
+        assert_eq!(MmapAllocator::size_class(64), Some(1));
+        assert_eq!(MmapAllocator::size_class(65), Some(2));
+        assert_eq!(
+            MmapAllocator::block_size(MmapAllocator::size_class(384).unwrap()),
+            512
+        );
+        assert_eq!(
+            MmapAllocator::size_class(MAX_BLOCK_SIZE),
+            Some(NR_CLASSES as u32)
+        );
+        assert_eq!(MmapAllocator::size_class(MAX_BLOCK_SIZE + 1), None);
+    }
+
+    #[test]
+    fn alloc_free() {
+        let pool = get_dummy_handle(HEAP_OFFSET + 32 * CHUNK_SIZE).unwrap();
+        let alloc = &pool.allocator;
+
+        unsafe {
+            // Freed blocks are reused
+            let small = alloc.malloc(100);
+            assert_eq!(small as usize % 128, 0);
+            alloc.free(small, 100);
+            assert_eq!(alloc.malloc(100), small);
+
+            let large = alloc.malloc(3 * CHUNK_SIZE as c_ulong);
+            assert!(pool.valid(large as usize + 3 * CHUNK_SIZE - 1));
+            alloc.free(large, 3 * CHUNK_SIZE);
+            assert_eq!(alloc.malloc(2 * CHUNK_SIZE as c_ulong), large);
+
+            // Out of memory
+            assert!(alloc
+                .malloc((alloc.capacity * CHUNK_SIZE) as c_ulong)
+                .is_null());
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let pool = get_dummy_handle(HEAP_OFFSET + 32 * CHUNK_SIZE).unwrap();
+        let alloc = &pool.allocator;
+        let live = |stats: &PoolStats, block_size| {
+            stats
+                .classes
+                .iter()
+                .find(|c| c.block_size == block_size)
+                .map_or(0, |c| c.live_blocks)
+        };
+
+        unsafe {
+            let before = alloc.stats();
+            let small = (0..3).map(|_| alloc.malloc(100)).collect::<Vec<_>>();
+            let large = alloc.malloc(2 * CHUNK_SIZE as c_ulong);
+
+            let after = alloc.stats();
+            assert_eq!(live(&after, 128), live(&before, 128) + 3);
+            assert_eq!(live(&after, 0), live(&before, 0) + 1);
+            assert_eq!(after.used, before.used + 3 * 128 + 2 * CHUNK_SIZE);
+            assert_eq!(after.used + after.free, after.heap_size);
+
+            for ptr in small {
+                alloc.free(ptr, 100);
+            }
+            alloc.free(large, 2 * CHUNK_SIZE);
+            assert_eq!(alloc.stats().used, before.used);
+        }
+    }
+}
@@ -9,20 +9,34 @@ use crossbeam_utils::CachePadded;
 use etrace::some_or;
 use libc::*;
 use std::{
+    alloc::Layout,
+    borrow::Cow,
     ffi::CString,
+    fs::OpenOptions,
     io::Error,
     mem::{self, transmute, MaybeUninit},
+    ops::Range,
+    os::unix::fs::FileExt,
     path::Path,
     sync::atomic::AtomicUsize,
 };
 
-use super::{global_pool, Pool, PoolHandle};
+use super::{Pool, PoolHandle, PoolStats};
 
-#[cfg(not(feature = "pmdk"))]
+#[cfg(all(feature = "pmdk", feature = "mmap"))]
+compile_error!("Please select only one from pmdk and mmap");
+
+#[cfg(not(any(feature = "pmdk", feature = "mmap")))]
 mod ralloc;
-#[cfg(not(feature = "pmdk"))]
+#[cfg(not(any(feature = "pmdk", feature = "mmap")))]
 pub(crate) type PMEMAllocator = ralloc::RallocAllocator;
 
+#[cfg(feature = "mmap")]
+mod mmap;
+/// Persistent Allocator
+#[cfg(feature = "mmap")]
+pub(crate) type PMEMAllocator = mmap::MmapAllocator;
+
 #[cfg(feature = "pmdk")]
 mod pmdk;
 /// Persistent Allocator
@@ -32,39 +46,172 @@ pub(crate) type PMEMAllocator = pmdk::PMDKAllocator;
 pub(crate) use pmdk::POPS;
 
 /// Trait for persistent allocator
+///
+/// An allocator instance is owned by the `PoolHandle` of the pool it manages.
 #[allow(missing_docs)]
-pub trait PAllocator {
+pub trait PAllocator: Sized {
     ///  Persistent pool file management
-    unsafe fn open(filepath: *const c_char, filesize: u64) -> c_int;
-    unsafe fn create(filepath: *const c_char, filesize: u64) -> c_int;
-    unsafe fn mmapped_addr() -> usize;
-    unsafe fn close(start: usize, len: usize);
-    unsafe fn recover() -> c_int;
+    unsafe fn open(filepath: *const c_char, filesize: u64) -> Result<Self, Error>;
+    unsafe fn create(filepath: *const c_char, filesize: u64) -> Result<Self, Error>;
+    unsafe fn grow(filepath: *const c_char, filesize: u64) -> Result<(), Error>;
+    fn mmapped_addr(&self) -> usize;
+    unsafe fn close(&self, start: usize, len: usize);
+    unsafe fn recover(pool: &mut PoolHandle) -> c_int;
+
+    /// Keep what the allocator has only in DRAM (e.g. free lists) for a clean shutdown
+    ///
+    /// Return false if it cannot be kept, so that the next open should run GC.
+    /// Nothing should be allocated or freed after it returns true.
+    unsafe fn keep_for_shutdown(&self) -> bool;
+
+    /// Restore what `keep_for_shutdown` kept, instead of running GC by `recover`
+    ///
+    /// Return false if nothing was kept, so that GC should run.
+    unsafe fn restore_after_shutdown(&self) -> bool;
+
+    fn exists(filepath: &str) -> bool;
+    fn remove(filepath: &str) -> Result<(), Error>;
+    fn copy(from: &str, to: &str) -> Result<(), Error>;
+
+    /// Write the mapped pool to new file(s) at `to` as they would be after a crash at this instant
+    ///
+    /// Bytes of the mapping in `last` are written after all the others are synced.
+    /// The caller should keep the pool from being changed until it returns.
+    unsafe fn snapshot(&self, to: &str, last: Range<usize>) -> Result<(), Error>;
 
     /// Root management
-    unsafe fn set_root(ptr: *mut c_void, i: u64) -> *mut c_void;
-    unsafe fn get_root(i: u64) -> *mut c_void;
+    unsafe fn set_root(&self, ptr: *mut c_void, i: u64) -> *mut c_void;
+    unsafe fn get_root(&self, i: u64) -> *mut c_void;
 
     // Dyanmic allocation
-    unsafe fn malloc(sz: c_ulong) -> *mut c_void;
-    unsafe fn free(ptr: *mut c_void, _len: usize);
+    unsafe fn malloc(&self, sz: c_ulong) -> *mut c_void;
+    unsafe fn free(&self, ptr: *mut c_void, _len: usize);
+
+    /// Allocate a block aligned according to `layout`
+    ///
+    /// The allocators do not take an alignment, but a block of a power-of-two size is aligned to its size.
+    /// Hence a layout aligned to more than a word is allocated as a block of its size rounded up to a power of two.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is not aligned (e.g. on PMDK, which does not align a block to its size)
+    unsafe fn malloc_layout(&self, layout: Layout) -> *mut c_void {
+        let ptr = self.malloc(block_size(layout) as c_ulong);
+        assert!(
+            ptr as usize % layout.align() == 0,
+            "block of {layout:?} is not aligned"
+        );
+        ptr
+    }
+
+    /// Free a block allocated by `malloc_layout` with the same `layout`
+    unsafe fn free_layout(&self, ptr: *mut c_void, layout: Layout) {
+        self.free(ptr, block_size(layout));
+    }
+
+    /// Statistics
+    fn stats(&self) -> PoolStats;
+
+    /// Start address of the block containing `ptr`, or `None` if `ptr` is not in a block in use
+    ///
+    /// Ralloc and the mmap allocator do not track each block of a size class, so a free block in a
+    /// superblock (chunk) in use is also found. It takes time linear to the number of objects on PMDK.
+    unsafe fn block_of(&self, ptr: *const c_void) -> Option<usize>;
+
+    /// Run `f` with a context of GC that is never used, to call `Collectable::filter` out of recovery
+    /// (e.g. `fsck`, which does not mark anything)
+    unsafe fn with_gc_stub<R>(f: impl FnOnce(&mut GarbageCollection) -> R) -> R;
 
     /// Functions for recovery
-    unsafe fn mark<T: Collectable>(s: &mut T, tid: usize, gc: &mut GarbageCollection);
+    unsafe fn mark<T: Collectable>(
+        s: &mut T,
+        tid: usize,
+        gc: &mut GarbageCollection,
+        pool: &mut PoolHandle,
+    );
     unsafe extern "C" fn filter_inner<T: Collectable>(
         ptr: *mut T,
         tid: usize,
         gc: &mut GarbageCollection,
     );
-    unsafe fn set_root_filter<T: Collectable>(i: u64);
+    unsafe fn set_root_filter<T: Collectable>(&self, i: u64);
+}
+
+/// Size of the block allocated by `PAllocator::malloc_layout` for `layout`
+fn block_size(layout: Layout) -> usize {
+    if layout.align() <= mem::align_of::<usize>() {
+        layout.size()
+    } else {
+        layout.pad_to_align().size().next_power_of_two()
+    }
+}
+
+/// Write mappings to new files as a snapshot: (path, address where the bytes are mapped, bytes)
+///
+/// Zero pages are skipped so that files stay sparse. Bytes mapped in `last` are written after all the others
+/// are synced, so a snapshot interrupted by a crash lacks them.
+pub(crate) fn write_snapshot(
+    files: &[(String, usize, &[u8])],
+    last: Range<usize>,
+) -> Result<(), Error> {
+    const PAGE_SIZE: usize = 4096;
+
+    let mut opened = Vec::new();
+    for (path, start, bytes) in files {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        file.set_len(bytes.len() as u64)?;
+        for (i, page) in bytes.chunks(PAGE_SIZE).enumerate() {
+            let page_start = start + i * PAGE_SIZE;
+            let hole = last.start.max(page_start)..last.end.min(page_start + page.len());
+            let page = if hole.is_empty() {
+                Cow::Borrowed(page)
+            } else {
+                let mut page = page.to_vec();
+                page[hole.start - page_start..hole.end - page_start].fill(0);
+                Cow::Owned(page)
+            };
+            if page.iter().any(|&b| b != 0) {
+                file.write_all_at(&page, (i * PAGE_SIZE) as u64)?;
+            }
+        }
+        file.sync_all()?;
+        opened.push(file);
+    }
+
+    for (file, (_, start, bytes)) in opened.iter().zip(files) {
+        let range = last.start.max(*start)..last.end.min(start + bytes.len());
+        if !range.is_empty() {
+            file.write_all_at(
+                &bytes[range.start - start..range.end - start],
+                (range.start - start) as u64,
+            )?;
+            file.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Error of allocation when the pool is out of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pool is out of memory")
+    }
 }
 
+impl std::error::Error for AllocError {}
+
 /// GarbageCollection
-#[cfg(not(feature = "pmdk"))]
+#[cfg(not(any(feature = "pmdk", feature = "mmap")))]
 pub type GarbageCollection = ralloc::GarbageCollection;
 /// GarbageCollection
+#[cfg(feature = "mmap")]
+pub type GarbageCollection = mmap::GarbageCollection;
+/// GarbageCollection
 #[cfg(feature = "pmdk")]
-pub type GarbageCollection = ();
+pub type GarbageCollection = pmdk::GarbageCollection;
 
 /// Trait for Garbage Collection
 ///
@@ -83,8 +230,12 @@ pub type GarbageCollection = ();
 /// ```
 pub trait Collectable: Sized {
     /// Mark itself and reserve the next marking with its filter func
-    fn mark(s: &mut Self, tid: usize, gc: &mut GarbageCollection) {
-        unsafe { PMEMAllocator::mark(s, tid, gc) }
+    fn mark(s: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        if let Some(fsck) = &mut pool.fsck {
+            // Checking the pool, not recovering it
+            return fsck.visit(s, tid);
+        }
+        unsafe { PMEMAllocator::mark(s, tid, gc, pool) }
     }
 
     /// - This function is intended to be called by Ralloc and should not be used by the user.
@@ -112,7 +263,7 @@ pub trait Collectable: Sized {
     /// # use memento::pmem::ptr::PPtr;
     /// # struct Inner {}
     /// # impl Collectable for Inner {
-    /// #    fn filter(_: &mut Self, _: &mut GarbageCollection, _: &PoolHandle) {
+    /// #    fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {
     /// #    }
     /// # }
     /// struct Node {
@@ -121,14 +272,14 @@ pub trait Collectable: Sized {
     /// }
     ///
     /// impl Collectable for Node {
-    ///     fn filter(node: &mut Self, gc: &mut GarbageCollection, pool: &PoolHandle) {
+    ///     fn filter(node: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
     ///         // Call filter of inner to mark the ptr in the inner struct
-    ///         Inner::filter(&mut node.inner, gc, pool);
+    ///         Inner::filter(&mut node.inner, tid, gc, pool);
     ///
     ///         // Mark the next node if the pointer is valid
     ///         if !node.next.is_null() {
     ///             let next = unsafe { node.next.deref_mut(pool) };
-    ///             Node::mark(next, gc);
+    ///             Node::mark(next, tid, gc, pool);
     ///         }
     ///     }
     /// }
@@ -172,6 +323,10 @@ impl Collectable for u64 {
     fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {}
 }
 
+impl Collectable for u128 {
+    fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {}
+}
+
 impl Collectable for u32 {
     fn filter(_: &mut Self, _: usize, _: &mut GarbageCollection, _: &mut PoolHandle) {}
 }
@@ -1,16 +1,21 @@
 //! PMDK
 
-use std::mem;
+use std::collections::HashSet;
+use std::io::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use std::{fmt, fs, mem, slice};
 
+use etrace::some_or;
 use libc::*;
 
-use crate::pmem::{global_pool, PoolHandle};
+use crate::pmem::pool::{RootIdx, NR_ROOTS as NUM_ROOT};
+use crate::pmem::{ClassStats, PoolHandle, PoolStats, RecoveryStats};
 
-use super::{Collectable, GarbageCollection, PAllocator};
-use crate::ploc::NR_MAX_THREADS;
-const NUM_ROOT: usize = NR_MAX_THREADS * 2; // 1024
+use super::{write_snapshot, Collectable, GarbageCollection, PAllocator};
 
-static mut ROOT: *mut Root = std::ptr::null_mut();
+/// Pool opened most recently. It is used only by PSan (`pmcheck`), which checks one pool at a time.
 pub(crate) static mut POPS: *mut pmemobj_sys::PMEMobjpool = std::ptr::null_mut();
 
 struct Root {
@@ -21,6 +26,53 @@ type filter_func =
     unsafe fn(s: *mut c_void, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle);
 unsafe impl Sync for Root {}
 
+type ObjFilter = unsafe extern "C" fn(*mut c_char, usize, &mut GarbageCollection);
+
+/// Marking state of the recovery-time reachability pass
+pub struct GarbageCollection {
+    pool: *mut PoolHandle,
+
+    /// Allocated objects (offset, usable size) sorted by offset
+    objs: Vec<(u64, u64)>,
+
+    /// Offsets of reachable objects
+    marked: HashSet<u64>,
+
+    /// Marked but not yet filtered objects
+    pending: Vec<(*mut c_char, usize, ObjFilter)>,
+}
+
+// `ObjFilter` does not implement `Debug`, so implement it manually.
+impl fmt::Debug for GarbageCollection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GarbageCollection")
+            .field("objs", &self.objs.len())
+            .field("marked", &self.marked.len())
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl GarbageCollection {
+    /// Offset of the allocated object containing `off`
+    fn obj_start(&self, off: u64) -> Option<u64> {
+        let i = self.objs.partition_point(|&(start, _)| start <= off);
+        let (start, size) = *self.objs.get(i.checked_sub(1)?)?;
+        (off < start + size).then_some(start)
+    }
+
+    /// Mark the object containing `ptr`, returning `true` if it is newly marked
+    unsafe fn mark_obj(&mut self, ptr: *mut c_void) -> bool {
+        let oid = pmemobj_sys::pmemobj_oid(ptr);
+        if oid.off == 0 {
+            // Not in the pool
+            return false;
+        }
+        let start = some_or!(self.obj_start(oid.off), return false);
+        self.marked.insert(start)
+    }
+}
+
 impl Root {
     fn new() -> Self {
         Root {
@@ -33,88 +85,185 @@ impl Root {
     }
 }
 
-pub(crate) struct PMDKAllocator {}
+#[derive(Debug)]
+pub(crate) struct PMDKAllocator {
+    pop: *mut pmemobj_sys::PMEMobjpool,
+    root: *mut Root,
 
-impl PAllocator for PMDKAllocator {
-    unsafe fn open(filepath: *const c_char, filesize: u64) -> c_int {
-        let res = chmod(filepath, 0o777);
-        POPS = pmemobj_sys::pmemobj_open(filepath, std::ptr::null_mut());
-        if POPS.is_null() {
+    /// Size of pool file
+    size: usize,
+
+    /// Result of the last recovery
+    last_gc: Mutex<Option<RecoveryStats>>,
+}
+
+unsafe impl Send for PMDKAllocator {}
+unsafe impl Sync for PMDKAllocator {}
+
+impl PMDKAllocator {
+    unsafe fn from_pop(pop: *mut pmemobj_sys::PMEMobjpool, size: u64) -> Self {
+        if pop.is_null() {
             let msg = pmemobj_sys::pmemobj_errormsg();
             let msgg = msg.as_ref().unwrap().to_string();
             panic!("err: {:?}", msgg);
         }
+        POPS = pop;
+
+        let root = pmemobj_sys::pmemobj_root(pop, mem::size_of::<Root>());
+        Self {
+            pop,
+            root: pmemobj_sys::pmemobj_direct(root) as *mut Root,
+            size: size as usize,
+            last_gc: Mutex::new(None),
+        }
+    }
+
+    /// All allocated objects (offset, usable size) sorted by offset
+    ///
+    /// The root object of pmemobj (i.e. `Root`) is not included.
+    unsafe fn objects(&self) -> Vec<(u64, u64)> {
+        let mut objs = Vec::new();
+        let mut oid = pmemobj_sys::pmemobj_first(self.pop);
+        while oid.off != 0 {
+            objs.push((oid.off, pmemobj_sys::pmemobj_alloc_usable_size(oid) as u64));
+            oid = pmemobj_sys::pmemobj_next(oid);
+        }
+        objs.sort_unstable();
+        objs
+    }
+}
 
-        let root = pmemobj_sys::pmemobj_root(POPS, mem::size_of::<Root>());
-        ROOT = pmemobj_sys::pmemobj_direct(root) as *mut Root;
+impl PAllocator for PMDKAllocator {
+    unsafe fn open(filepath: *const c_char, filesize: u64) -> Result<Self, Error> {
+        let res = chmod(filepath, 0o777);
+        let pop = pmemobj_sys::pmemobj_open(filepath, std::ptr::null_mut());
+        let alloc = Self::from_pop(pop, filesize);
         println!("[pmem_open] finish!]");
-        return 1;
+        Ok(alloc)
     }
 
-    unsafe fn create(filepath: *const c_char, filesize: u64) -> c_int {
-        POPS =
+    unsafe fn create(filepath: *const c_char, filesize: u64) -> Result<Self, Error> {
+        let pop =
             pmemobj_sys::pmemobj_create(filepath, std::ptr::null_mut(), filesize as usize, 0o777);
-        if POPS.is_null() {
-            let msg = pmemobj_sys::pmemobj_errormsg();
-            let msgg = msg.as_ref().unwrap().to_string();
-            panic!("err: {:?}", msgg)
-        }
+        Ok(Self::from_pop(pop, filesize))
+    }
 
-        let root = pmemobj_sys::pmemobj_root(POPS, mem::size_of::<Root>());
-        ROOT = pmemobj_sys::pmemobj_direct(root) as *mut Root;
-        return 0;
+    unsafe fn grow(filepath: *const c_char, filesize: u64) -> Result<(), Error> {
+        // A pmemobj pool cannot be extended in place (it needs a poolset).
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "PMDK pool cannot grow.",
+        ))
     }
 
-    unsafe fn mmapped_addr() -> usize {
-        let root_oid = pmemobj_sys::pmemobj_oid(ROOT as *mut c_void);
-        let start = ROOT as usize - root_oid.off as usize;
-        assert!(root_oid.off % 64 == 16);
-        start + 16
+    fn mmapped_addr(&self) -> usize {
+        unsafe {
+            let root_oid = pmemobj_sys::pmemobj_oid(self.root as *mut c_void);
+            let start = self.root as usize - root_oid.off as usize;
+            assert!(root_oid.off % 64 == 16);
+            start + 16
+        }
     }
 
-    unsafe fn close(start: usize, len: usize) {
-        if !POPS.is_null() {
-            pmemobj_sys::pmemobj_close(POPS);
+    unsafe fn close(&self, start: usize, len: usize) {
+        pmemobj_sys::pmemobj_close(self.pop);
+        if POPS == self.pop {
             POPS = std::ptr::null_mut();
         }
     }
 
-    unsafe fn recover() -> c_int {
-        // Call root filters
-        let root = ROOT.as_mut().unwrap();
+    unsafe fn recover(pool: &mut PoolHandle) -> c_int {
+        let pool_ptr = pool as *mut PoolHandle;
+        let alloc = &(*pool_ptr).allocator;
+        let mut gc = GarbageCollection {
+            pool: pool_ptr,
+            objs: alloc.objects(),
+            marked: HashSet::new(),
+            pending: Vec::new(),
+        };
+
+        // Mark
+        let root = alloc.root.as_ref().unwrap();
         for (i, filter) in root.filters.iter().enumerate() {
-            let oid = root.objs[i];
-            if let Some(filter) = filter {
-                let obj = pmemobj_sys::pmemobj_direct(oid);
-                assert!(!obj.is_null());
-                filter(
-                    obj,
-                    i,
-                    &mut *(&mut () as *mut _ as *mut GarbageCollection),
-                    global_pool().unwrap(),
-                );
+            let obj = pmemobj_sys::pmemobj_direct(root.objs[i]);
+            if obj.is_null() {
+                continue;
             }
+            match filter {
+                Some(filter) => filter(obj, i, &mut gc, &mut *pool_ptr),
+                // Like Ralloc, a root without filter is still reachable itself
+                None => {
+                    let _ = gc.mark_obj(obj);
+                }
+            }
+        }
+        while let Some((ptr, tid, filter)) = gc.pending.pop() {
+            filter(ptr, tid, &mut gc);
         }
+
+        // Sweep
+        let uuid = pmemobj_sys::pmemobj_oid(alloc.root as *mut c_void).pool_uuid_lo;
+        for &(off, _) in &gc.objs {
+            if !gc.marked.contains(&off) {
+                let mut oid = pmemobj_sys::PMEMoid {
+                    off,
+                    pool_uuid_lo: uuid,
+                };
+                pmemobj_sys::pmemobj_free(&mut oid as *mut _);
+            }
+        }
+
+        *alloc.last_gc.lock().unwrap() = Some(RecoveryStats {
+            reachable: gc.marked.len(),
+            reclaimed: gc.objs.len() - gc.marked.len(),
+        });
         1
     }
 
-    unsafe fn set_root(ptr: *mut c_void, i: u64) -> *mut c_void {
-        let root = ROOT.as_mut().unwrap();
+    unsafe fn keep_for_shutdown(&self) -> bool {
+        // PMDK keeps track of allocated objects by itself.
+        true
+    }
+
+    unsafe fn restore_after_shutdown(&self) -> bool {
+        true
+    }
+
+    fn exists(filepath: &str) -> bool {
+        Path::new(filepath).exists()
+    }
+
+    fn remove(filepath: &str) -> Result<(), Error> {
+        let _ = fs::remove_file(filepath);
+        Ok(())
+    }
+
+    fn copy(from: &str, to: &str) -> Result<(), Error> {
+        let _ = fs::copy(from, to)?;
+        Ok(())
+    }
+
+    unsafe fn snapshot(&self, to: &str, last: Range<usize>) -> Result<(), Error> {
+        // The whole pool file is mapped from `pop`.
+        let bytes = slice::from_raw_parts(self.pop as *const u8, self.size);
+        write_snapshot(&[(to.to_owned(), self.pop as usize, bytes)], last)
+    }
+
+    unsafe fn set_root(&self, ptr: *mut c_void, i: u64) -> *mut c_void {
+        let root = self.root.as_mut().unwrap();
         let old = root.objs[i as usize];
         let oid = pmemobj_sys::pmemobj_oid(ptr);
         root.objs[i as usize] = oid;
         pmemobj_sys::pmemobj_direct(old)
     }
 
-    unsafe fn get_root(i: u64) -> *mut c_void {
-        let oid = ROOT.as_mut().unwrap().objs[i as usize];
-        if oid.pool_uuid_lo == 0 {
-            panic!("err: !!!");
-        }
+    unsafe fn get_root(&self, i: u64) -> *mut c_void {
+        // Like Ralloc, return null if there is no root `i`
+        let oid = self.root.as_mut().unwrap().objs[i as usize];
         pmemobj_sys::pmemobj_direct(oid)
     }
 
-    unsafe fn malloc(sz: c_ulong) -> *mut c_void {
+    unsafe fn malloc(&self, sz: c_ulong) -> *mut c_void {
         let mut oid = pmemobj_sys::PMEMoid {
             off: 0,
             pool_uuid_lo: 0,
@@ -123,7 +272,7 @@ impl PAllocator for PMDKAllocator {
         let sz = if sz < 64 { 64 } else { sz.try_into().unwrap() };
         let status = unsafe {
             pmemobj_sys::pmemobj_zalloc(
-                POPS,
+                self.pop,
                 oidp as *mut pmemobj_sys::PMEMoid,
                 sz,
                 0,
@@ -131,34 +280,108 @@ impl PAllocator for PMDKAllocator {
                 // std::ptr::null_mut(),
             )
         };
-        assert!(oid.off % 64 == 16, "oid: {:?}, sz: {}", oid, sz);
-        if status == 0 {
-            pmemobj_sys::pmemobj_direct(oid)
-        } else {
-            panic!("err");
+        if status != 0 {
+            // out of memory
+            return std::ptr::null_mut();
         }
+        assert!(oid.off % 64 == 16, "oid: {:?}, sz: {}", oid, sz);
+        pmemobj_sys::pmemobj_direct(oid)
     }
 
-    unsafe fn free(ptr: *mut c_void, _len: usize) {
+    unsafe fn free(&self, ptr: *mut c_void, _len: usize) {
         let mut oid = pmemobj_sys::pmemobj_oid(ptr);
         pmemobj_sys::pmemobj_free(&mut oid as *mut _);
     }
 
-    unsafe fn set_root_filter<T: Collectable>(i: u64) {
+    fn stats(&self) -> PoolStats {
+        // PMDK does not expose its size classes, so group objects by their usable size.
+        let mut classes: Vec<ClassStats> = Vec::new();
+        let mut sizes = unsafe { self.objects() }
+            .into_iter()
+            .map(|(_, size)| size as usize)
+            .collect::<Vec<_>>();
+        sizes.sort_unstable();
+        for size in sizes {
+            match classes.last_mut() {
+                Some(class) if class.block_size == size => {
+                    class.live_blocks += 1;
+                    class.live_bytes += size;
+                }
+                _ => classes.push(ClassStats {
+                    block_size: size,
+                    live_blocks: 1,
+                    live_bytes: size,
+                }),
+            }
+        }
+
+        // The heap size includes the metadata of pmemobj.
+        let used = classes.iter().map(|c| c.live_bytes).sum::<usize>();
+        PoolStats {
+            heap_size: self.size,
+            used,
+            free: self.size.saturating_sub(used),
+            fragmented: 0,
+            classes,
+            recovery: *self.last_gc.lock().unwrap(),
+        }
+    }
+
+    unsafe fn block_of(&self, ptr: *const c_void) -> Option<usize> {
+        let off = pmemobj_sys::pmemobj_oid(ptr).off;
+        if off == 0 {
+            // Not in the pool
+            return None;
+        }
+        let mut oid = pmemobj_sys::pmemobj_first(self.pop);
+        while oid.off != 0 {
+            let size = pmemobj_sys::pmemobj_alloc_usable_size(oid) as u64;
+            if oid.off <= off && off < oid.off + size {
+                return Some(pmemobj_sys::pmemobj_direct(oid) as usize);
+            }
+            oid = pmemobj_sys::pmemobj_next(oid);
+        }
+        None
+    }
+
+    unsafe fn with_gc_stub<R>(f: impl FnOnce(&mut GarbageCollection) -> R) -> R {
+        let mut gc = GarbageCollection {
+            pool: std::ptr::null_mut(),
+            objs: Vec::new(),
+            marked: HashSet::new(),
+            pending: Vec::new(),
+        };
+        f(&mut gc)
+    }
+
+    unsafe fn set_root_filter<T: Collectable>(&self, i: u64) {
         unsafe fn root_filter<T: Collectable>(
             s: *mut c_void,
             tid: usize,
             gc: &mut GarbageCollection,
             pool: &mut PoolHandle,
         ) {
-            T::filter(&mut *(s as *mut T), tid, gc, pool)
+            T::mark(
+                &mut *(s as *mut T),
+                tid.wrapping_sub(RootIdx::MementoStart as usize),
+                gc,
+                pool,
+            )
         };
 
-        ROOT.as_mut().unwrap().filters[i as usize] = Some(root_filter::<T>);
+        self.root.as_mut().unwrap().filters[i as usize] = Some(root_filter::<T>);
     }
 
-    unsafe fn mark<T: Collectable>(s: &mut T, tid: usize, gc: &mut GarbageCollection) {
-        T::filter(s, tid, gc, global_pool().unwrap());
+    unsafe fn mark<T: Collectable>(
+        s: &mut T,
+        tid: usize,
+        gc: &mut GarbageCollection,
+        _: &mut PoolHandle,
+    ) {
+        let ptr = s as *mut T as *mut c_char;
+        if gc.mark_obj(ptr as *mut c_void) {
+            gc.pending.push((ptr, tid, T::filter_inner));
+        }
     }
 
     unsafe extern "C" fn filter_inner<T: Collectable>(
@@ -166,6 +389,7 @@ impl PAllocator for PMDKAllocator {
         tid: usize,
         gc: &mut GarbageCollection,
     ) {
-        unreachable!("This function is only for Ralloc GC.")
+        let pool = gc.pool;
+        T::filter(&mut *ptr, tid, gc, &mut *pool);
     }
 }
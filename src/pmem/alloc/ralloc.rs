@@ -3,16 +3,19 @@
 use crossbeam_utils::CachePadded;
 use etrace::some_or;
 
-use crate::pmem::{Collectable, RootIdx};
+use crate::pmem::pool::NR_ROOTS;
+use crate::pmem::{ClassStats, Collectable, PoolStats, RecoveryStats, RootIdx};
 
-use super::{
-    super::{global_pool, PoolHandle},
-    PAllocator,
-};
+use super::{super::PoolHandle, write_snapshot, PAllocator};
 use std::{
+    fs,
+    io::{Error, ErrorKind},
     mem::MaybeUninit,
+    ops::Range,
     os::raw::{c_char, c_int, c_ulong, c_void},
-    sync::atomic::AtomicUsize,
+    path::Path,
+    slice,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
 
 /* automatically generated by rust-bindgen 0.59.1 */
@@ -28,6 +31,9 @@ extern "C" {
 
     pub(crate) fn RP_close();
 
+    /// If return is 0, the heap file is grown, otherwise it failed (e.g. the heap is mapped or the size is smaller).
+    pub(crate) fn RP_grow(_id: *const c_char, size: u64) -> c_int;
+
     pub(crate) fn RP_malloc(sz: c_ulong) -> *mut c_void;
 
     pub(crate) fn RP_free(ptr: *mut c_void);
@@ -95,46 +101,257 @@ extern "C" {
     );
 }
 
+// Functions added for statistics that are not in the original ralloc
+#[link(name = "ralloc", kind = "static")]
+extern "C" {
+    /// Number of size classes. Class 0 is for large blocks.
+    pub(crate) fn RP_nr_classes() -> c_int;
+
+    /// Block size of size class `idx` (0 for large blocks)
+    pub(crate) fn RP_class_size(idx: c_int) -> usize;
+
+    /// Capacity of the superblock region in bytes
+    pub(crate) fn RP_heap_size() -> usize;
+
+    /// Count live blocks and bytes of each size class, and free bytes in superblocks in use.
+    pub(crate) fn RP_count_blocks(
+        live_blocks: *mut u64,
+        live_bytes: *mut u64,
+        free_bytes: *mut u64,
+    );
+
+    /// If return is 1, the GC ran in the last `RP_recover` and its result is written, otherwise 0.
+    pub(crate) fn RP_last_gc(reachable: *mut u64, reclaimed: *mut u64) -> c_int;
+
+    /// Start of the block containing `ptr` in a superblock in use, otherwise null
+    pub(crate) fn RP_block_start(ptr: *mut c_void) -> *mut c_void;
+}
+
+// Functions added for snapshot that are not in the original ralloc
+#[link(name = "ralloc", kind = "static")]
+extern "C" {
+    /// If the return is 0, the address where the file of region `idx` is mapped and its size are written, otherwise 1.
+    pub(crate) fn RP_region_file(idx: c_int, base_addr: *mut *mut c_void, size: *mut u64) -> c_int;
+
+    /// Mark the dirty flag in `meta`, a copy of the file of metadata region, as if the process crashed.
+    pub(crate) fn RP_mark_crashed(meta: *mut c_void);
+}
+
+/// Number of roots of Ralloc (`MAX_ROOTS` in `ext/ralloc/src/pm_config.hpp`)
+const MAX_ROOTS: usize = 16384;
+const _: () = assert!(NR_ROOTS <= MAX_ROOTS);
+
+/// Ralloc keeps its heap in global variables, so only one pool can be mapped by Ralloc per process.
+static IS_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Pool being recovered by Ralloc's GC.
+/// (Ralloc calls `filter_inner` without any context, so the pool should be found here.)
+static GC_POOL: AtomicPtr<PoolHandle> = AtomicPtr::new(std::ptr::null_mut());
+
+#[derive(Debug)]
 pub(crate) struct RallocAllocator {}
 
+impl RallocAllocator {
+    unsafe fn init(
+        filepath: *const libc::c_char,
+        filesize: u64,
+        is_reopen: c_int,
+    ) -> Result<Self, Error> {
+        if IS_OPEN
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Ralloc can map only one pool per process (use the `pmdk` or `mmap` feature for several pools).",
+            ));
+        }
+
+        if RP_init(filepath, filesize) != is_reopen {
+            // The file has been created or removed by someone else since it was checked.
+            RP_close();
+            IS_OPEN.store(false, Ordering::SeqCst);
+            return Err(if is_reopen == 1 {
+                Error::new(ErrorKind::NotFound, "Pool does not exist.")
+            } else {
+                Error::new(ErrorKind::AlreadyExists, "Pool already exists.")
+            });
+        }
+        Ok(Self {})
+    }
+}
+
 impl PAllocator for RallocAllocator {
-    unsafe fn open(filepath: *const libc::c_char, filesize: u64) -> libc::c_int {
-        RP_init(filepath, filesize)
+    unsafe fn open(filepath: *const libc::c_char, filesize: u64) -> Result<Self, Error> {
+        Self::init(filepath, filesize, 1)
+    }
+
+    unsafe fn create(filepath: *const libc::c_char, filesize: u64) -> Result<Self, Error> {
+        Self::init(filepath, filesize, 0)
     }
 
-    unsafe fn create(filepath: *const libc::c_char, filesize: u64) -> libc::c_int {
-        RP_init(filepath, filesize)
+    unsafe fn grow(filepath: *const libc::c_char, filesize: u64) -> Result<(), Error> {
+        if IS_OPEN.load(Ordering::SeqCst) {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "Ralloc cannot grow a pool while a pool is mapped.",
+            ));
+        }
+
+        if RP_grow(filepath, filesize) != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Failed to grow the pool files.",
+            ));
+        }
+        Ok(())
     }
 
-    unsafe fn mmapped_addr() -> usize {
-        RP_mmapped_addr()
+    fn mmapped_addr(&self) -> usize {
+        unsafe { RP_mmapped_addr() }
     }
 
-    unsafe fn close(start: usize, len: usize) {
+    unsafe fn close(&self, start: usize, len: usize) {
         RP_close();
+        IS_OPEN.store(false, Ordering::SeqCst);
+    }
+
+    unsafe fn recover(pool: &mut PoolHandle) -> libc::c_int {
+        GC_POOL.store(pool, Ordering::SeqCst);
+        let res = RP_recover();
+        GC_POOL.store(std::ptr::null_mut(), Ordering::SeqCst);
+        res
+    }
+
+    unsafe fn keep_for_shutdown(&self) -> bool {
+        // `RP_close` marks the pool clean.
+        true
+    }
+
+    unsafe fn restore_after_shutdown(&self) -> bool {
+        // `RP_recover` skips GC by itself if the pool was closed cleanly.
+        false
+    }
+
+    fn exists(filepath: &str) -> bool {
+        Path::new(&(filepath.to_owned() + "_basemd")).exists()
+    }
+
+    fn remove(filepath: &str) -> Result<(), Error> {
+        // Ralloc maps a pool with three files: metadata, descriptors, and superblocks
+        for postfix in ["_basemd", "_desc", "_sb"] {
+            let _ = fs::remove_file(filepath.to_owned() + postfix);
+        }
+        Ok(())
     }
 
-    unsafe fn recover() -> libc::c_int {
-        RP_recover()
+    fn copy(from: &str, to: &str) -> Result<(), Error> {
+        for postfix in ["_basemd", "_desc", "_sb"] {
+            let _ = fs::copy(from.to_owned() + postfix, to.to_owned() + postfix)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn snapshot(&self, to: &str, last: Range<usize>) -> Result<(), Error> {
+        let region = |idx| {
+            let mut base = std::ptr::null_mut();
+            let mut size = 0;
+            assert_eq!(RP_region_file(idx, &mut base, &mut size), 0);
+            (
+                base as usize,
+                slice::from_raw_parts(base as *const u8, size as usize),
+            )
+        };
+        // Region indices are from `RegionIndex` of Ralloc.
+        let (desc_start, desc) = region(0);
+        let (sb_start, sb) = region(1);
+        let (meta_start, meta) = region(2);
+
+        // Ralloc holds the dirty flag while the pool is mapped, and it should look released by a crash
+        // in the snapshot so that GC runs when it is opened.
+        let mut meta = meta.to_vec();
+        RP_mark_crashed(meta.as_mut_ptr() as *mut c_void);
+
+        write_snapshot(
+            &[
+                (to.to_owned() + "_desc", desc_start, desc),
+                (to.to_owned() + "_sb", sb_start, sb),
+                (to.to_owned() + "_basemd", meta_start, &meta),
+            ],
+            last,
+        )
     }
 
-    unsafe fn set_root(ptr: *mut libc::c_void, i: u64) -> *mut libc::c_void {
+    unsafe fn set_root(&self, ptr: *mut libc::c_void, i: u64) -> *mut libc::c_void {
         RP_set_root(ptr, i)
     }
 
-    unsafe fn get_root(i: u64) -> *mut libc::c_void {
+    fn stats(&self) -> PoolStats {
+        unsafe {
+            let nr_classes = RP_nr_classes() as usize;
+            let mut live_blocks = vec![0; nr_classes];
+            let mut live_bytes = vec![0; nr_classes];
+            let mut fragmented = 0;
+            RP_count_blocks(
+                live_blocks.as_mut_ptr(),
+                live_bytes.as_mut_ptr(),
+                &mut fragmented,
+            );
+
+            // Large blocks (class 0) have the block size 0, so they come first.
+            let classes = (0..nr_classes)
+                .filter(|&i| live_blocks[i] > 0)
+                .map(|i| ClassStats {
+                    block_size: RP_class_size(i as c_int),
+                    live_blocks: live_blocks[i] as usize,
+                    live_bytes: live_bytes[i] as usize,
+                })
+                .collect::<Vec<_>>();
+
+            let (mut reachable, mut reclaimed) = (0, 0);
+            let recovery =
+                (RP_last_gc(&mut reachable, &mut reclaimed) == 1).then_some(RecoveryStats {
+                    reachable: reachable as usize,
+                    reclaimed: reclaimed as usize,
+                });
+
+            let heap_size = RP_heap_size();
+            let used = classes.iter().map(|c| c.live_bytes).sum::<usize>();
+            PoolStats {
+                heap_size,
+                used,
+                free: heap_size.saturating_sub(used),
+                fragmented: fragmented as usize,
+                classes,
+                recovery,
+            }
+        }
+    }
+
+    unsafe fn block_of(&self, ptr: *const libc::c_void) -> Option<usize> {
+        let start = RP_block_start(ptr as *mut libc::c_void);
+        (!start.is_null()).then_some(start as usize)
+    }
+
+    unsafe fn with_gc_stub<R>(f: impl FnOnce(&mut GarbageCollection) -> R) -> R {
+        // `GarbageCollection` is an extern type of no size, so a dangling pointer is a valid reference to it.
+        let gc = std::ptr::NonNull::<u8>::dangling().as_ptr() as *mut GarbageCollection;
+        f(&mut *gc)
+    }
+
+    unsafe fn get_root(&self, i: u64) -> *mut libc::c_void {
         RP_get_root_c(i)
     }
 
-    unsafe fn malloc(sz: libc::c_ulong) -> *mut libc::c_void {
+    unsafe fn malloc(&self, sz: libc::c_ulong) -> *mut libc::c_void {
         RP_malloc(sz)
     }
 
-    unsafe fn free(ptr: *mut libc::c_void, _len: usize) {
+    unsafe fn free(&self, ptr: *mut libc::c_void, _len: usize) {
         RP_free(ptr)
     }
 
-    unsafe fn set_root_filter<T: Collectable>(i: u64) {
+    unsafe fn set_root_filter<T: Collectable>(&self, i: u64) {
         unsafe extern "C" fn root_filter<T: Collectable>(
             ptr: *mut c_char,
             tid: usize,
@@ -151,7 +368,12 @@ impl PAllocator for RallocAllocator {
         RP_set_root_filter(Some(root_filter::<T>), i)
     }
 
-    unsafe fn mark<T: Collectable>(s: &mut T, tid: usize, gc: &mut super::GarbageCollection) {
+    unsafe fn mark<T: Collectable>(
+        s: &mut T,
+        tid: usize,
+        gc: &mut super::GarbageCollection,
+        _: &mut PoolHandle,
+    ) {
         let ptr = s as *mut _ as *mut c_char;
         unsafe { RP_mark(gc, ptr, tid, Some(T::filter_inner)) };
     }
@@ -161,8 +383,7 @@ impl PAllocator for RallocAllocator {
         tid: usize,
         gc: &mut GarbageCollection,
     ) {
-        let pool = global_pool().unwrap();
-        // let s = (ptr as *mut _ as *mut T).as_mut().unwrap();
+        let pool = GC_POOL.load(Ordering::SeqCst).as_mut().unwrap();
         T::filter(ptr.as_mut().unwrap(), tid, gc, pool);
     }
 }
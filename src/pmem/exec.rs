@@ -0,0 +1,285 @@
+//! Execution policy of root mementos
+//!
+//! `PoolHandle::execute_with` re-executes a root memento that fails (i.e. its thread panics or is killed)
+//! according to `ExecConfig`, and returns the outcome of each root memento.
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Failure of a root memento, which is handed over to `ExecConfig::on_failure`
+#[derive(Debug)]
+pub struct ExecFailure<'a> {
+    /// Tid of the root memento
+    pub tid: usize,
+
+    /// Number of failures of the root memento so far (including this one)
+    pub attempt: usize,
+
+    /// Payload of the panic (`None` if the thread was killed without panicking)
+    pub payload: Option<&'a (dyn Any + Send)>,
+}
+
+impl ExecFailure<'_> {
+    /// Message of the panic if its payload is a string
+    pub fn message(&self) -> Option<&str> {
+        let payload = self.payload?;
+        payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    }
+}
+
+/// Callback on each failure of a root memento
+type OnFailure = Arc<dyn Fn(&ExecFailure<'_>) + Send + Sync>;
+
+/// Execution policy of root mementos for `PoolHandle::execute_with`
+///
+/// By default, a failing root memento is re-executed forever without delay, as `PoolHandle::execute` does.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use memento::pmem::*;
+/// # use memento::test_utils::tests::{DummyRootObj as MyRootObj, DummyRootMemento as MyRootMemento};
+/// # let pool_handle = Pool::open::<MyRootObj, MyRootMemento>("foo.pool", 8 * 1024 * 1024 * 1024).unwrap();
+/// let config = ExecConfig::new()
+///     .max_retries(3)
+///     .backoff(Duration::from_millis(10), Duration::from_secs(1))
+///     .on_failure(|f| eprintln!("root memento {} failed: {:?}", f.tid, f.message()));
+///
+/// for res in pool_handle.execute_with::<MyRootObj, MyRootMemento>(&config) {
+///     match res {
+///         Ok(res) if res.failures > 0 => println!("recovered after {} failures", res.failures),
+///         Ok(_) => {}
+///         Err(e) => println!("{e}"),
+///     }
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ExecConfig {
+    max_retries: Option<usize>,
+    backoff: Duration,
+    max_backoff: Duration,
+    on_failure: Option<OnFailure>,
+}
+
+// `OnFailure` does not implement `Debug`, so implement it manually.
+impl fmt::Debug for ExecConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecConfig")
+            .field("max_retries", &self.max_retries)
+            .field("backoff", &self.backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("on_failure", &self.on_failure.is_some())
+            .finish()
+    }
+}
+
+impl ExecConfig {
+    /// Re-execute forever without delay
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up a root memento after it is re-executed `max_retries` times
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Wait before each re-execution, starting from `initial` and doubling up to `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Call `f` on each failure instead of printing it
+    pub fn on_failure(mut self, f: impl Fn(&ExecFailure<'_>) + Send + Sync + 'static) -> Self {
+        self.on_failure = Some(Arc::new(f));
+        self
+    }
+
+    /// Report `failure`, and return whether to re-execute
+    pub(crate) fn fail(&self, failure: &ExecFailure<'_>) -> bool {
+        match &self.on_failure {
+            Some(f) => f(failure),
+            None => println!("[pool::execute] Thread {} re-executed.", failure.tid),
+        }
+
+        if self.max_retries.map_or(false, |max| failure.attempt > max) {
+            return false;
+        }
+        std::thread::sleep(self.delay(failure.attempt));
+        true
+    }
+
+    /// Delay before the `attempt`th re-execution
+    fn delay(&self, attempt: usize) -> Duration {
+        let shift = (attempt - 1).min(u32::MAX as usize) as u32;
+        self.backoff
+            .checked_mul(1u32.checked_shl(shift).unwrap_or(u32::MAX))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+/// Outcome of a root memento that succeeded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput<T> {
+    /// Output of `RootObj::run`
+    pub output: T,
+
+    /// Number of failures before it succeeded
+    pub failures: usize,
+
+    /// Whether it had finished before (i.e. the output was in the pool and `run` was not called)
+    pub finished_before: bool,
+}
+
+/// Error of a root memento that is given up after `ExecConfig::max_retries`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecError {
+    /// Tid of the root memento
+    pub tid: usize,
+
+    /// Number of failures (i.e. `max_retries + 1`)
+    pub attempts: usize,
+
+    /// Message of the last panic if its payload is a string
+    pub message: Option<String>,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "root memento {} failed {} times",
+            self.tid, self.attempts
+        )?;
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let ms = Duration::from_millis;
+        let config = ExecConfig::new();
+        assert_eq!(config.delay(1), Duration::ZERO);
+
+        let config = config.backoff(ms(10), ms(50));
+        let delays = (1..=4)
+            .map(|attempt| config.delay(attempt))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [ms(10), ms(20), ms(40), ms(50)]);
+        assert_eq!(config.delay(usize::MAX), ms(50));
+    }
+
+    #[test]
+    fn message() {
+        let payload: &(dyn Any + Send) = &String::from("boom");
+        let failure = ExecFailure {
+            tid: 1,
+            attempt: 1,
+            payload: Some(payload),
+        };
+        assert_eq!(failure.message(), Some("boom"));
+    }
+
+    #[cfg(feature = "mmap")]
+    mod flaky {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use super::*;
+        use crate::ploc::Handle;
+        use crate::pmem::alloc::{Collectable, GarbageCollection};
+        use crate::pmem::pool::{Pool, PoolHandle, RootObj};
+        use crate::test_utils::tests::DummyRootMemento;
+        use crate::*;
+
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+        /// Root object whose root memento panics in its first `FAILURES` runs
+        #[derive(Debug, Collectable)]
+        struct Flaky;
+
+        impl PDefault for Flaky {
+            fn pdefault(_: &Handle) -> Self {
+                Self
+            }
+        }
+
+        impl RootObj<DummyRootMemento> for Flaky {
+            fn run(&self, _: &mut DummyRootMemento, _: &Handle) {
+                if RUNS.fetch_add(1, Ordering::SeqCst) < FAILURES.load(Ordering::SeqCst) {
+                    panic!("flaky");
+                }
+            }
+        }
+
+        #[test]
+        fn execute_with() {
+            const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+            let dir = tempfile::tempdir().unwrap();
+            let create = |name: &str| {
+                let path = dir.path().join(name).to_str().unwrap().to_owned();
+                Pool::create::<Flaky, DummyRootMemento>(&path, FILE_SIZE, 1).unwrap()
+            };
+            let reported = Arc::new(AtomicUsize::new(0));
+            let config = {
+                let reported = reported.clone();
+                ExecConfig::new().max_retries(2).on_failure(move |f| {
+                    assert_eq!((f.tid, f.message()), (1, Some("flaky")));
+                    let _ = reported.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+
+            // Recovered after a transient failure
+            FAILURES.store(2, Ordering::SeqCst);
+            let pool = create("transient");
+            let res = pool.execute_with::<Flaky, DummyRootMemento>(&config);
+            assert_eq!(
+                res,
+                [Ok(ExecOutput {
+                    output: (),
+                    failures: 2,
+                    finished_before: false,
+                })]
+            );
+            assert_eq!(reported.load(Ordering::SeqCst), 2);
+
+            // A finished root memento does not run again.
+            let res = pool.execute_with::<Flaky, DummyRootMemento>(&config);
+            assert!(res[0].as_ref().unwrap().finished_before);
+            assert_eq!(RUNS.load(Ordering::SeqCst), 3);
+
+            // Permanently failing
+            RUNS.store(0, Ordering::SeqCst);
+            FAILURES.store(usize::MAX, Ordering::SeqCst);
+            let res = create("permanent").execute_with::<Flaky, DummyRootMemento>(&config);
+            assert_eq!(
+                res,
+                [Err(ExecError {
+                    tid: 1,
+                    attempts: 3,
+                    message: Some("flaky".to_owned()),
+                })]
+            );
+            assert_eq!(reported.load(Ordering::SeqCst), 5);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Consistency checker of pool
+//!
+//! `fsck` walks the object graph from the roots with `Collectable::filter` as the recovery GC does,
+//! but checks each pointer on the way instead of marking blocks.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use libc::c_void;
+
+use crate::pmem::alloc::{Collectable, GarbageCollection, PAllocator, PMEMAllocator};
+use crate::pmem::inspect::PoolView;
+use crate::pmem::pool::{Pool, PoolHandle, RootIdx, RootObj};
+use crate::*;
+
+/// Filter function of an object found by `fsck`
+pub(crate) type FsckFilter =
+    unsafe fn(ptr: *mut c_void, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle);
+
+unsafe fn filter_obj<T: Collectable>(
+    ptr: *mut c_void,
+    tid: usize,
+    gc: &mut GarbageCollection,
+    pool: &mut PoolHandle,
+) {
+    T::filter(&mut *(ptr as *mut T), tid, gc, pool)
+}
+
+/// Pointer that `fsck` cannot follow
+///
+/// Locations are offsets from the start of the pool. `owner` is the object holding the pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckError {
+    /// The pointer is out of the pool
+    OutOfRange {
+        /// Object holding the pointer
+        owner: usize,
+        /// Offset of the pointer
+        offset: usize,
+    },
+
+    /// The pointer is in the pool but not in an allocated block
+    Dangling {
+        /// Object holding the pointer
+        owner: usize,
+        /// Offset of the pointer
+        offset: usize,
+    },
+
+    /// Tag bits of a `PAtomic` are in an illegal state
+    ///
+    /// A descriptor (i.e. an ongoing detectable CAS) should be tagged with the tid of its owner,
+    /// and a tid should be one of the root mementos (`1..=nr_memento`).
+    IllegalTag {
+        /// Object holding the pointer
+        owner: usize,
+        /// Raw word of the `PAtomic`
+        word: usize,
+    },
+}
+
+impl fmt::Display for FsckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckError::OutOfRange { owner, offset } => {
+                write!(f, "{owner:#x}: pointer to {offset:#x} is out of the pool")
+            }
+            FsckError::Dangling { owner, offset } => {
+                write!(f, "{owner:#x}: pointer to {offset:#x} is dangling")
+            }
+            FsckError::IllegalTag { owner, word } => {
+                write!(f, "{owner:#x}: PAtomic {word:#x} has illegal tag bits")
+            }
+        }
+    }
+}
+
+/// Result of `PoolView::fsck`
+///
+/// Locations are offsets from the start of the pool.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Number of objects reachable from the roots (including the roots)
+    pub nr_objects: usize,
+
+    /// Pointers that cannot be followed
+    pub errors: Vec<FsckError>,
+
+    /// Objects pointed to by more than one object: (object, owners)
+    ///
+    /// It is not always a bug. e.g. the checkpoint of a memento may keep a node that is also in a data structure.
+    pub shared: Vec<(usize, Vec<usize>)>,
+
+    /// Pointers closing a cycle: (owner, object)
+    ///
+    /// None of the data structures in this crate has a cycle, but a user-defined one may.
+    pub cycles: Vec<(usize, usize)>,
+}
+
+impl FsckReport {
+    /// Whether no pointer is broken
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "fsck: {} objects, {} errors, {} shared, {} cycles",
+            self.nr_objects,
+            self.errors.len(),
+            self.shared.len(),
+            self.cycles.len()
+        )?;
+        for e in &self.errors {
+            writeln!(f, "  error: {e}")?;
+        }
+        for (obj, owners) in &self.shared {
+            writeln!(f, "  shared: {obj:#x} is owned by {owners:#x?}")?;
+        }
+        for (owner, obj) in &self.cycles {
+            writeln!(f, "  cycle: {owner:#x} -> {obj:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// State of `fsck` in the pool being checked
+///
+/// While it is set in `PoolHandle`, `Collectable::mark` hands objects over to it instead of the allocator,
+/// and `PPtr`/`PAtomic` check themselves before being followed.
+pub(crate) struct Fsck {
+    start: usize,
+    end: usize,
+    nr_memento: usize,
+
+    /// Object being filtered
+    current: usize,
+
+    /// Objects found while filtering `current`: (address, tid, filter)
+    children: Vec<(usize, usize, FsckFilter)>,
+
+    /// Owners of each object
+    owners: HashMap<usize, Vec<usize>>,
+
+    errors: Vec<FsckError>,
+}
+
+// `FsckFilter` does not implement `Debug`, so implement it manually.
+impl fmt::Debug for Fsck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fsck")
+            .field("current", &self.current)
+            .field("owners", &self.owners.len())
+            .field("errors", &self.errors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Fsck {
+    fn new(start: usize, end: usize, nr_memento: usize) -> Self {
+        Self {
+            start,
+            end,
+            nr_memento,
+            current: 0,
+            children: Vec::new(),
+            owners: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Check the tag bits of a `PAtomic`, returning `true` if its pointer can be followed
+    pub(crate) fn check_tag(&mut self, word: usize, desc_bit: usize, tid: usize) -> bool {
+        if (desc_bit != 0 && tid == 0) || tid > self.nr_memento {
+            self.errors.push(FsckError::IllegalTag {
+                owner: self.current,
+                word,
+            });
+            return false;
+        }
+
+        // A descriptor holds a sequence number, not a pointer.
+        desc_bit == 0
+    }
+
+    /// Check a non-null pointer, returning `true` if it can be followed
+    pub(crate) fn check_ptr(&mut self, allocator: &PMEMAllocator, offset: usize) -> bool {
+        let owner = self.current;
+        let addr = self.start.wrapping_add(offset);
+        if addr < self.start || addr >= self.end {
+            self.errors.push(FsckError::OutOfRange { owner, offset });
+            return false;
+        }
+        if unsafe { allocator.block_of(addr as *const c_void) }.is_none() {
+            self.errors.push(FsckError::Dangling { owner, offset });
+            return false;
+        }
+        true
+    }
+
+    /// Record `s` as an object owned by the object being filtered
+    pub(crate) fn visit<T: Collectable>(&mut self, s: &mut T, tid: usize) {
+        let addr = s as *mut T as usize;
+        let owners = self.owners.entry(addr - self.start).or_default();
+        if !owners.contains(&self.current) {
+            owners.push(self.current);
+        }
+        self.children.push((addr, tid, filter_obj::<T>));
+    }
+
+    /// Walk the object graph from `roots` (address, tid, filter) in depth-first order
+    unsafe fn run(pool: &mut PoolHandle, roots: Vec<(usize, usize, FsckFilter)>) -> FsckReport {
+        let start = pool.start();
+
+        PMEMAllocator::with_gc_stub(|gc| {
+            // Filter an object and return the objects it points to.
+            let mut filter = |pool: &mut PoolHandle, (addr, tid, f): (usize, usize, FsckFilter)| {
+                pool.fsck.as_mut().unwrap().current = addr - start;
+                f(addr as *mut c_void, tid, gc, pool);
+                std::mem::take(&mut pool.fsck.as_mut().unwrap().children).into_iter()
+            };
+
+            // Objects whose descendants are being visited
+            let mut on_path = HashSet::new();
+            let mut visited = HashSet::new();
+            let mut cycles = Vec::new();
+            for root in roots {
+                if !visited.insert(root.0) {
+                    continue;
+                }
+                let _ = on_path.insert(root.0);
+                let mut stack = vec![(root.0, filter(pool, root))];
+                while let Some((addr, children)) = stack.last_mut() {
+                    let addr = *addr;
+                    match children.next() {
+                        Some(child) => {
+                            if on_path.contains(&child.0) {
+                                cycles.push((addr - start, child.0 - start));
+                            } else if visited.insert(child.0) {
+                                let _ = on_path.insert(child.0);
+                                let children = filter(pool, child);
+                                stack.push((child.0, children));
+                            }
+                        }
+                        None => {
+                            let _ = on_path.remove(&addr);
+                            let _ = stack.pop();
+                        }
+                    }
+                }
+            }
+
+            let fsck = pool.fsck.take().unwrap();
+            let mut shared = fsck
+                .owners
+                .into_iter()
+                .filter(|(_, owners)| owners.len() > 1)
+                .collect::<Vec<_>>();
+            shared.sort_unstable();
+            FsckReport {
+                nr_objects: visited.len(),
+                errors: fsck.errors,
+                shared,
+                cycles,
+            }
+        })
+    }
+}
+
+impl PoolView {
+    /// Check the pointers reachable from the roots of the pool
+    ///
+    /// It walks the root object and root mementos with their `Collectable::filter` as the recovery GC does,
+    /// and reports pointers out of the pool, pointers to unallocated blocks, and `PAtomic`s with illegal tags,
+    /// which are not followed. It also reports objects with more than one owner and cycles.
+    ///
+    /// Filters may write to the objects (e.g. to fix up a pointer), which changes only the copy of the pool.
+    ///
+    /// O: root obj
+    /// M: root memento(s)
+    ///
+    /// # Errors
+    ///
+    /// * Fail with `LayoutError` if `O` or `M` is not the type the pool was created with.
+    pub fn fsck<O: RootObj<M>, M: Memento>(&mut self) -> Result<FsckReport, Error> {
+        let pool = unsafe { self.pool_mut() };
+        unsafe { Pool::check_layout::<O, M>(&pool.allocator) }
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let nr_memento = unsafe { *(pool.get_root(RootIdx::NrMemento as u64) as *const usize) };
+        let mut roots = Vec::new();
+        let root_obj = unsafe { pool.get_root(RootIdx::RootObj as u64) };
+        if !root_obj.is_null() {
+            // Tid of the root object is the same as that of the recovery GC.
+            let tid = (RootIdx::RootObj as usize).wrapping_sub(RootIdx::MementoStart as usize);
+            roots.push((root_obj as usize, tid, filter_obj::<O> as FsckFilter));
+        }
+        for tid in 1..=nr_memento {
+            let mmt = unsafe { pool.get_root(RootIdx::MementoStart as u64 + tid as u64) };
+            if !mmt.is_null() {
+                roots.push((mmt as usize, tid, filter_obj::<M> as FsckFilter));
+            }
+        }
+
+        pool.fsck = Some(Fsck::new(pool.start(), pool.end(), nr_memento));
+        Ok(unsafe { Fsck::run(pool, roots) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pepoch::PShared;
+
+    #[test]
+    fn check_tag() {
+        let mut fsck = Fsck::new(0, 1 << 20, 2);
+
+        let ptr = PShared::<usize>::null();
+        assert!(fsck.check_tag(0, ptr.desc_bit(), ptr.tid()));
+        let ptr = ptr.with_tid(2);
+        assert!(fsck.check_tag(0, ptr.desc_bit(), ptr.tid()));
+        assert!(fsck.errors.is_empty());
+
+        // A descriptor is not followed.
+        let desc = ptr.with_desc_bit(1);
+        assert!(!fsck.check_tag(1, desc.desc_bit(), desc.tid()));
+        assert!(fsck.errors.is_empty());
+
+        // A descriptor without tid, and a tid of no root memento
+        let no_tid = desc.with_tid(0);
+        assert!(!fsck.check_tag(2, no_tid.desc_bit(), no_tid.tid()));
+        let bad_tid = ptr.with_tid(3);
+        assert!(!fsck.check_tag(3, bad_tid.desc_bit(), bad_tid.tid()));
+        assert_eq!(
+            fsck.errors,
+            [
+                FsckError::IllegalTag { owner: 0, word: 2 },
+                FsckError::IllegalTag { owner: 0, word: 3 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Inspection of a closed pool
+//!
+//! `PoolView` maps a copy of a pool without recovery, so that a pool left by a crash can be looked into as it is.
+
+use std::ffi::CString;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::Ordering;
+
+use tempfile::TempDir;
+
+use crate::pmem::alloc::{PAllocator, PMEMAllocator};
+use crate::pmem::layout::{PoolLayout, TypeFingerprint};
+use crate::pmem::output::Outputs;
+use crate::pmem::pool::{Pool, PoolHandle, RootIdx};
+use crate::pmem::stats::PoolStats;
+use crate::pmem::superblock::SuperBlockError;
+
+/// Data structure whose contents can be summarized by `memento-inspect`
+pub trait Inspect {
+    /// Summary of the contents
+    ///
+    /// It is called on a pool that is not running and has not been recovered, so it should not write anything
+    /// and should tolerate operations interrupted by a crash.
+    fn summary(&self, pool: &PoolHandle) -> String;
+}
+
+/// Read-only view of a closed pool
+#[derive(Debug)]
+pub struct PoolView {
+    pool: *mut PoolHandle,
+    superblock: Result<(), SuperBlockError>,
+
+    /// Directory holding the copy of the pool
+    _dir: TempDir,
+}
+
+impl PoolView {
+    /// Open a view of the pool in `filepath`
+    ///
+    /// The pool file(s) are copied to a temporary directory and the copy is mapped without recovery.
+    /// Thus the view shows the pool as it was left (e.g. by a crash), and the pool itself is never written.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if pool does not exist in `filepath`
+    /// * Fail if not called with the size of the pool (forced by Ralloc)
+    /// * Fail with `SuperBlockError` if the file is not a pool or its creation was not completed.
+    ///   (A pool whose root table does not match its checksum is opened, and the mismatch is reported.)
+    pub fn open(filepath: &str, size: usize) -> Result<Self, Error> {
+        if !PMEMAllocator::exists(filepath) {
+            return Err(Error::new(ErrorKind::NotFound, "Pool does not exist."));
+        }
+
+        let dir = tempfile::tempdir()?;
+        let copy = dir.path().join("pool").to_str().unwrap().to_owned();
+        PMEMAllocator::copy(filepath, &copy)?;
+
+        let copy = CString::new(copy).expect("CString::new failed");
+        let allocator = unsafe { PMEMAllocator::open(copy.as_ptr(), size as u64)? };
+
+        let superblock = unsafe { Pool::check_superblock(&allocator) };
+        let checked = match superblock {
+            Ok(()) | Err(SuperBlockError::Checksum { .. }) => {
+                let has_metadata = [
+                    RootIdx::CASHelpArr,
+                    RootIdx::CASHelpDescArr,
+                    RootIdx::NrMemento,
+                ]
+                .into_iter()
+                .all(|ix| unsafe { !allocator.get_root(ix as u64).is_null() });
+                if has_metadata {
+                    Ok(())
+                } else {
+                    Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Pool metadata is missing.",
+                    ))
+                }
+            }
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e)),
+        };
+        if let Err(e) = checked {
+            unsafe { allocator.close(allocator.mmapped_addr(), size) };
+            return Err(e);
+        }
+
+        Ok(Self {
+            pool: unsafe { PoolHandle::new_view(allocator, size) },
+            superblock,
+            _dir: dir,
+        })
+    }
+
+    /// Handle of the copy of the pool (e.g. to dereference pointers in the root object)
+    ///
+    /// Root mementos cannot be run with it.
+    pub fn pool(&self) -> &'static PoolHandle {
+        unsafe { &*self.pool }
+    }
+
+    /// Handle of the copy of the pool to walk it with `Collectable::filter`, which takes it mutably
+    pub(crate) unsafe fn pool_mut(&mut self) -> &'static mut PoolHandle {
+        &mut *self.pool
+    }
+
+    /// Root object
+    ///
+    /// # Safety
+    ///
+    /// The root object of the pool should be of type `O`.
+    pub unsafe fn root_obj<O>(&self) -> Option<&O> {
+        (self.pool().get_root(RootIdx::RootObj as u64) as *const O).as_ref()
+    }
+
+    /// Report of the metadata and heap usage of the pool
+    pub fn report(&self) -> PoolReport {
+        let pool = self.pool();
+        unsafe {
+            let layout = (pool.get_root(RootIdx::Layout as u64) as *const PoolLayout).as_ref();
+            let nr_memento = *(pool.get_root(RootIdx::NrMemento as u64) as *const usize);
+            let clearing_flags = (1..=nr_memento)
+                .map(|tid| {
+                    let flag = pool.get_root(RootIdx::MementoClearingFlagStart as u64 + tid as u64);
+                    (flag as *const bool).as_ref().copied()
+                })
+                .collect();
+
+            let help = pool.exec_info.cas_info.help;
+            let help_desc = pool.exec_info.cas_info.help_desc;
+            let cas_help = (1..=nr_memento)
+                .map(|tid| {
+                    let [t0, t1] = &help[tid].inner;
+                    [t0.load(Ordering::SeqCst), t1.load(Ordering::SeqCst)]
+                })
+                .collect();
+            let cas_help_desc = (1..=nr_memento).map(|tid| help_desc[tid].seq()).collect();
+            let outputs = (pool.get_root(RootIdx::Outputs as u64) as *const Outputs<()>).as_ref();
+            let finished = (1..=nr_memento)
+                .map(|tid| outputs.map_or(false, |o| o.is_finished(tid)))
+                .collect();
+
+            PoolReport {
+                superblock: self.superblock,
+                format_version: layout.map(|l| l.version()),
+                root_obj: layout.map(|l| l.root_obj()),
+                root_obj_version: layout.map(|l| l.root_obj_version()),
+                root_memento: layout.map(|l| l.root_mmt()),
+                max_threads: pool.max_threads(),
+                nr_memento,
+                clearing_flags,
+                cas_help,
+                cas_help_desc,
+                finished,
+                stats: pool.stats(),
+            }
+        }
+    }
+}
+
+/// Report of the metadata and heap usage of a pool
+///
+/// Per-thread items are for thread `1..=nr_memento`.
+#[derive(Debug, Clone)]
+pub struct PoolReport {
+    /// Result of checking the superblock
+    pub superblock: Result<(), SuperBlockError>,
+
+    /// Pool format version
+    pub format_version: Option<u64>,
+
+    /// Fingerprint of the root object type
+    pub root_obj: Option<TypeFingerprint>,
+
+    /// Version of the root object (`RootObj::VERSION`)
+    pub root_obj_version: Option<u64>,
+
+    /// Fingerprint of the root memento type
+    pub root_memento: Option<TypeFingerprint>,
+
+    /// Maximum number of threads
+    pub max_threads: usize,
+
+    /// Number of root mementos
+    pub nr_memento: usize,
+
+    /// Clearing flag of each root memento (`None` if it is not set)
+    pub clearing_flags: Vec<Option<bool>>,
+
+    /// Timestamps of CAS helping of each thread (`CasHelpArr`)
+    pub cas_help: Vec<[u64; 2]>,
+
+    /// Sequence number of the help descriptor of each thread (`CasHelpDescArr`)
+    pub cas_help_desc: Vec<usize>,
+
+    /// Whether each root memento has finished (i.e. its output is in the pool)
+    pub finished: Vec<bool>,
+
+    /// Heap usage
+    ///
+    /// It is taken before recovery: Ralloc counts blocks held by a crashed process as live,
+    /// and the mmap allocator counts all blocks in chunks in use as live.
+    pub stats: PoolStats,
+}
+
+impl fmt::Display for PoolReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.superblock {
+            Ok(()) => writeln!(f, "superblock: ok")?,
+            Err(e) => writeln!(f, "superblock: {e}")?,
+        }
+        match self.format_version {
+            Some(version) => writeln!(f, "format version: {version}")?,
+            None => writeln!(f, "format version: (no layout header)")?,
+        }
+        if let (Some(obj), Some(version), Some(mmt)) =
+            (self.root_obj, self.root_obj_version, self.root_memento)
+        {
+            writeln!(f, "root object type: {obj:?} (version {version})")?;
+            writeln!(f, "root memento type: {mmt:?}")?;
+        }
+        writeln!(f, "max_threads: {}", self.max_threads)?;
+        writeln!(f, "nr_memento: {}", self.nr_memento)?;
+
+        writeln!(f, "threads:")?;
+        for tid in 1..=self.nr_memento {
+            let flag = match self.clearing_flags[tid - 1] {
+                Some(flag) => flag.to_string(),
+                None => "-".to_owned(),
+            };
+            let [t0, t1] = self.cas_help[tid - 1];
+            writeln!(
+                f,
+                "  [{tid}] clearing: {flag}, cas help: ({t0}, {t1}), help desc seq: {}, finished: {}",
+                self.cas_help_desc[tid - 1],
+                self.finished[tid - 1]
+            )?;
+        }
+
+        let stats = &self.stats;
+        writeln!(
+            f,
+            "heap: {} used, {} free of {} bytes (fragmentation {:.1}%)",
+            stats.used,
+            stats.free,
+            stats.heap_size,
+            stats.fragmentation() * 100.0
+        )?;
+        for class in &stats.classes {
+            let size = match class.block_size {
+                0 => "large".to_owned(),
+                size => size.to_string(),
+            };
+            writeln!(
+                f,
+                "  {size:>8}: {} blocks, {} bytes",
+                class.live_blocks, class.live_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
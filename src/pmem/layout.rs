@@ -0,0 +1,252 @@
+//! Layout header of pool
+//!
+//! `Pool::create` records which root object and root memento types the pool is made of,
+//! so that `Pool::open` can reject a pool that is opened with other types.
+
+use std::{
+    any::type_name,
+    fmt,
+    mem::{align_of, size_of},
+};
+
+/// Version of the pool format. Increase it whenever the persistent layout of pool metadata changes.
+pub const POOL_FORMAT_VERSION: u64 = 11;
+
+/// Fingerprint of a persistent type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct TypeFingerprint {
+    /// Hash of the type name
+    pub hash: u64,
+
+    /// Size of the type
+    pub size: u64,
+
+    /// Alignment of the type
+    pub align: u64,
+}
+
+impl TypeFingerprint {
+    /// Fingerprint of `T`
+    ///
+    /// NOTE: The type name is not guaranteed to be stable across compiler versions,
+    /// so a pool might be rejected after upgrading the compiler.
+    pub fn of<T>() -> Self {
+        Self {
+            hash: fnv1a(type_name::<T>().as_bytes()),
+            size: size_of::<T>() as u64,
+            align: align_of::<T>() as u64,
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash (stable across processes unlike `std::hash`)
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes
+        .iter()
+        .fold(OFFSET_BASIS, |h, b| (h ^ u64::from(*b)).wrapping_mul(PRIME))
+}
+
+/// Layout header of pool stored at `RootIdx::Layout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) struct PoolLayout {
+    version: u64,
+    root_obj_version: u64,
+    root_obj: TypeFingerprint,
+    root_mmt: TypeFingerprint,
+}
+
+impl PoolLayout {
+    /// Layout of pool whose root object is `O` of `root_obj_version` and root memento is `M`
+    pub(crate) fn new<O, M>(root_obj_version: u64) -> Self {
+        Self {
+            version: POOL_FORMAT_VERSION,
+            root_obj_version,
+            root_obj: TypeFingerprint::of::<O>(),
+            root_mmt: TypeFingerprint::of::<M>(),
+        }
+    }
+
+    /// Pool format version
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Version of the root object (`RootObj::VERSION`)
+    pub(crate) fn root_obj_version(&self) -> u64 {
+        self.root_obj_version
+    }
+
+    /// Fingerprint of the root object type
+    pub(crate) fn root_obj(&self) -> TypeFingerprint {
+        self.root_obj
+    }
+
+    /// Fingerprint of the root memento type
+    pub(crate) fn root_mmt(&self) -> TypeFingerprint {
+        self.root_mmt
+    }
+
+    /// Check if the pool of layout `self` can be opened with `expected` layout
+    pub(crate) fn check(&self, expected: &Self) -> Result<(), LayoutError> {
+        if self.version != expected.version {
+            return Err(LayoutError::Version {
+                expected: expected.version,
+                found: self.version,
+            });
+        }
+        if self.root_obj_version != expected.root_obj_version {
+            return Err(LayoutError::RootObjVersion {
+                expected: expected.root_obj_version,
+                found: self.root_obj_version,
+            });
+        }
+        if self.root_obj != expected.root_obj {
+            return Err(LayoutError::RootObj {
+                expected: expected.root_obj,
+                found: self.root_obj,
+            });
+        }
+        if self.root_mmt != expected.root_mmt {
+            return Err(LayoutError::RootMemento {
+                expected: expected.root_mmt,
+                found: self.root_mmt,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check if the pool of layout `self` can be migrated to `expected` layout from the old root object
+    /// `old` of version `from`
+    ///
+    /// The type name of `old` is not checked, since the old type is usually renamed (e.g. moved to a `v1` module).
+    pub(crate) fn check_old(
+        &self,
+        expected: &Self,
+        from: u64,
+        old: TypeFingerprint,
+    ) -> Result<(), LayoutError> {
+        self.check(&Self {
+            root_obj_version: from,
+            root_obj: TypeFingerprint {
+                hash: self.root_obj.hash,
+                ..old
+            },
+            ..*expected
+        })
+    }
+}
+
+/// Error of opening a pool with a layout different from the one specified during `Pool::create`
+///
+/// `Pool::open` returns it wrapped in `std::io::Error` of `ErrorKind::InvalidData`.
+/// Use `get_ref()` and `downcast_ref::<LayoutError>()` to inspect it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The pool has no layout header (e.g. it was created by an older version)
+    Missing,
+
+    /// The pool format version is different
+    Version {
+        /// Version of this library
+        expected: u64,
+        /// Version recorded in the pool
+        found: u64,
+    },
+
+    /// The version of the root object is different and the pool is not migrated from it
+    RootObjVersion {
+        /// `RootObj::VERSION` of the root object type given to `Pool::open`
+        expected: u64,
+        /// Version recorded in the pool
+        found: u64,
+    },
+
+    /// The root object type is different
+    RootObj {
+        /// Fingerprint of the root object type given to `Pool::open`
+        expected: TypeFingerprint,
+        /// Fingerprint recorded in the pool
+        found: TypeFingerprint,
+    },
+
+    /// The root memento type is different
+    RootMemento {
+        /// Fingerprint of the root memento type given to `Pool::open`
+        expected: TypeFingerprint,
+        /// Fingerprint recorded in the pool
+        found: TypeFingerprint,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Missing => write!(f, "pool has no layout header"),
+            LayoutError::Version { expected, found } => {
+                write!(f, "pool format version {found} (expected {expected})")
+            }
+            LayoutError::RootObjVersion { expected, found } => {
+                write!(f, "root object version {found} (expected {expected})")
+            }
+            LayoutError::RootObj { expected, found } => {
+                write!(f, "root object type {found:?} (expected {expected:?})")
+            }
+            LayoutError::RootMemento { expected, found } => {
+                write!(f, "root memento type {found:?} (expected {expected:?})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::tests::{DummyRootMemento, DummyRootObj};
+
+    #[test]
+    fn check_layout() {
+        let layout = PoolLayout::new::<DummyRootObj, DummyRootMemento>(0);
+        assert_eq!(layout.check(&layout), Ok(()));
+
+        let other = PoolLayout::new::<DummyRootObj, usize>(0);
+        assert!(matches!(
+            layout.check(&other),
+            Err(LayoutError::RootMemento { .. })
+        ));
+
+        let other = PoolLayout::new::<usize, DummyRootMemento>(0);
+        assert!(matches!(
+            layout.check(&other),
+            Err(LayoutError::RootObj { .. })
+        ));
+    }
+
+    #[test]
+    fn check_old_layout() {
+        let layout = PoolLayout::new::<usize, DummyRootMemento>(1);
+        let expected = PoolLayout::new::<(usize, usize), DummyRootMemento>(2);
+        assert!(matches!(
+            layout.check(&expected),
+            Err(LayoutError::RootObjVersion { .. })
+        ));
+
+        // The old type may be renamed (here `u64`), but its version and size should match.
+        let old = TypeFingerprint::of::<u64>();
+        assert_eq!(layout.check_old(&expected, 1, old), Ok(()));
+        assert!(matches!(
+            layout.check_old(&expected, 0, old),
+            Err(LayoutError::RootObjVersion { .. })
+        ));
+        assert!(matches!(
+            layout.check_old(&expected, 1, TypeFingerprint::of::<u8>()),
+            Err(LayoutError::RootObj { .. })
+        ));
+    }
+}
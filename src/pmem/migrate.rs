@@ -0,0 +1,341 @@
+//! Migration of root object
+//!
+//! A root object declares its version with `RootObj::VERSION`. When its persistent layout changes, increase the
+//! version and open existing pools with `Pool::open_with_migration`, which rewrites the old root object into the new
+//! one with a user-supplied `Migration`.
+//!
+//! The migration runs as a memento kept at `RootIdx::Migration`, so an interrupted migration resumes in recovery
+//! mode on the next open. Once it returns, the new root object is recorded in the log, and the root table, layout
+//! header, and superblock checksum are switched by redoing the log until it is removed.
+
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::c_void;
+
+use crate::ploc::Handle;
+use crate::pmem::alloc::{Collectable, GarbageCollection, PAllocator, PMEMAllocator};
+use crate::pmem::layout::{LayoutError, PoolLayout, TypeFingerprint};
+use crate::pmem::ll::persist_obj;
+use crate::pmem::output::Outputs;
+use crate::pmem::pool::{PoolHandle, RootIdx, RootObj};
+use crate::pmem::ptr::PPtr;
+use crate::pmem::superblock::{root_table_checksum, SuperBlock};
+use crate::Memento;
+use etrace::some_or;
+
+/// Migration of the root object from an old version to `O`
+///
+/// # Example
+///
+/// ```no_run
+/// # use memento::ploc::{Checkpoint, Handle};
+/// # use memento::pmem::*;
+/// # use memento::*;
+/// mod v1 {
+///     # use memento::pmem::*;
+///     # use memento::*;
+///     #[derive(Collectable)]
+///     pub struct Counter {
+///         pub count: usize,
+///     }
+/// }
+///
+/// #[derive(Collectable)]
+/// struct Counter {
+///     count: usize,
+///     max: usize,
+/// }
+///
+/// impl RootObj<usize> for Counter {
+///     const VERSION: u64 = 2;
+///
+///     // ...
+/// #   fn run(&self, _: &mut usize, _: &Handle) {}
+/// }
+/// # impl PDefault for Counter {
+/// #     fn pdefault(_: &Handle) -> Self {
+/// #         Self { count: 0, max: usize::MAX }
+/// #     }
+/// # }
+///
+/// #[derive(Default, Collectable, Memento)]
+/// struct CounterV1ToV2 {
+///     new: Checkpoint<PPtr<Counter>>,
+/// }
+///
+/// impl Migration<Counter, usize> for CounterV1ToV2 {
+///     type Old = v1::Counter;
+///     const FROM: u64 = 1;
+///
+///     fn migrate(&mut self, old: &v1::Counter, handle: &Handle) -> PPtr<Counter> {
+///         self.new.checkpoint(
+///             || {
+///                 let new = handle.pool.alloc::<Counter>();
+///                 let obj = unsafe { new.deref_mut(handle.pool) };
+///                 *obj = Counter { count: old.count, max: usize::MAX };
+///                 persist_obj(obj, true);
+///                 new
+///             },
+///             handle,
+///         )
+///     }
+/// }
+///
+/// let pool = Pool::open_with_migration::<Counter, usize, CounterV1ToV2>(
+///     "foo.pool",
+///     8 * 1024 * 1024 * 1024,
+///     &NamedRoots::new(),
+/// )
+/// .unwrap();
+/// ```
+pub trait Migration<O: RootObj<M>, M: Memento>: Memento {
+    /// Root object type of the old version
+    ///
+    /// Only its size and alignment are checked against the pool, so it may be a copy of the old type under
+    /// another name. The recovery GC traces the pool with its `Collectable` until the migration is committed.
+    type Old: Collectable;
+
+    /// `RootObj::VERSION` of the old root object
+    const FROM: u64;
+
+    /// Rewrite the old root object into a new one
+    ///
+    /// If a crash occurs, it is called again with `handle.rec` set on the next open, so its steps should be
+    /// detectable (e.g. with `Checkpoint`). The new root object should be checkpointed as well, or the recovery GC
+    /// reclaims it. The old root object is reclaimed by the recovery GC of the next open, except for the blocks
+    /// reachable from the new one.
+    fn migrate(&mut self, old: &Self::Old, handle: &Handle) -> PPtr<O>;
+}
+
+/// Redo log of a migration, which is the header of `MigrationLog`
+#[derive(Debug)]
+#[repr(C)]
+struct MigrationHeader {
+    /// Offset of the new root object (0 until the migration is committed)
+    new_root: AtomicUsize,
+
+    /// Layout header of the pool after the migration
+    layout: PoolLayout,
+}
+
+/// Migration stored at `RootIdx::Migration`
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct MigrationLog<G> {
+    header: MigrationHeader,
+    mmt: G,
+}
+
+impl<G: Collectable> Collectable for MigrationLog<G> {
+    fn filter(log: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        G::filter(&mut log.mmt, tid, gc, pool);
+    }
+}
+
+/// Check if the pool mapped by `allocator` can be migrated to `O` with `G`
+pub(crate) unsafe fn check_old<O: RootObj<M>, M: Memento, G: Migration<O, M>>(
+    allocator: &PMEMAllocator,
+) -> Result<(), LayoutError> {
+    let layout = (allocator.get_root(RootIdx::Layout as u64) as *const PoolLayout)
+        .as_ref()
+        .ok_or(LayoutError::Missing)?;
+    layout.check_old(
+        &PoolLayout::new::<O, M>(O::VERSION),
+        G::FROM,
+        TypeFingerprint::of::<G::Old>(),
+    )
+}
+
+/// Log of the migration of the pool mapped by `allocator`, which is created if it does not exist
+///
+/// Return the log and whether it existed (i.e. the migration is resumed).
+pub(crate) unsafe fn start_migration<O: RootObj<M>, M: Memento, G: Migration<O, M>>(
+    allocator: &PMEMAllocator,
+) -> Result<(*mut MigrationLog<G>, bool), Error> {
+    let log = allocator.get_root(RootIdx::Migration as u64) as *mut MigrationLog<G>;
+    if !log.is_null() {
+        return Ok((log, true));
+    }
+
+    let log = allocator.malloc(mem::size_of::<MigrationLog<G>>() as u64) as *mut MigrationLog<G>;
+    if log.is_null() {
+        return Err(Error::new(ErrorKind::OutOfMemory, "Out of memory"));
+    }
+    // Outputs of the old version are forgotten, since the program has changed.
+    (*(allocator.get_root(RootIdx::Outputs as u64) as *const Outputs<()>)).clear();
+
+    log.write(MigrationLog {
+        header: MigrationHeader {
+            new_root: AtomicUsize::new(0),
+            layout: PoolLayout::new::<O, M>(O::VERSION),
+        },
+        mmt: G::default(),
+    });
+    persist_obj(log.as_mut().unwrap(), true);
+    let _prev = allocator.set_root(log as *mut c_void, RootIdx::Migration as u64);
+    Ok((log, false))
+}
+
+/// Run the migration in `log` and commit it
+pub(crate) unsafe fn run_migration<O: RootObj<M>, M: Memento, G: Migration<O, M>>(
+    pool: &'static PoolHandle,
+    log: *mut MigrationLog<G>,
+    resumed: bool,
+) {
+    let log = &mut *log;
+    let old = &*(pool.get_root(RootIdx::RootObj as u64) as *const G::Old);
+
+    let handle = Handle::new(1, pool.pin(), pool);
+    handle.rec.store(resumed, Ordering::SeqCst);
+    let new = log.mmt.migrate(old, &handle);
+    persist_obj(new.deref(pool), true);
+
+    log.header
+        .new_root
+        .store(new.into_offset(), Ordering::SeqCst);
+    persist_obj(&log.header.new_root, true);
+    finish_migration(&pool.allocator);
+}
+
+/// Switch the pool mapped by `allocator` to the new root object if a migration is committed
+///
+/// It is idempotent, so it is redone on every open until the log is removed. Since it changes the root table,
+/// it should be called before the superblock is checked.
+pub(crate) unsafe fn finish_migration(allocator: &PMEMAllocator) {
+    let header = some_or!(
+        (allocator.get_root(RootIdx::Migration as u64) as *const MigrationHeader).as_ref(),
+        return
+    );
+    let new_root = header.new_root.load(Ordering::SeqCst);
+    if new_root == 0 {
+        // Not committed: it is resumed by `Pool::open_with_migration`.
+        return;
+    }
+
+    let layout = allocator.get_root(RootIdx::Layout as u64) as *mut PoolLayout;
+    layout.write(header.layout);
+    persist_obj(layout.as_mut().unwrap(), true);
+
+    let new_root = allocator.mmapped_addr() + new_root;
+    let _prev = allocator.set_root(new_root as *mut c_void, RootIdx::RootObj as u64);
+    let sb = &mut *(allocator.get_root(RootIdx::SuperBlock as u64) as *mut SuperBlock);
+    sb.set_checksum(root_table_checksum(allocator, sb.nr_memento()));
+
+    // The log is reclaimed by the recovery GC.
+    let _prev = allocator.set_root(ptr::null_mut(), RootIdx::Migration as u64);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ploc::Checkpoint;
+    #[cfg(feature = "mmap")]
+    use crate::pmem::pool::Pool;
+    #[cfg(feature = "mmap")]
+    use crate::pmem::roots::NamedRoots;
+    use crate::test_utils::tests::DummyRootMemento;
+    use crate::*;
+
+    #[derive(Collectable)]
+    struct V1 {
+        count: usize,
+    }
+
+    impl PDefault for V1 {
+        fn pdefault(_: &Handle) -> Self {
+            Self { count: 42 }
+        }
+    }
+
+    impl RootObj<DummyRootMemento> for V1 {
+        const VERSION: u64 = 1;
+
+        fn run(&self, _: &mut DummyRootMemento, _: &Handle) {}
+    }
+
+    #[derive(Default, Collectable)]
+    struct V2 {
+        count: usize,
+        max: usize,
+    }
+
+    impl PDefault for V2 {
+        fn pdefault(_: &Handle) -> Self {
+            Self {
+                count: 0,
+                max: usize::MAX,
+            }
+        }
+    }
+
+    impl RootObj<DummyRootMemento> for V2 {
+        const VERSION: u64 = 2;
+
+        fn run(&self, _: &mut DummyRootMemento, _: &Handle) {}
+    }
+
+    #[derive(Default, Collectable, Memento)]
+    struct V1ToV2 {
+        new: Checkpoint<PPtr<V2>>,
+    }
+
+    impl Migration<V2, DummyRootMemento> for V1ToV2 {
+        type Old = V1;
+        const FROM: u64 = 1;
+
+        fn migrate(&mut self, old: &V1, handle: &Handle) -> PPtr<V2> {
+            self.new.checkpoint(
+                || {
+                    let new = handle.pool.alloc::<V2>();
+                    let obj = unsafe { new.deref_mut(handle.pool) };
+                    *obj = V2 {
+                        count: old.count,
+                        max: usize::MAX,
+                    };
+                    persist_obj(obj, true);
+                    new
+                },
+                handle,
+            )
+        }
+    }
+
+    // Pools are opened from snapshots, since a pool cannot be closed.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn migrate() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let pool = Pool::create::<V1, DummyRootMemento>(&path("v1"), FILE_SIZE, 0).unwrap();
+        pool.snapshot(&path("copy")).unwrap();
+
+        let err = Pool::open::<V2, DummyRootMemento>(&path("copy"), FILE_SIZE).unwrap_err();
+        assert!(matches!(
+            err.get_ref().unwrap().downcast_ref::<LayoutError>(),
+            Some(LayoutError::RootObjVersion {
+                expected: 2,
+                found: 1
+            })
+        ));
+
+        let roots = NamedRoots::new();
+        let pool = Pool::open_with_migration::<V2, DummyRootMemento, V1ToV2>(
+            &path("copy"),
+            FILE_SIZE,
+            &roots,
+        )
+        .unwrap();
+        let root = unsafe { &*(pool.get_root(RootIdx::RootObj as u64) as *const V2) };
+        assert_eq!((root.count, root.max), (42, usize::MAX));
+
+        // The migrated pool is opened without migration.
+        pool.snapshot(&path("v2")).unwrap();
+        let _ = Pool::open::<V2, DummyRootMemento>(&path("v2"), FILE_SIZE).unwrap();
+    }
+}
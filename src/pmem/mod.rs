@@ -1,13 +1,30 @@
 //! Persistent location
 
 pub mod alloc;
-pub mod global;
+pub mod exec;
+pub mod fsck;
+pub mod inspect;
+pub mod layout;
 pub mod ll;
+pub mod migrate;
+pub mod output;
 pub mod pool;
 pub mod ptr;
+pub mod roots;
+pub mod shutdown;
+pub mod slots;
+pub mod stats;
+pub mod superblock;
 
 pub use alloc::*;
-pub use global::*;
+pub use exec::*;
+pub use fsck::*;
+pub use inspect::*;
+pub use layout::*;
 pub use ll::*;
+pub use migrate::*;
 pub use pool::*;
 pub use ptr::*;
+pub use roots::*;
+pub use stats::*;
+pub use superblock::*;
@@ -0,0 +1,88 @@
+//! Outputs of root mementos
+//!
+//! The output of `RootObj::run` is made durable once it returns, so that a root memento that has finished
+//! returns its output without running again after the pool is reopened.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ploc::NR_MAX_THREADS;
+use crate::pmem::alloc::{Collectable, GarbageCollection, PAllocator};
+use crate::pmem::ll::persist_obj;
+use crate::pmem::pool::{PoolHandle, RootIdx, RootObj};
+use crate::Memento;
+
+/// Outputs of root mementos stored at `RootIdx::Outputs`
+///
+/// The output of root memento `tid` is allocated separately, and its offset (0 until `run` finishes) commits it.
+/// An all-zero array has no outputs.
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct Outputs<T> {
+    offsets: [AtomicUsize; NR_MAX_THREADS + 1],
+    _marker: PhantomData<T>,
+}
+
+impl<T> Outputs<T> {
+    /// Whether root memento `tid` has finished
+    pub(crate) fn is_finished(&self, tid: usize) -> bool {
+        self.offsets[tid].load(Ordering::SeqCst) != 0
+    }
+
+    /// Forget the output of root memento `tid`, whose block is reclaimed by the recovery GC
+    pub(crate) fn forget(&self, tid: usize) {
+        self.offsets[tid].store(0, Ordering::SeqCst);
+        persist_obj(&self.offsets[tid], true);
+    }
+
+    /// Forget all outputs, whose blocks are reclaimed by the recovery GC
+    pub(crate) fn clear(&self) {
+        for offset in &self.offsets {
+            offset.store(0, Ordering::SeqCst);
+        }
+        persist_obj(&self.offsets, true);
+    }
+}
+
+impl<T: Collectable> Collectable for Outputs<T> {
+    fn filter(outputs: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        for offset in &outputs.offsets {
+            let offset = offset.load(Ordering::SeqCst);
+            if offset != 0 {
+                let output = unsafe { &mut *((pool.start() + offset) as *mut T) };
+                T::mark(output, tid, gc, pool);
+            }
+        }
+    }
+}
+
+impl PoolHandle {
+    pub(crate) fn outputs<T>(&self) -> &Outputs<T> {
+        unsafe { &*(self.get_root(RootIdx::Outputs as u64) as *const Outputs<T>) }
+    }
+
+    /// Output of root memento `tid` if its `run` has finished
+    ///
+    /// O: root obj
+    /// M: root memento(s)
+    pub fn output<O: RootObj<M>, M: Memento>(&self, tid: usize) -> Option<&O::Output> {
+        let offset = self.outputs::<O::Output>().offsets[tid].load(Ordering::SeqCst);
+        (offset != 0).then(|| unsafe { &*((self.start() + offset) as *const O::Output) })
+    }
+
+    /// Make `output` of root memento `tid` durable
+    ///
+    /// If a crash occurs before it returns, the root memento runs again in recovery mode,
+    /// which returns the same output.
+    pub(crate) fn set_output<O: RootObj<M>, M: Memento>(&self, tid: usize, output: O::Output) {
+        let size = std::mem::size_of::<O::Output>().max(1);
+        let ptr = unsafe { self.allocator.malloc(size as u64) } as *mut O::Output;
+        assert!(!ptr.is_null(), "Out of memory");
+        unsafe { ptr.write(output) };
+        persist_obj(unsafe { &*ptr }, true);
+
+        let offset = &self.outputs::<O::Output>().offsets[tid];
+        offset.store(ptr as usize - self.start(), Ordering::SeqCst);
+        persist_obj(offset, true);
+    }
+}
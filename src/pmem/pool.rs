@@ -7,36 +7,64 @@ use std::ffi::{c_void, CString};
 use std::io::Error;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::{fs, mem};
 
-use crate::ploc::{CasHelpArr, CasHelpDescArr, ExecInfo, Handle, NR_MAX_THREADS};
-use crate::pmem::global::global_pool;
+use crate::ploc::{
+    CasHelp, CasHelpArr, CasHelpDescArr, CasHelpDescriptor, ExecInfo, Handle, DEFAULT_MAX_THREADS,
+    NR_MAX_THREADS,
+};
+use crate::pmem::alloc::*;
+use crate::pmem::exec::{ExecConfig, ExecError, ExecFailure, ExecOutput};
+use crate::pmem::fsck::Fsck;
+use crate::pmem::layout::{LayoutError, PoolLayout};
 use crate::pmem::ll::persist_obj;
+use crate::pmem::migrate::{
+    check_old, finish_migration, run_migration, start_migration, Migration, MigrationLog,
+};
+use crate::pmem::output::Outputs;
 use crate::pmem::ptr::PPtr;
-use crate::pmem::{alloc::*, global};
+use crate::pmem::roots::{Directory, NamedRoots};
+use crate::pmem::shutdown::take_shutdown;
+use crate::pmem::slots::recover_nr_memento;
+use crate::pmem::stats::PoolStats;
+use crate::pmem::superblock::{root_table_checksum, SuperBlock, SuperBlockError};
 use crate::*;
-use crossbeam_epoch::{self as epoch};
+use crossbeam_epoch::{Collector, Guard};
+use etrace::some_or;
 use test_utils::thread;
 
 use super::sfence;
 
 // indicating at which root of Ralloc the metadata, root obj, and root mementos are located.
 pub(crate) enum RootIdx {
-    RootObj,                                            // root obj
-    CASHelpArr,                                         // cas help array
-    CASHelpDescArr,                                     // cas help descriptor array
-    NrMemento,                                          // number of root mementos
-    MementoStart,                                       // start index of root memento(s)
-    MementoClearingFlagStart = NR_MAX_THREADS as isize, // start index of root memento's clearing flag
+    RootObj,        // root obj
+    CASHelpArr,     // cas help array
+    CASHelpDescArr, // cas help descriptor array
+    NrMemento,      // number of root mementos
+    Layout,         // layout header
+    SuperBlock,     // superblock
+    Directory,      // directory of named roots
+    Migration,      // migration of root obj in progress
+    Outputs,        // outputs of root memento(s)
+    Shutdown,       // record of clean shutdown
+    MementoStart,   // start index of root memento(s)
+    // start index of root memento's clearing flag, after the root mementos of tid 1..=NR_MAX_THREADS
+    MementoClearingFlagStart = RootIdx::MementoStart as isize + NR_MAX_THREADS as isize,
 }
 
-lazy_static::lazy_static! {
-    static ref BARRIER_WAIT: [AtomicBool; NR_MAX_THREADS+1] =
-        array_init::array_init(|_| AtomicBool::new(false));
-}
+/// Number of roots of a pool, covering the root memento and clearing flag of every tid in `1..=NR_MAX_THREADS`
+pub(crate) const NR_ROOTS: usize = RootIdx::MementoClearingFlagStart as usize + NR_MAX_THREADS + 1;
 
 /// PoolHandle
 ///
+/// Context of an opened pool. Several pools can be opened at the same time, each with its own handle,
+/// with the PMDK (`pmdk`) or mmap (`mmap`) allocator. Ralloc, the default allocator, keeps its heap in
+/// global variables, so it maps only one pool per process.
+///
+/// A handle is never freed, so that it can be shared as `&'static PoolHandle` by root mementos and `Handle`s.
+/// Its pool stays mapped until `PoolHandle::close` unmaps it or the process exits.
+///
 /// # Example
 ///
 /// ```no_run
@@ -56,14 +84,122 @@ pub struct PoolHandle {
 
     len: usize,
 
+    /// Maximum number of threads (i.e. tids are in `0..=max_threads`)
+    max_threads: usize,
+
+    /// Persistent allocator managing the pool
+    pub(crate) allocator: PMEMAllocator,
+
+    /// Epoch-based garbage collector of the pool
+    pub(crate) collector: Collector,
+
     /// Detectable execution information per thread
     pub(crate) exec_info: ExecInfo,
 
+    /// Barrier to start root mementos at the same time
+    barrier: Vec<AtomicBool>,
+
+    /// Set while `snapshot` stops root mementos
+    quiescing: AtomicBool,
+
+    /// Whether each root memento is stopped for `snapshot` or not running
+    quiesced: Vec<AtomicBool>,
+
+    /// Whether each tid is used by a running root memento or another thread (`PoolHandle::try_acquire_tid`)
+    tids_in_use: Vec<AtomicBool>,
+
+    /// Lock to take one snapshot at a time
+    snapshot_lock: Mutex<()>,
+
+    /// Types of named roots registered when the pool is opened
+    pub(crate) named_roots: NamedRoots,
+
+    /// Lock to create one named root at a time
+    pub(crate) roots_lock: Mutex<()>,
+
+    /// Lock held while root mementos are executed or their slots are added or retired
+    pub(crate) slots_lock: Mutex<()>,
+
+    /// Whether a root memento may have been interrupted (e.g. by a crash) and not finished since,
+    /// so that root mementos should run in recovery mode
+    pub(crate) interrupted: AtomicBool,
+
+    /// Whether the pool has been unmapped by `close`
+    pub(crate) closed: AtomicBool,
+
     /// Root Memento's clear function
     clear_func: Option<unsafe fn(s: *mut c_void)>,
+
+    /// State of `fsck` while it walks the pool
+    pub(crate) fsck: Option<Fsck>,
 }
 
 impl PoolHandle {
+    /// Create a handle for the pool mapped by `allocator` and leak it for the rest of the process.
+    unsafe fn new<M: Memento>(
+        allocator: PMEMAllocator,
+        len: usize,
+        max_threads: usize,
+    ) -> &'static mut PoolHandle {
+        unsafe fn root_clear<M: Memento>(s: *mut c_void) {
+            M::clear(&mut *(s as *mut M))
+        }
+
+        Self::new_inner(allocator, len, max_threads, Some(root_clear::<M>))
+    }
+
+    /// Create a handle for the pool mapped by `allocator` only to look into it (e.g. `PoolView`).
+    /// Root mementos cannot be run with it.
+    pub(crate) unsafe fn new_view(allocator: PMEMAllocator, len: usize) -> &'static mut PoolHandle {
+        let max_threads = Pool::superblock(&allocator).max_threads();
+        Self::new_inner(allocator, len, max_threads, None)
+    }
+
+    #[allow(box_pointers)]
+    unsafe fn new_inner(
+        allocator: PMEMAllocator,
+        len: usize,
+        max_threads: usize,
+        clear_func: Option<unsafe fn(s: *mut c_void)>,
+    ) -> &'static mut PoolHandle {
+        let chk_ref = CasHelpArr::from_raw(
+            allocator.get_root(RootIdx::CASHelpArr as u64) as *const CasHelp,
+            max_threads + 1,
+        );
+        let desc_ref = CasHelpDescArr::from_raw(
+            allocator.get_root(RootIdx::CASHelpDescArr as u64) as *const CasHelpDescriptor,
+            max_threads + 1,
+        );
+
+        // The handle lives for the whole process (see `PoolHandle`).
+        Box::leak(Box::new(PoolHandle {
+            start: allocator.mmapped_addr(),
+            len,
+            max_threads,
+            allocator,
+            collector: Collector::new(),
+            exec_info: ExecInfo::from((chk_ref, desc_ref)),
+            barrier: (0..max_threads + 1)
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+            quiescing: AtomicBool::new(false),
+            quiesced: (0..max_threads + 1)
+                .map(|_| AtomicBool::new(true))
+                .collect(),
+            tids_in_use: (0..max_threads + 1)
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+            snapshot_lock: Mutex::new(()),
+            named_roots: NamedRoots::new(),
+            roots_lock: Mutex::new(()),
+            slots_lock: Mutex::new(()),
+            interrupted: AtomicBool::new(true),
+            closed: AtomicBool::new(false),
+            clear_func,
+            fsck: None,
+        }))
+    }
+
     /// start address of pool
     #[inline]
     pub fn start(&self) -> usize {
@@ -76,14 +212,23 @@ impl PoolHandle {
         self.start() + self.len
     }
 
+    /// Maximum number of threads of the pool (`Pool::create_with_max_threads`)
+    #[inline]
+    pub fn max_threads(&self) -> usize {
+        self.max_threads
+    }
+
     pub(crate) fn clear_mmt(&self, tid: usize) {
         unsafe {
-            let m_addr = PMEMAllocator::get_root(RootIdx::MementoStart as u64 + tid as u64);
-            let m_is_clearing =
-                (PMEMAllocator::get_root(RootIdx::MementoClearingFlagStart as u64 + tid as u64)
-                    as *mut bool)
-                    .as_mut()
-                    .unwrap();
+            let m_addr = self
+                .allocator
+                .get_root(RootIdx::MementoStart as u64 + tid as u64);
+            let m_is_clearing = (self
+                .allocator
+                .get_root(RootIdx::MementoClearingFlagStart as u64 + tid as u64)
+                as *mut bool)
+                .as_mut()
+                .unwrap();
 
             // Set flag
             *m_is_clearing = true;
@@ -103,91 +248,283 @@ impl PoolHandle {
 
     /// Start main program of pool by running root memento(s)
     ///
+    /// A root memento is re-executed whenever it fails, until it succeeds.
+    /// Return the output of each root memento in the order of tid.
+    ///
+    /// O: root obj
+    /// M: root memento(s)
+    pub fn execute<O, M>(&'static self) -> Vec<O::Output>
+    where
+        O: RootObj<M> + Send + Sync + 'static,
+        M: Memento + Send + Sync,
+    {
+        self.execute_with::<O, M>(&ExecConfig::new())
+            .into_iter()
+            .map(|res| {
+                res.expect("A root memento is re-executed until it succeeds.")
+                    .output
+            })
+            .collect()
+    }
+
+    /// Start main program of pool by running root memento(s) with an execution policy
+    ///
+    /// Root mementos run in recovery mode unless the pool was created or closed cleanly (`PoolHandle::close`)
+    /// and no root memento has failed since.
+    /// A root memento fails if its thread panics or is killed (e.g. by a simulated crash), and is re-executed
+    /// in recovery mode according to `config`.
+    /// Return the outcome of each root memento in the order of tid: its output if it succeeded,
+    /// or `ExecError` if it was given up. A root memento that is given up is resumed by the next `execute`.
+    ///
+    /// A root memento whose output is in the pool (i.e. it finished before) returns it without running again.
+    ///
     /// O: root obj
     /// M: root memento(s)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is closed.
     #[allow(box_pointers)]
-    pub fn execute<O, M>(&'static self)
+    pub fn execute_with<O, M>(
+        &'static self,
+        config: &ExecConfig,
+    ) -> Vec<Result<ExecOutput<O::Output>, ExecError>>
     where
         O: RootObj<M> + Send + Sync + 'static,
         M: Memento + Send + Sync,
     {
+        assert!(!self.closed.load(Ordering::SeqCst), "Pool is closed.");
+
         // get root obj
         let root_obj = unsafe {
-            (PMEMAllocator::get_root(RootIdx::RootObj as u64) as *const O)
+            (self.allocator.get_root(RootIdx::RootObj as u64) as *const O)
                 .as_ref()
                 .unwrap()
         };
 
+        // keep slots of root memento(s) while running them
+        let _lock = self.slots_lock.lock().unwrap();
+
         // get number of root memento(s)
-        let nr_memento =
-            unsafe { *(PMEMAllocator::get_root(RootIdx::NrMemento as u64) as *mut usize) };
+        let nr_memento = self.nr_memento();
 
-        // repeat until `tid` thread succeeds the `tid`th memento
+        // repeat until `tid` thread succeeds the `tid`th memento or gives it up
         let mut handles = Vec::new();
         for tid in 1..=nr_memento {
             // get `tid`th root mement
             let (m_addr, m_is_clearing) = unsafe {
                 (
-                    PMEMAllocator::get_root(RootIdx::MementoStart as u64 + tid as u64) as usize,
-                    PMEMAllocator::get_root(RootIdx::MementoClearingFlagStart as u64 + tid as u64)
+                    self.allocator
+                        .get_root(RootIdx::MementoStart as u64 + tid as u64)
+                        as usize,
+                    self.allocator
+                        .get_root(RootIdx::MementoClearingFlagStart as u64 + tid as u64)
                         as usize,
                 )
             };
 
+            let config = config.clone();
             let th = thread::spawn(move || {
+                let config = config.clone();
                 let h = thread::spawn(move || {
+                    if let Some(output) = self.output::<O, M>(tid) {
+                        // Let the others pass the barrier without running
+                        self.barrier[tid].store(true, Ordering::SeqCst);
+                        return Ok(ExecOutput {
+                            output: output.clone(),
+                            failures: 0,
+                            finished_before: true,
+                        });
+                    }
+
+                    // Wait for the tid if another thread has taken it over
+                    while !self.try_acquire_tid(tid) {
+                        std::thread::yield_now();
+                    }
+
+                    let mut attempt = 0;
                     loop {
                         // Run memento
+                        let panic = Mutex::new(None);
+                        let panic_ref = &panic;
                         let mh = thread::spawn(move || {
+                            self.resume(tid);
+
                             let is_clearing = unsafe { *(m_is_clearing as *mut bool) };
                             if is_clearing {
                                 self.clear_mmt(tid)
                             }
 
-                            let handle = Handle::new(tid, unsafe { epoch::old_guard(tid) }, self);
+                            let handle =
+                                Handle::new(tid, unsafe { self.collector.old_guard(tid) }, self);
+                            handle
+                                .rec
+                                .store(self.interrupted.load(Ordering::SeqCst), Ordering::SeqCst);
                             let root_mmt = unsafe { (m_addr as *mut M).as_mut().unwrap() };
 
                             // Barrier
-                            handle.pool.barrier_wait(handle.tid, nr_memento);
+                            handle.pool.barrier_wait(handle.tid);
 
                             // Run memento
-                            root_obj.run(root_mmt, &handle);
+                            // - A panic is caught to be reported. The handle is not dropped as if the thread crashed.
+                            // - A thread killed by `pthread_exit` (i.e. simulated crash) cannot be run in
+                            //   `catch_unwind`, since its forced unwinding aborts there.
+                            #[cfg(not(feature = "tcrash"))]
+                            let res =
+                                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                                    root_obj.run(root_mmt, &handle)
+                                }));
+                            #[cfg(feature = "tcrash")]
+                            let res: std::thread::Result<O::Output> =
+                                Ok(root_obj.run(root_mmt, &handle));
+                            match res {
+                                Ok(output) => self.set_output::<O, M>(tid, output),
+                                Err(payload) => {
+                                    mem::forget(handle);
+                                    *panic_ref.lock().unwrap() = Some(payload);
+                                }
+                            }
                         });
 
                         // Join
-                        // - Exit on success, re-run memento on failure
+                        // - Exit on success, re-run memento on failure unless it is given up
                         // - The guard used in case of failure is also not cleaned up.
                         //   A guard that loses its owner should be used well by the thread created in the next iteration.
-                        if let Ok(_) = mh.join() {
-                            break;
+                        let joined = mh.join();
+                        let payload = panic.into_inner().unwrap();
+                        if joined.is_ok() && payload.is_none() {
+                            self.quiesced[tid].store(true, Ordering::SeqCst);
+                            self.release_tid(tid);
+                            return Ok(ExecOutput {
+                                output: self.output::<O, M>(tid).unwrap().clone(),
+                                failures: attempt,
+                                finished_before: false,
+                            });
                         }
 
-                        println!("[pool::execute] Thread {tid} re-executed.");
+                        self.interrupted.store(true, Ordering::SeqCst);
+                        attempt += 1;
+                        let failure = ExecFailure {
+                            tid,
+                            attempt,
+                            payload: payload.as_deref(),
+                        };
+                        if !config.fail(&failure) {
+                            self.quiesced[tid].store(true, Ordering::SeqCst);
+                            self.release_tid(tid);
+                            return Err(ExecError {
+                                tid,
+                                attempts: attempt,
+                                message: failure.message().map(str::to_owned),
+                            });
+                        }
                     }
                 });
-                let _ = h.join();
+                h.join().unwrap()
             });
             handles.push(th);
         }
 
-        while let Some(h) = handles.pop() {
-            let _ = h.join();
-        }
+        handles.iter().map(|h| h.join().unwrap()).collect()
     }
 
-    fn barrier_wait(&self, tid: usize, nr_memento: usize) {
+    fn barrier_wait(&self, tid: usize) {
         // Initialize Ralloc's thread local structures
         #[cfg(feature = "tcrash")]
         let _a = self.alloc::<usize>();
 
-        BARRIER_WAIT[tid].store(true, Ordering::SeqCst);
-        for other in 1..=nr_memento {
-            while !BARRIER_WAIT[other].load(Ordering::SeqCst) {
+        self.barrier[tid].store(true, Ordering::SeqCst);
+        for other in 1..=self.nr_memento() {
+            while !self.barrier[other].load(Ordering::SeqCst) {
                 std::hint::spin_loop();
             }
         }
     }
 
+    /// Take `tid` for the calling thread unless a running root memento or another thread uses it
+    ///
+    /// A root memento run by `execute` holds its tid from its first run until it succeeds or is given up, so
+    /// a tid that cannot be taken belongs to a live thread. A taken tid should be given back with `release_tid`.
+    pub(crate) fn try_acquire_tid(&self, tid: usize) -> bool {
+        self.tids_in_use[tid]
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// Give back `tid` taken with `try_acquire_tid`
+    pub(crate) fn release_tid(&self, tid: usize) {
+        self.tids_in_use[tid].store(false, Ordering::SeqCst);
+    }
+
+    /// Stop here while `snapshot` copies the pool
+    ///
+    /// It is called by root memento `tid` at an epoch boundary, i.e. while it is unpinned.
+    pub(crate) fn quiesce(&self, tid: usize) {
+        if self.quiescing.load(Ordering::SeqCst) {
+            self.quiesced[tid].store(true, Ordering::SeqCst);
+            self.resume(tid);
+        }
+    }
+
+    /// Mark root memento `tid` as running, waiting for `snapshot` first if it is copying the pool
+    fn resume(&self, tid: usize) {
+        loop {
+            self.quiesced[tid].store(false, Ordering::SeqCst);
+            if !self.quiescing.load(Ordering::SeqCst) {
+                return;
+            }
+            self.quiesced[tid].store(true, Ordering::SeqCst);
+            while self.quiescing.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    /// Write a crash-consistent copy of the pool to `dest`
+    ///
+    /// It stops root mementos run by `execute` at their next epoch boundary (`Handle::repin_guard`), copies the
+    /// mapped pool and the allocator metadata, and then lets them go. The snapshot is the pool as it would be if
+    /// the process had crashed at that instant, so `Pool::open` recovers it as after a crash.
+    /// The superblock is validated before copying, and its creation-complete flag is written last,
+    /// so a snapshot interrupted by a crash does not open.
+    ///
+    /// It blocks until every running root memento reaches an epoch boundary or finishes, so it should not be
+    /// called by a root memento. Other threads using the pool (e.g. with a `Handle` of `PoolHandle::pin`) are not stopped.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if pool already exists in `dest`
+    /// * Fail with `SuperBlockError` if the superblock of the pool is not valid
+    pub fn snapshot(&self, dest: &str) -> Result<(), Error> {
+        if PMEMAllocator::exists(dest) {
+            return Err(Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "File already exist.",
+            ));
+        }
+        fs::create_dir_all(Path::new(dest).parent().unwrap())?;
+
+        let _lock = self.snapshot_lock.lock().unwrap();
+        self.quiescing.store(true, Ordering::SeqCst);
+        for quiesced in &self.quiesced {
+            while !quiesced.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+        }
+
+        let res = unsafe {
+            Pool::check_superblock(&self.allocator)
+                .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+                .and_then(|()| {
+                    let sb =
+                        self.allocator.get_root(RootIdx::SuperBlock as u64) as *const SuperBlock;
+                    self.allocator.snapshot(dest, (*sb).complete_flag())
+                })
+        };
+        self.quiescing.store(false, Ordering::SeqCst);
+        res
+    }
+
     /// unsafe get root
     ///
     /// It is useful to check the object in the pool directly
@@ -196,14 +533,39 @@ impl PoolHandle {
     ///
     /// Carefully use `ix`
     pub unsafe fn get_root(&self, ix: u64) -> *mut c_void {
-        PMEMAllocator::get_root(ix)
+        self.allocator.get_root(ix)
+    }
+
+    /// Pin a guard of the pool's epoch-based garbage collector
+    ///
+    /// The guard is not bound to any thread id. Use it for a temporary `Handle`.
+    pub fn pin(&self) -> Guard {
+        self.collector.register(None).pin()
+    }
+
+    /// Guard used by the `tid` thread, which is kept across crashes
+    ///
+    /// # Safety
+    ///
+    /// Each `tid` should be used by only one thread.
+    pub(crate) unsafe fn old_guard(&self, tid: usize) -> Guard {
+        self.collector.old_guard(tid)
     }
 
     /// alloc
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is out of memory. Use `try_alloc` to handle it.
     #[inline]
     pub fn alloc<T>(&self) -> PPtr<T> {
-        let ptr = self.pool().alloc(mem::size_of::<T>());
-        PPtr::from(ptr as usize - self.start())
+        self.try_alloc().expect("Out of memory")
+    }
+
+    /// alloc, or return `AllocError` if the pool is out of memory
+    #[inline]
+    pub fn try_alloc<T>(&self) -> Result<PPtr<T>, AllocError> {
+        unsafe { self.try_alloc_layout(Layout::new::<T>()) }
     }
 
     /// allocate according to the layout and return pointer pointing to it as `T`
@@ -211,10 +573,29 @@ impl PoolHandle {
     /// # Safety
     ///
     /// Carefully check `T` and `layout`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is out of memory. Use `try_alloc_layout` to handle it.
     #[inline]
     pub unsafe fn alloc_layout<T>(&self, layout: Layout) -> PPtr<T> {
-        let ptr = self.pool().alloc(layout.size());
-        PPtr::from(ptr as usize - self.start())
+        self.try_alloc_layout(layout).expect("Out of memory")
+    }
+
+    /// allocate according to the layout, or return `AllocError` if the pool is out of memory
+    ///
+    /// The block is aligned to `layout.align()`, and should be freed by `free_layout` with the same layout.
+    ///
+    /// # Safety
+    ///
+    /// Carefully check `T` and `layout`
+    #[inline]
+    pub unsafe fn try_alloc_layout<T>(&self, layout: Layout) -> Result<PPtr<T>, AllocError> {
+        let ptr = self.allocator.malloc_layout(layout);
+        if ptr.is_null() {
+            return Err(AllocError);
+        }
+        Ok(PPtr::from(ptr as usize - self.start()))
     }
 
     /// free
@@ -222,7 +603,10 @@ impl PoolHandle {
     pub fn free<T>(&self, pptr: PPtr<T>) {
         let addr_abs = self.start() + pptr.into_offset();
         assert!(self.valid(addr_abs));
-        self.pool().free(addr_abs as *mut u8, mem::size_of::<T>());
+        unsafe {
+            self.allocator
+                .free_layout(addr_abs as *mut c_void, Layout::new::<T>())
+        };
     }
 
     /// deallocate as much as the layout size from the offset address
@@ -234,12 +618,7 @@ impl PoolHandle {
     pub unsafe fn free_layout(&self, offset: usize, layout: Layout) {
         // NOTE: Ralloc's free does not receive a size, so just pass the address to deallocate.
         let addr_abs = self.start() + offset;
-        self.pool().free(addr_abs as *mut u8, layout.size());
-    }
-
-    #[inline]
-    fn pool(&self) -> &Pool {
-        unsafe { &*(self.start() as *const Pool) }
+        self.allocator.free_layout(addr_abs as *mut c_void, layout);
     }
 
     /// check if the `raw` addr is in range of pool
@@ -247,11 +626,10 @@ impl PoolHandle {
     pub fn valid(&self, raw: usize) -> bool {
         raw >= self.start() && raw < self.end()
     }
-}
 
-impl Drop for PoolHandle {
-    fn drop(&mut self) {
-        unsafe { PMEMAllocator::close(self.start, self.len) }
+    /// Heap usage of the pool and the result of the last recovery GC
+    pub fn stats(&self) -> PoolStats {
+        self.allocator.stats()
     }
 }
 
@@ -263,88 +641,115 @@ impl Pool {
     /// Create pool
     ///
     /// Create and initialize a file to be used as a pool and return its handle.
+    /// Per-thread metadata is allocated for `DEFAULT_MAX_THREADS` threads.
     ///
     /// # Errors
     ///
-    /// * Fail if file already exists in `filepath`
+    /// * Fail if pool already exists in `filepath`
     /// * Fail if `size` is not more than `1GB` and less than `1TB` (forced by Ralloc)
+    /// * Fail with `ErrorKind::Unsupported` if another pool is open with Ralloc, which maps only one pool per process
     pub fn create<O: RootObj<M>, M: Memento>(
         filepath: &str,
         size: usize,
         nr_memento: usize, // number of root memento(s)
     ) -> Result<&'static PoolHandle, Error> {
-        if Pool::is_valid(filepath) {
+        Self::create_with_max_threads::<O, M>(filepath, size, nr_memento, DEFAULT_MAX_THREADS)
+    }
+
+    /// Create pool for at most `max_threads` threads
+    ///
+    /// Same as `create`, but per-thread metadata is allocated only for `max_threads` threads.
+    /// Root mementos and `Handle`s of the pool should have a tid in `1..=max_threads`.
+    ///
+    /// # Errors
+    ///
+    /// * Fail for the same reasons as `create`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is not in `1..=NR_MAX_THREADS`, or `nr_memento` is larger than `max_threads`.
+    pub fn create_with_max_threads<O: RootObj<M>, M: Memento>(
+        filepath: &str,
+        size: usize,
+        nr_memento: usize, // number of root memento(s)
+        max_threads: usize,
+    ) -> Result<&'static PoolHandle, Error> {
+        if PMEMAllocator::exists(filepath) {
             return Err(Error::new(
                 std::io::ErrorKind::AlreadyExists,
                 "File already exist.",
             ));
         }
-        assert!(nr_memento <= NR_MAX_THREADS);
+        assert!((1..=NR_MAX_THREADS).contains(&max_threads));
+        assert!(nr_memento <= max_threads);
         fs::create_dir_all(Path::new(filepath).parent().unwrap())?;
 
-        global::clear();
-
-        // create fil and initialze its content to pool layout of Ralloc
+        // create fil and initialze its content to pool layout of allocator
         let filepath_c = CString::new(filepath).expect("CString::new failed");
-        let is_reopen = unsafe { PMEMAllocator::create(filepath_c.as_ptr(), size as u64) };
-        assert_eq!(is_reopen, 0);
+        let allocator = unsafe { PMEMAllocator::create(filepath_c.as_ptr(), size as u64)? };
 
         unsafe {
-            // set general cas checkpoint
-            let cas_help_arr =
-                PMEMAllocator::malloc(mem::size_of::<CasHelpArr>() as u64) as *mut CasHelpArr;
-            cas_help_arr.write(CasHelpArr::default());
-            persist_obj(cas_help_arr.as_mut().unwrap(), true);
-            let _prev =
-                PMEMAllocator::set_root(cas_help_arr as *mut c_void, RootIdx::CASHelpArr as u64);
-            let chk_ref = cas_help_arr.as_ref().unwrap();
-
-            // set cas help descriptor
-            let cas_help_desc_arr = PMEMAllocator::malloc(mem::size_of::<CasHelpDescArr>() as u64)
-                as *mut CasHelpDescArr;
-            cas_help_desc_arr.write(CasHelpDescArr::default());
-            persist_obj(cas_help_desc_arr.as_mut().unwrap(), true);
-            let _prev = PMEMAllocator::set_root(
+            // set general cas checkpoint: 0 ~ max_threads
+            let cas_help_arr = allocator
+                .malloc((mem::size_of::<CasHelp>() * (max_threads + 1)) as u64)
+                as *mut CasHelp;
+            let _ = CasHelpArr::init(cas_help_arr, max_threads + 1);
+            let _prev = allocator.set_root(cas_help_arr as *mut c_void, RootIdx::CASHelpArr as u64);
+
+            // set cas help descriptor: 0 ~ max_threads
+            let cas_help_desc_arr = allocator
+                .malloc((mem::size_of::<CasHelpDescriptor>() * (max_threads + 1)) as u64)
+                as *mut CasHelpDescriptor;
+            let _ = CasHelpDescArr::init(cas_help_desc_arr, max_threads + 1);
+            let _prev = allocator.set_root(
                 cas_help_desc_arr as *mut c_void,
                 RootIdx::CASHelpDescArr as u64,
             );
-            let desc_ref = cas_help_desc_arr.as_ref().unwrap();
 
-            // set global pool
-            unsafe fn root_clear<M: Memento>(s: *mut c_void) {
-                M::clear(&mut *(s as *mut M))
-            }
-            global::init(PoolHandle {
-                start: PMEMAllocator::mmapped_addr(),
-                len: size,
-                exec_info: ExecInfo::from((chk_ref, desc_ref)),
-                clear_func: Some(root_clear::<M>),
-            });
-
-            let pool = global_pool().unwrap();
+            // set layout header
+            let layout = allocator.malloc(mem::size_of::<PoolLayout>() as u64) as *mut PoolLayout;
+            layout.write(PoolLayout::new::<O, M>(O::VERSION));
+            persist_obj(layout.as_mut().unwrap(), true);
+            let _prev = allocator.set_root(layout as *mut c_void, RootIdx::Layout as u64);
+
+            // set directory of named roots, which is empty if all-zero
+            let dir = allocator.malloc(mem::size_of::<Directory>() as u64) as *mut Directory;
+            dir.write_bytes(0, 1);
+            persist_obj(dir.as_mut().unwrap(), true);
+            let _prev = allocator.set_root(dir as *mut c_void, RootIdx::Directory as u64);
+
+            // set outputs of root memento(s), which is empty if all-zero
+            let outputs = allocator.malloc(mem::size_of::<Outputs<O::Output>>() as u64)
+                as *mut Outputs<O::Output>;
+            outputs.write_bytes(0, 1);
+            persist_obj(outputs.as_mut().unwrap(), true);
+            let _prev = allocator.set_root(outputs as *mut c_void, RootIdx::Outputs as u64);
+
+            // create pool handle
+            let pool: &'static PoolHandle = PoolHandle::new::<M>(allocator, size, max_threads);
+            let allocator = &pool.allocator;
 
             // set root obj
-            let o_ptr = PMEMAllocator::malloc(mem::size_of::<O>() as u64) as *mut O;
-            let tmp_handle = Handle::new(1, epoch::pin(), pool);
+            let o_ptr = allocator.malloc_layout(Layout::new::<O>()) as *mut O;
+            let tmp_handle = Handle::new(1, pool.pin(), pool);
             tmp_handle.rec.store(false, Ordering::SeqCst);
             o_ptr.write(O::pdefault(&tmp_handle));
             persist_obj(o_ptr.as_mut().unwrap(), true);
-            let _prev = PMEMAllocator::set_root(o_ptr as *mut c_void, RootIdx::RootObj as u64);
+            let _prev = allocator.set_root(o_ptr as *mut c_void, RootIdx::RootObj as u64);
 
             // set number of root mementos
-            let nr_memento_ptr =
-                PMEMAllocator::malloc(mem::size_of::<usize>() as u64) as *mut usize;
+            let nr_memento_ptr = allocator.malloc(mem::size_of::<usize>() as u64) as *mut usize;
             nr_memento_ptr.write(nr_memento);
             persist_obj(nr_memento_ptr.as_mut().unwrap(), true);
             let _prev =
-                PMEMAllocator::set_root(nr_memento_ptr as *mut c_void, RootIdx::NrMemento as u64);
+                allocator.set_root(nr_memento_ptr as *mut c_void, RootIdx::NrMemento as u64);
 
             // set root memento(s): 1 ~ nr_memento
             for i in 1..nr_memento + 1 {
-                let root_ptr = PMEMAllocator::malloc(mem::size_of::<M>() as u64) as *mut M;
+                let root_ptr = allocator.malloc_layout(Layout::new::<M>()) as *mut M;
                 root_ptr.write(M::default());
                 persist_obj(root_ptr.as_mut().unwrap(), true);
-                let _prev = PMEMAllocator::set_root(
+                let _prev = allocator.set_root(
                     root_ptr as *mut c_void,
                     RootIdx::MementoStart as u64 + i as u64,
                 );
@@ -352,21 +757,23 @@ impl Pool {
 
             // set root memento(s)'s clearing flag : 1 ~ nr_memento
             for i in 1..nr_memento + 1 {
-                let root_ptr = PMEMAllocator::malloc(mem::size_of::<bool>() as u64) as *mut bool;
+                let root_ptr = allocator.malloc(mem::size_of::<bool>() as u64) as *mut bool;
                 root_ptr.write(false);
                 persist_obj(root_ptr.as_mut().unwrap(), true);
-                let _prev = PMEMAllocator::set_root(
+                let _prev = allocator.set_root(
                     root_ptr as *mut c_void,
                     RootIdx::MementoClearingFlagStart as u64 + i as u64,
                 );
             }
 
-            // Initialize shared volatile variables
-            lazy_static::initialize(&BARRIER_WAIT);
-            epoch::init();
-
-            // Mark pool file as valid
-            Pool::mark_valid(filepath)?;
+            // set superblock, whose creation-complete flag is written last
+            let checksum = root_table_checksum(allocator, nr_memento);
+            let sb = allocator.malloc(mem::size_of::<SuperBlock>() as u64) as *mut SuperBlock;
+            sb.write(SuperBlock::new(max_threads, nr_memento, checksum));
+            persist_obj(sb.as_mut().unwrap(), true);
+            let _prev = allocator.set_root(sb as *mut c_void, RootIdx::SuperBlock as u64);
+            sb.as_mut().unwrap().mark_complete();
+            pool.interrupted.store(false, Ordering::SeqCst);
             Ok(pool)
         }
     }
@@ -375,120 +782,272 @@ impl Pool {
     ///
     /// mapping the file to the persistent heap and return its handler with root type `O`
     ///
-    /// # Safety
+    /// If the pool was closed cleanly (`PoolHandle::close`), the recovery GC is skipped.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if pool does not exist in `filepath`
+    /// * Fail with `ErrorKind::Unsupported` if another pool is open with Ralloc, which maps only one pool per process
+    /// * Fail with `SuperBlockError` if the pool was not completely created or its root table is corrupted
+    /// * Fail if not called with the same size as the size specified during `Pool::create` or the latest `Pool::grow` (forced by Ralloc)
+    /// * Fail with `LayoutError` if not called with the same root obj and root memento types (i.e. `O`, `M`)
+    ///   specified during `Pool::create`
+    pub fn open<O: RootObj<M>, M: Memento>(
+        filepath: &str,
+        size: usize,
+    ) -> Result<&'static PoolHandle, Error> {
+        Self::open_with_roots::<O, M>(filepath, size, &NamedRoots::new())
+    }
+
+    /// Open pool with named roots
     ///
-    /// * it must be loaded with the same type as the root op type (i.e. `O`) specified during `Pool::create`.
+    /// Same as `open`, but the named roots in `roots` are traced by the recovery GC.
+    /// The types of all named roots in the pool should be registered in `roots`.
     ///
     /// # Errors
     ///
-    /// * Fail if file does not exist in `filepath`
-    /// * Fail if not called with the same size as the size specified during `Pool::create` (forced by Ralloc)
-    pub unsafe fn open<O: RootObj<M>, M: Memento>(
+    /// * Fail for the same reasons as `open`
+    /// * Fail with `RootError` if a named root in the pool is not registered in `roots` or registered with another type
+    pub fn open_with_roots<O: RootObj<M>, M: Memento>(
         filepath: &str,
         size: usize,
+        roots: &NamedRoots,
     ) -> Result<&'static PoolHandle, Error> {
-        if !Pool::is_valid(filepath) {
+        let allocator = Pool::open_allocator(filepath, size)?;
+
+        // check layout header and directory before touching any other root
+        let checked = unsafe {
+            Pool::check_layout::<O, M>(&allocator)
+                .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+                .and_then(|_| Pool::check_directory(&allocator, roots))
+        };
+        if let Err(e) = checked {
+            unsafe { allocator.close(allocator.mmapped_addr(), size) };
+            return Err(e);
+        }
+
+        unsafe { Pool::open_inner::<O, M, O>(allocator, size, roots) }
+    }
+
+    /// Open pool, migrating its root object from an old version with `G`
+    ///
+    /// If the root object of the pool is of version `G::FROM`, it is rewritten into `O` by `G::migrate`
+    /// before the pool is returned. If the migration was interrupted by a crash, it resumes.
+    /// Otherwise, it is the same as `open_with_roots`.
+    ///
+    /// # Errors
+    ///
+    /// * Fail for the same reasons as `open_with_roots`
+    /// * Fail with `LayoutError` if the root object of the pool is neither `O` nor `G::Old` of version `G::FROM`
+    pub fn open_with_migration<O: RootObj<M>, M: Memento, G: Migration<O, M>>(
+        filepath: &str,
+        size: usize,
+        roots: &NamedRoots,
+    ) -> Result<&'static PoolHandle, Error> {
+        let allocator = Pool::open_allocator(filepath, size)?;
+
+        // check layout header (of the new or old root object) and directory before touching any other root
+        let checked = unsafe {
+            match Pool::check_layout::<O, M>(&allocator) {
+                Ok(()) => Ok(false),
+                Err(e) => check_old::<O, M, G>(&allocator)
+                    .map(|_| true)
+                    .map_err(|_| e),
+            }
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+            .and_then(|migrating| {
+                Pool::check_directory(&allocator, roots)?;
+                if !migrating {
+                    return Ok(None);
+                }
+                start_migration::<O, M, G>(&allocator).map(Some)
+            })
+        };
+        let migration = match checked {
+            Ok(migration) => migration,
+            Err(e) => {
+                unsafe { allocator.close(allocator.mmapped_addr(), size) };
+                return Err(e);
+            }
+        };
+
+        unsafe {
+            let (log, resumed) = some_or!(
+                migration,
+                return Pool::open_inner::<O, M, O>(allocator, size, roots)
+            );
+
+            // Until the migration is committed, the pool is traced with the old root object and the migration.
+            allocator.set_root_filter::<MigrationLog<G>>(RootIdx::Migration as u64);
+            let pool = Pool::open_inner::<O, M, G::Old>(allocator, size, roots)?;
+            run_migration::<O, M, G>(pool, log, resumed);
+            Ok(pool)
+        }
+    }
+
+    /// Open the allocator of pool and check its superblock
+    fn open_allocator(filepath: &str, size: usize) -> Result<PMEMAllocator, Error> {
+        if !PMEMAllocator::exists(filepath) {
             return Err(Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Pool is not valid.",
+                std::io::ErrorKind::NotFound,
+                "Pool does not exist.",
             ));
         }
 
-        global::clear();
-
         // open file
         let filepath = CString::new(filepath).expect("CString::new failed");
-        let is_reopen = PMEMAllocator::open(filepath.as_ptr(), size as u64);
-        assert_eq!(is_reopen, 1);
+        let allocator = unsafe { PMEMAllocator::open(filepath.as_ptr(), size as u64)? };
+
+        // finish a committed migration, which changes the root table, and then check superblock
+        // and the number of root mementos committed in it
+        let checked = unsafe {
+            finish_migration(&allocator);
+            Pool::check_superblock(&allocator)
+                .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+                .map(|()| recover_nr_memento(&allocator))
+        };
+        if let Err(e) = checked {
+            unsafe { allocator.close(allocator.mmapped_addr(), size) };
+            return Err(e);
+        }
+        Ok(allocator)
+    }
 
-        // get the starting address of the mapped address and set the global pool
-        let chk_ref = (PMEMAllocator::get_root(RootIdx::CASHelpArr as u64) as *const CasHelpArr)
+    /// Check that the types of all named roots are registered in `roots`
+    unsafe fn check_directory(allocator: &PMEMAllocator, roots: &NamedRoots) -> Result<(), Error> {
+        let dir = (allocator.get_root(RootIdx::Directory as u64) as *const Directory)
             .as_ref()
-            .unwrap();
-        let desc_ref = (PMEMAllocator::get_root(RootIdx::CASHelpDescArr as u64)
-            as *const CasHelpDescArr)
+            .ok_or_else(|| {
+                Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Named root directory is missing.",
+                )
+            })?;
+        dir.check(roots).map_err(Error::from)
+    }
+
+    /// Superblock of the pool mapped by `allocator`, which should be checked before
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn superblock(allocator: &PMEMAllocator) -> &mut SuperBlock {
+        &mut *(allocator.get_root(RootIdx::SuperBlock as u64) as *mut SuperBlock)
+    }
+
+    pub(crate) unsafe fn check_superblock(
+        allocator: &PMEMAllocator,
+    ) -> Result<(), SuperBlockError> {
+        let sb = (allocator.get_root(RootIdx::SuperBlock as u64) as *const SuperBlock)
             .as_ref()
-            .unwrap();
+            .ok_or(SuperBlockError::Missing)?;
+        sb.check(|nr_memento| root_table_checksum(allocator, nr_memento))
+    }
 
-        unsafe fn root_clear<M: Memento>(s: *mut c_void) {
-            M::clear(&mut *(s as *mut M))
-        }
-        global::init(PoolHandle {
-            start: PMEMAllocator::mmapped_addr(),
-            len: size,
-            exec_info: ExecInfo::from((chk_ref, desc_ref)),
-            clear_func: Some(root_clear::<M>),
-        });
+    pub(crate) unsafe fn check_layout<O: RootObj<M>, M: Memento>(
+        allocator: &PMEMAllocator,
+    ) -> Result<(), LayoutError> {
+        let layout = (allocator.get_root(RootIdx::Layout as u64) as *const PoolLayout)
+            .as_ref()
+            .ok_or(LayoutError::Missing)?;
+        layout.check(&PoolLayout::new::<O, M>(O::VERSION))
+    }
 
-        // run GC of Ralloc
+    /// R: type of the root obj to be traced by the recovery GC (`O` or the old one being migrated from)
+    unsafe fn open_inner<O: RootObj<M>, M: Memento, R: Collectable>(
+        allocator: PMEMAllocator,
+        size: usize,
+        roots: &NamedRoots,
+    ) -> Result<&'static PoolHandle, Error> {
+        // create pool handle
+        let max_threads = Pool::superblock(&allocator).max_threads();
+        let pool = PoolHandle::new::<M>(allocator, size, max_threads);
+        pool.named_roots = roots.clone();
+
+        // take the record of clean shutdown, which is not valid once the pool is changed
+        let shutdown = take_shutdown(&pool.allocator, max_threads);
+
+        // run GC of allocator
         {
+            let allocator = &pool.allocator;
+
             // set filter function of root obj
-            PMEMAllocator::set_root_filter::<O>(RootIdx::RootObj as u64);
+            allocator.set_root_filter::<R>(RootIdx::RootObj as u64);
+
+            // set filter function of outputs of root memento(s)
+            allocator.set_root_filter::<Outputs<O::Output>>(RootIdx::Outputs as u64);
+
+            // set filter function of directory, which marks named roots with `pool.named_roots`
+            allocator.set_root_filter::<Directory>(RootIdx::Directory as u64);
 
             // set filter function of root memento(s)
-            let nr_memento = *(PMEMAllocator::get_root(RootIdx::NrMemento as u64) as *mut usize);
-            assert!(nr_memento <= NR_MAX_THREADS);
+            let nr_memento = *(allocator.get_root(RootIdx::NrMemento as u64) as *mut usize);
+            assert!(nr_memento <= max_threads);
             for tid in 1..nr_memento + 1 {
-                PMEMAllocator::set_root_filter::<M>(RootIdx::MementoStart as u64 + tid as u64);
+                allocator.set_root_filter::<M>(RootIdx::MementoStart as u64 + tid as u64);
             }
 
             // set dummy filter function for root memento(s)'s clearing flag
             for tid in 1..nr_memento + 1 {
-                PMEMAllocator::set_root_filter::<bool>(
-                    RootIdx::MementoClearingFlagStart as u64 + tid as u64,
-                );
+                allocator
+                    .set_root_filter::<bool>(RootIdx::MementoClearingFlagStart as u64 + tid as u64);
             }
 
-            // call GC of Ralloc
-            let _is_gc_executed = PMEMAllocator::recover();
+            // call GC of allocator, unless what it keeps in DRAM is restored after clean shutdown
+            let is_gc_executed = !(shutdown.is_some() && allocator.restore_after_shutdown())
+                && PMEMAllocator::recover(pool) != 0;
+            pool.interrupted.store(shutdown.is_none(), Ordering::SeqCst);
+            if let Some(shutdown) = shutdown {
+                shutdown.restore(pool, is_gc_executed);
+            }
         }
 
-        let pool = global_pool().unwrap();
         pool.exec_info.set_info();
-
-        // Initialize shared volatile variables
-        lazy_static::initialize(&BARRIER_WAIT);
-        epoch::init();
-
         Ok(pool)
     }
 
-    /// Remove pool
-    pub fn remove(filepath: &str) -> Result<(), Error> {
-        // _basedmd, _desc, _sb are pool files created by Ralloc
-        let _ = fs::remove_file(filepath.to_owned());
-        let _ = fs::remove_file(filepath.to_owned() + "_basemd");
-        let _ = fs::remove_file(filepath.to_owned() + "_desc");
-        let _ = fs::remove_file(filepath.to_owned() + "_sb");
-        let _ = fs::remove_file(filepath.to_owned() + "_valid");
-        Ok(())
-    }
-
-    fn is_valid(filepath: &str) -> bool {
-        Path::new(&(filepath.to_owned() + "_valid")).exists()
-    }
-
-    fn mark_valid(filepath: &str) -> Result<(), Error> {
-        fs::write(Path::new(&(filepath.to_owned() + "_valid")), "1")?;
-        Ok(())
-    }
+    /// Grow pool
+    ///
+    /// Extend the pool files in `filepath` so that the pool is opened with `new_size` from now on.
+    /// The pool is extended at its end, so offsets of `PPtr`s in the pool stay the same.
+    ///
+    /// If it fails in the middle (e.g. crash), call it again with the same `new_size`.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if pool does not exist in `filepath`
+    /// * Fail if the pool is open (Ralloc cannot grow a pool while any pool is mapped in the process)
+    /// * Fail if `new_size` is smaller than the current size or not less than `1TB` (forced by Ralloc)
+    /// * Fail if the allocator does not support growing (PMDK)
+    pub fn grow(filepath: &str, new_size: usize) -> Result<(), Error> {
+        if !PMEMAllocator::exists(filepath) {
+            return Err(Error::new(
+                std::io::ErrorKind::NotFound,
+                "Pool does not exist.",
+            ));
+        }
 
-    #[inline]
-    fn alloc(&self, size: usize) -> *mut u8 {
-        let addr_abs = unsafe { PMEMAllocator::malloc(size as u64) };
-        addr_abs as *mut u8
+        let filepath = CString::new(filepath).expect("CString::new failed");
+        unsafe { PMEMAllocator::grow(filepath.as_ptr(), new_size as u64) }
     }
 
-    #[inline]
-    fn free(&self, ptr: *mut u8, len: usize) {
-        unsafe { PMEMAllocator::free(ptr as *mut c_void, len) }
+    /// Remove pool
+    ///
+    /// Remove the file(s) mapped by the allocator as a pool (e.g. `_basemd`, `_desc`, `_sb` for Ralloc).
+    pub fn remove(filepath: &str) -> Result<(), Error> {
+        PMEMAllocator::remove(filepath)
     }
 }
 
 /// Root object of pool
 pub trait RootObj<M: Memento>: PDefault + Collectable {
+    /// Version of the persistent layout of the root object
+    ///
+    /// Increase it when the layout changes, and migrate existing pools with `Pool::open_with_migration`.
+    const VERSION: u64 = 0;
+
+    /// Output of `run`, which is made durable once `run` returns
+    type Output: Clone + Collectable + Send + 'static = ();
+
     /// Root object's default run function with a root memento
-    fn run(&self, mmt: &mut M, handle: &Handle);
+    fn run(&self, mmt: &mut M, handle: &Handle) -> Self::Output;
 }
 
 /// Test
@@ -498,7 +1057,8 @@ pub mod test {
     use mmt_derive::Collectable;
 
     impl RootObj<CheckInv> for DummyRootObj {
-        fn run(&self, mmt: &mut CheckInv, _: &Handle) {
+        fn run(&self, mmt: &mut CheckInv, handle: &Handle) {
+            let _testee = unsafe { TESTER.as_ref().unwrap().testee(false, handle) };
             if mmt.flag {
                 assert_eq!(mmt.value, 42);
             } else {
@@ -523,6 +1083,123 @@ pub mod test {
         run_test::<DummyRootObj, CheckInv>(FILE_NAME, FILE_SIZE, 1, 1);
     }
 
+    // Two pools opened at the same time keep their own contexts.
+    #[cfg(any(feature = "pmdk", feature = "mmap"))]
+    #[test]
+    fn multiple_pools() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let pool1 = get_dummy_handle(FILE_SIZE).unwrap();
+        let pool2 = get_dummy_handle(FILE_SIZE).unwrap();
+        assert_ne!(pool1.start(), pool2.start());
+
+        let (p1, p2) = (pool1.alloc::<usize>(), pool2.alloc::<usize>());
+        unsafe {
+            *p1.deref_mut(pool1) = 1;
+            *p2.deref_mut(pool2) = 2;
+            assert_eq!(*p1.deref(pool1), 1);
+            assert_eq!(*p2.deref(pool2), 2);
+        }
+        assert!(pool1.valid(pool1.start() + p1.into_offset()));
+        assert!(pool2.valid(pool2.start() + p2.into_offset()));
+    }
+
+    // Ralloc refuses a second pool while one is open, and maps another pool once it is closed.
+    #[cfg(not(any(feature = "pmdk", feature = "mmap")))]
+    #[test]
+    fn single_ralloc_pool() {
+        const FILE_SIZE: usize = 8 * 1024 * 1024 * 1024;
+
+        let pool1 = get_dummy_handle(FILE_SIZE).unwrap();
+        assert_eq!(
+            get_dummy_handle(FILE_SIZE).unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+
+        let _ = unsafe { pool1.close() }.unwrap();
+        let _pool2 = get_dummy_handle(FILE_SIZE).unwrap();
+    }
+
+    // A snapshot opens as the pool after a crash, so blocks not in the pool are reclaimed.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn snapshot() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let pool = get_dummy_handle(FILE_SIZE).unwrap();
+        let _leaked = pool.alloc::<usize>();
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("snapshot").to_str().unwrap().to_owned();
+        pool.snapshot(&dest).unwrap();
+        assert!(pool.snapshot(&dest).is_err());
+
+        let copy = Pool::open::<DummyRootObj, DummyRootMemento>(&dest, FILE_SIZE).unwrap();
+        assert_eq!(copy.stats().recovery.unwrap().reclaimed, 1);
+    }
+
+    // Per-thread metadata is allocated for `max_threads`, which is kept when the pool is opened.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn max_threads() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let pool = Pool::create_with_max_threads::<DummyRootObj, DummyRootMemento>(
+            &path("pool"),
+            FILE_SIZE,
+            1,
+            2,
+        )
+        .unwrap();
+        assert_eq!(pool.exec_info.cas_info.help.len(), 3);
+        assert_eq!(
+            pool.add_memento::<DummyRootObj, DummyRootMemento>()
+                .unwrap(),
+            2
+        );
+        assert!(pool
+            .add_memento::<DummyRootObj, DummyRootMemento>()
+            .is_err());
+
+        pool.snapshot(&path("copy")).unwrap();
+        let pool = Pool::open::<DummyRootObj, DummyRootMemento>(&path("copy"), FILE_SIZE).unwrap();
+        assert_eq!((pool.max_threads(), pool.nr_memento()), (2, 2));
+    }
+
+    // Root mementos and clearing flags of every tid are kept at distinct roots, even at `NR_MAX_THREADS`.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn max_threads_roots() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let pool = Pool::create_with_max_threads::<DummyRootObj, DummyRootMemento>(
+            &path("pool"),
+            FILE_SIZE,
+            NR_MAX_THREADS,
+            NR_MAX_THREADS,
+        )
+        .unwrap();
+
+        let mut roots = std::collections::HashSet::new();
+        for tid in 1..=NR_MAX_THREADS {
+            for start in [RootIdx::MementoStart, RootIdx::MementoClearingFlagStart] {
+                let root = unsafe { pool.get_root(start as u64 + tid as u64) };
+                assert!(!root.is_null());
+                assert!(roots.insert(root as usize));
+            }
+        }
+
+        pool.snapshot(&path("copy")).unwrap();
+        let pool = Pool::open::<DummyRootObj, DummyRootMemento>(&path("copy"), FILE_SIZE).unwrap();
+        assert_eq!(pool.nr_memento(), NR_MAX_THREADS);
+    }
+
     /// check flag=1 => value=42
     /// TODO chek inv for pmcheck
     #[cfg(feature = "pmcheck")]
@@ -26,8 +26,15 @@ impl<T: ?Sized> Clone for PPtr<T> {
 impl<T: Collectable> Collectable for PPtr<T> {
     fn filter(ptr: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
         if !ptr.is_null() {
-            let t_ref = unsafe { ptr.deref_mut(pool) };
-            T::mark(t_ref, tid, gc);
+            if let Some(fsck) = &mut pool.fsck {
+                if !fsck.check_ptr(&pool.allocator, ptr.into_offset()) {
+                    return;
+                }
+            }
+
+            // Detach the reference from `pool` so that `pool` can be handed over to `mark`.
+            let t_ref = unsafe { &mut *(ptr.deref_mut(pool) as *mut T) };
+            T::mark(t_ref, tid, gc, pool);
         }
     }
 }
@@ -0,0 +1,338 @@
+//! Named roots of pool
+//!
+//! Besides the root object, a pool keeps a directory of roots looked up by name (e.g. a queue, a hash table,
+//! and a config record), to which new roots can be added after the pool is created.
+//!
+//! Filter functions are not persistent, so the types of named roots should be registered with `NamedRoots`
+//! whenever the pool is opened (`Pool::open_with_roots`), to be traced by the recovery GC.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::c_void;
+
+use crate::ploc::Handle;
+use crate::pmem::alloc::{Collectable, GarbageCollection};
+use crate::pmem::layout::TypeFingerprint;
+use crate::pmem::ll::persist_obj;
+use crate::pmem::pool::{PoolHandle, RootIdx};
+use crate::PDefault;
+
+/// Maximum number of named roots in a pool
+pub const NR_NAMED_ROOTS: usize = 64;
+
+/// Maximum length of the name of a root in bytes
+pub const MAX_ROOT_NAME_LEN: usize = 64;
+
+/// Function that marks a named root of a registered type
+type MarkRoot =
+    unsafe fn(ptr: *mut c_void, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle);
+
+unsafe fn mark_root<T: Collectable>(
+    ptr: *mut c_void,
+    tid: usize,
+    gc: &mut GarbageCollection,
+    pool: &mut PoolHandle,
+) {
+    T::mark(&mut *(ptr as *mut T), tid, gc, pool)
+}
+
+/// Types of the named roots of a pool, registered when the pool is opened
+///
+/// # Example
+///
+/// ```no_run
+/// # use memento::pmem::pool::*;
+/// # use memento::pmem::roots::NamedRoots;
+/// # use memento::ds::queue::Queue;
+/// # use memento::test_utils::tests::{DummyRootObj as MyRootObj, DummyRootMemento as MyRootMemento};
+/// let roots = NamedRoots::new().add::<Queue<usize>>("queue");
+/// let pool = Pool::open_with_roots::<MyRootObj, MyRootMemento>("foo.pool", 8 * 1024 * 1024 * 1024, &roots).unwrap();
+/// let queue = pool.root::<Queue<usize>>("queue").unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct NamedRoots {
+    types: Vec<(String, TypeFingerprint, MarkRoot)>,
+}
+
+// `MarkRoot` does not implement `Debug`, so implement it manually.
+impl fmt::Debug for NamedRoots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.types.iter().map(|(name, fp, _)| (name, fp)))
+            .finish()
+    }
+}
+
+impl NamedRoots {
+    /// No named roots
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the root `name` of type `T`
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<T: Collectable>(mut self, name: &str) -> Self {
+        self.types
+            .push((name.to_owned(), TypeFingerprint::of::<T>(), mark_root::<T>));
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&(String, TypeFingerprint, MarkRoot)> {
+        self.types.iter().find(|(n, _, _)| n == name)
+    }
+}
+
+/// Error of named roots
+///
+/// It is returned wrapped in `std::io::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootError {
+    /// No root has the name
+    NotFound(String),
+
+    /// A root with the name already exists
+    Exists(String),
+
+    /// The root is of another type
+    Type {
+        /// Name of the root
+        name: String,
+        /// Fingerprint of the type given
+        expected: TypeFingerprint,
+        /// Fingerprint of the type the root was created with
+        found: TypeFingerprint,
+    },
+
+    /// The name is empty or longer than `MAX_ROOT_NAME_LEN`
+    Name(String),
+
+    /// The directory has no free entry (`NR_NAMED_ROOTS`)
+    Full,
+
+    /// The type of the root is not registered with `NamedRoots` when the pool is opened
+    Unregistered(String),
+}
+
+impl fmt::Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootError::NotFound(name) => write!(f, "root `{name}` does not exist"),
+            RootError::Exists(name) => write!(f, "root `{name}` already exists"),
+            RootError::Type {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "root `{name}` is of type {found:?} (expected {expected:?})"
+            ),
+            RootError::Name(name) => write!(f, "invalid root name `{name}`"),
+            RootError::Full => write!(f, "no more than {NR_NAMED_ROOTS} named roots"),
+            RootError::Unregistered(name) => write!(f, "type of root `{name}` is not registered"),
+        }
+    }
+}
+
+impl std::error::Error for RootError {}
+
+impl From<RootError> for Error {
+    fn from(e: RootError) -> Self {
+        let kind = match e {
+            RootError::NotFound(_) => ErrorKind::NotFound,
+            RootError::Exists(_) => ErrorKind::AlreadyExists,
+            RootError::Name(_) => ErrorKind::InvalidInput,
+            RootError::Full => ErrorKind::Other,
+            RootError::Type { .. } | RootError::Unregistered(_) => ErrorKind::InvalidData,
+        };
+        Error::new(kind, e)
+    }
+}
+
+/// Entry of the directory
+#[derive(Debug)]
+#[repr(C)]
+struct DirEntry {
+    /// Offset of the root (0 if the entry is free)
+    ///
+    /// It is set after the other fields and the root are persisted, so it commits the entry.
+    root: AtomicUsize,
+
+    fingerprint: TypeFingerprint,
+    name_len: usize,
+    name: [u8; MAX_ROOT_NAME_LEN],
+}
+
+impl DirEntry {
+    fn name(&self) -> &str {
+        std::str::from_utf8(&self.name[..self.name_len.min(MAX_ROOT_NAME_LEN)]).unwrap_or("")
+    }
+}
+
+/// Directory of named roots stored at `RootIdx::Directory`
+///
+/// An all-zero directory is empty.
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct Directory {
+    entries: [DirEntry; NR_NAMED_ROOTS],
+}
+
+impl Directory {
+    /// Committed entries
+    fn committed(&self) -> impl Iterator<Item = (&DirEntry, usize)> {
+        self.entries.iter().filter_map(|e| {
+            let root = e.root.load(Ordering::SeqCst);
+            (root != 0).then_some((e, root))
+        })
+    }
+
+    /// Check that the types of all roots are registered in `types`
+    pub(crate) fn check(&self, types: &NamedRoots) -> Result<(), RootError> {
+        for (e, _) in self.committed() {
+            let name = e.name();
+            let (_, fp, _) = types
+                .get(name)
+                .ok_or_else(|| RootError::Unregistered(name.to_owned()))?;
+            if *fp != e.fingerprint {
+                return Err(RootError::Type {
+                    name: name.to_owned(),
+                    expected: *fp,
+                    found: e.fingerprint,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collectable for Directory {
+    fn filter(dir: &mut Self, tid: usize, gc: &mut GarbageCollection, pool: &mut PoolHandle) {
+        for (e, root) in dir.committed() {
+            // Types were checked when the pool was opened.
+            let (_, _, mark) = pool.named_roots.get(e.name()).unwrap();
+            let mark = *mark;
+            unsafe { mark((pool.start() + root) as *mut c_void, tid, gc, pool) };
+        }
+    }
+}
+
+impl PoolHandle {
+    fn directory(&self) -> &Directory {
+        unsafe { &*(self.get_root(RootIdx::Directory as u64) as *const Directory) }
+    }
+
+    /// Named root `name` of type `T`
+    ///
+    /// # Errors
+    ///
+    /// * Fail with `RootError::NotFound` if the root does not exist
+    /// * Fail with `RootError::Type` if the root was created with another type
+    pub fn root<T>(&self, name: &str) -> Result<&T, Error> {
+        let (e, root) = self
+            .directory()
+            .committed()
+            .find(|(e, _)| e.name() == name)
+            .ok_or_else(|| RootError::NotFound(name.to_owned()))?;
+
+        let expected = TypeFingerprint::of::<T>();
+        if e.fingerprint != expected {
+            return Err(RootError::Type {
+                name: name.to_owned(),
+                expected,
+                found: e.fingerprint,
+            }
+            .into());
+        }
+        Ok(unsafe { &*((self.start() + root) as *const T) })
+    }
+
+    /// Create a named root `name` of type `T` initialized with `PDefault`
+    ///
+    /// It is crash-atomic: the root exists after a crash only if it was completely created.
+    /// Register `T` with `NamedRoots` whenever the pool is opened from now on.
+    ///
+    /// # Errors
+    ///
+    /// * Fail with `RootError::Exists` if a root with `name` exists
+    /// * Fail with `RootError::Name` if `name` is empty or longer than `MAX_ROOT_NAME_LEN`
+    /// * Fail with `RootError::Full` if there are `NR_NAMED_ROOTS` roots
+    /// * Fail with `AllocError` if the pool is out of memory
+    pub fn create_root<T: PDefault>(&'static self, name: &str) -> Result<&T, Error> {
+        if name.is_empty() || name.len() > MAX_ROOT_NAME_LEN {
+            return Err(RootError::Name(name.to_owned()).into());
+        }
+
+        let _lock = self.roots_lock.lock().unwrap();
+        let dir = self.directory();
+        if dir.committed().any(|(e, _)| e.name() == name) {
+            return Err(RootError::Exists(name.to_owned()).into());
+        }
+        let entry = dir
+            .entries
+            .iter()
+            .find(|e| e.root.load(Ordering::SeqCst) == 0)
+            .map(|e| e as *const DirEntry as *mut DirEntry)
+            .ok_or(RootError::Full)?;
+
+        // Initialize the root. It is reclaimed by the recovery GC if a crash occurs before the entry is committed.
+        let ptr = self
+            .try_alloc::<T>()
+            .map_err(|e| Error::new(ErrorKind::OutOfMemory, e))?;
+        let root = unsafe {
+            let tmp_handle = Handle::new(1, self.pin(), self);
+            tmp_handle.rec.store(false, Ordering::SeqCst);
+            let root = ptr.deref_mut(self);
+            (root as *mut T).write(T::pdefault(&tmp_handle));
+            persist_obj(root, true);
+            root
+        };
+
+        // Write the entry and commit it
+        let entry = unsafe { &mut *entry };
+        entry.fingerprint = TypeFingerprint::of::<T>();
+        entry.name_len = name.len();
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        persist_obj(entry, true);
+        entry.root.store(ptr.into_offset(), Ordering::SeqCst);
+        persist_obj(&entry.root, true);
+        Ok(root)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::tests::get_dummy_handle;
+
+    #[test]
+    fn create_root() {
+        let pool = get_dummy_handle(8 * 1024 * 1024 * 1024).unwrap();
+
+        let root = pool.create_root::<usize>("counter").unwrap();
+        assert_eq!(*root, 0);
+        assert!(std::ptr::eq(root, pool.root::<usize>("counter").unwrap()));
+
+        let err = |e: Error| e.get_ref().unwrap().downcast_ref::<RootError>().cloned();
+        assert!(matches!(
+            err(pool.create_root::<usize>("counter").unwrap_err()),
+            Some(RootError::Exists(_))
+        ));
+        assert!(matches!(
+            err(pool.root::<u32>("counter").unwrap_err()),
+            Some(RootError::Type { .. })
+        ));
+        assert!(matches!(
+            err(pool.root::<usize>("nothing").unwrap_err()),
+            Some(RootError::NotFound(_))
+        ));
+
+        let types = NamedRoots::new().add::<usize>("counter");
+        assert_eq!(pool.directory().check(&types), Ok(()));
+        assert!(matches!(
+            pool.directory().check(&NamedRoots::new()),
+            Err(RootError::Unregistered(_))
+        ));
+    }
+}
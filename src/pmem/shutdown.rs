@@ -0,0 +1,191 @@
+//! Clean shutdown of pool
+//!
+//! `PoolHandle::close` keeps what is otherwise recovered by the recovery GC in a record at `RootIdx::Shutdown`:
+//! the execution time, which is later than any timestamp in the pool, and the CAS information of each thread.
+//! The allocator also keeps what it has only in DRAM (e.g. free lists). The record is set last, so the pool is
+//! clean only if everything has been kept.
+//!
+//! `Pool::open` takes the record before changing the pool, so a pool that crashes after it is opened is not clean.
+
+use std::io::Error;
+use std::sync::atomic::Ordering;
+use std::{mem, ptr};
+
+use libc::c_void;
+
+use crate::ploc::Timestamp;
+use crate::pmem::alloc::{PAllocator, PMEMAllocator};
+use crate::pmem::ll::persist_obj;
+use crate::pmem::pool::{PoolHandle, RootIdx};
+
+/// Record of clean shutdown stored at `RootIdx::Shutdown`
+#[derive(Debug)]
+#[repr(C)]
+struct ShutdownRecord {
+    /// Execution time when the pool was closed
+    time: u64,
+
+    /// CAS-own timestamps of threads `0..=max_threads`
+    own: [u64],
+}
+
+impl ShutdownRecord {
+    fn size(len: usize) -> usize {
+        mem::size_of::<u64>() * (len + 1)
+    }
+
+    unsafe fn from_raw<'a>(ptr: *mut c_void, len: usize) -> &'a mut Self {
+        &mut *(ptr::slice_from_raw_parts_mut(ptr as *mut u64, len) as *mut Self)
+    }
+}
+
+/// What a clean shutdown kept, taken from the pool
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    record: *mut c_void,
+    time: Timestamp,
+    own: Vec<u64>,
+}
+
+/// Take the record of clean shutdown of the pool mapped by `allocator`, if any
+///
+/// The record is unset in the pool, and is reclaimed by `Shutdown::restore` or the recovery GC.
+pub(crate) unsafe fn take_shutdown(
+    allocator: &PMEMAllocator,
+    max_threads: usize,
+) -> Option<Shutdown> {
+    let ptr = allocator.get_root(RootIdx::Shutdown as u64);
+    if ptr.is_null() {
+        return None;
+    }
+
+    let record = ShutdownRecord::from_raw(ptr, max_threads + 1);
+    let shutdown = Shutdown {
+        record: ptr,
+        time: Timestamp::from(record.time),
+        own: record.own.to_vec(),
+    };
+    let _prev = allocator.set_root(ptr::null_mut(), RootIdx::Shutdown as u64);
+    Some(shutdown)
+}
+
+impl Shutdown {
+    /// Restore what the recovery GC would recover, unless it has run
+    pub(crate) unsafe fn restore(self, pool: &mut PoolHandle, is_gc_executed: bool) {
+        let exec_info = &mut pool.exec_info;
+        exec_info.chk_max_time = exec_info.chk_max_time.max(self.time);
+        if is_gc_executed {
+            return;
+        }
+
+        for (own, raw) in exec_info.cas_info.own.iter().zip(self.own) {
+            own.restore(raw);
+        }
+        pool.allocator.free(
+            self.record,
+            ShutdownRecord::size(exec_info.cas_info.own.len()),
+        );
+    }
+}
+
+impl PoolHandle {
+    /// Close the pool
+    ///
+    /// It persists the locations deferred by `Guard::defer_persist`, marks the pool clean, and unmaps the pool.
+    /// `Pool::open` of a pool closed cleanly skips the recovery GC, and root mementos run without recovery mode.
+    /// The pool is not marked clean if a root memento has been interrupted (e.g. given up by `execute_with`)
+    /// and has not finished since, which should run in recovery mode.
+    ///
+    /// Return whether the pool is marked clean.
+    ///
+    /// The pool handle itself is kept, as other references to it may remain, but it cannot run root mementos
+    /// or be closed again.
+    ///
+    /// # Safety
+    ///
+    /// Nothing in the pool (e.g. `Handle`, `Guard`, and references to objects in the pool) should be used
+    /// after it returns `Ok`.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if root mementos are being executed, in which case the pool is not closed
+    /// * Fail if the pool is already closed
+    pub unsafe fn close(&self) -> Result<bool, Error> {
+        let _lock = self.lock_slots()?;
+        self.collector.persist_deferred();
+
+        let outputs = self.outputs::<()>();
+        let finished = (1..=self.nr_memento()).all(|tid| outputs.is_finished(tid));
+        let clean = (!self.interrupted.load(Ordering::SeqCst) || finished) && self.keep_shutdown();
+
+        self.closed.store(true, Ordering::SeqCst);
+        self.allocator
+            .close(self.start(), self.end() - self.start());
+        Ok(clean)
+    }
+
+    /// Keep what is otherwise recovered by the recovery GC, and set the record of clean shutdown
+    unsafe fn keep_shutdown(&self) -> bool {
+        let own = &self.exec_info.cas_info.own;
+        let ptr = self
+            .allocator
+            .malloc(ShutdownRecord::size(own.len()) as u64);
+        if ptr.is_null() {
+            return false;
+        }
+
+        let record = ShutdownRecord::from_raw(ptr, own.len());
+        record.time = self.exec_info.exec_time().into();
+        for (raw, own) in record.own.iter_mut().zip(own) {
+            *raw = own.to_raw();
+        }
+        persist_obj(record, true);
+
+        if !self.allocator.keep_for_shutdown() {
+            self.allocator.free(ptr, ShutdownRecord::size(own.len()));
+            return false;
+        }
+        let _prev = self.allocator.set_root(ptr, RootIdx::Shutdown as u64);
+        true
+    }
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod test {
+    use super::*;
+    use crate::pmem::pool::Pool;
+    use crate::test_utils::tests::{DummyRootMemento, DummyRootObj};
+    use std::io::ErrorKind;
+
+    // A snapshot is opened as after a crash.
+    #[test]
+    fn close_and_open() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let pool =
+            Pool::create::<DummyRootObj, DummyRootMemento>(&path("pool"), FILE_SIZE, 1).unwrap();
+        let _ = pool.execute::<DummyRootObj, DummyRootMemento>();
+        let used = pool.stats().used;
+        assert!(unsafe { pool.close() }.unwrap());
+        assert_eq!(
+            unsafe { pool.close() }.unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+
+        // A pool closed cleanly is opened without GC, and is no longer clean once it is opened.
+        let pool = Pool::open::<DummyRootObj, DummyRootMemento>(&path("pool"), FILE_SIZE).unwrap();
+        assert!(pool.stats().recovery.is_none());
+        assert_eq!(pool.stats().used, used);
+        assert!(!pool.interrupted.load(Ordering::SeqCst));
+        pool.snapshot(&path("copy")).unwrap();
+        let copy = Pool::open::<DummyRootObj, DummyRootMemento>(&path("copy"), FILE_SIZE).unwrap();
+        assert!(copy.stats().recovery.is_some());
+        assert!(copy.interrupted.load(Ordering::SeqCst));
+
+        // A root memento that finished is not interrupted.
+        assert!(unsafe { copy.close() }.unwrap());
+    }
+}
@@ -0,0 +1,255 @@
+//! Slots of root mementos
+//!
+//! The root mementos of a pool are numbered `1..=nr_memento` by tid. `PoolHandle::add_memento` adds a slot after
+//! the last one, and `PoolHandle::retire_memento` retires the last one after its root memento has finished.
+//!
+//! The roots of a slot are set (or kept) beyond the number of root mementos committed in the superblock, so they are
+//! not trusted until the superblock switches to the new number and checksum at once. The `NrMemento` root follows
+//! the superblock, and is recovered from it on open if a crash occurred in between.
+//!
+//! The tids reserved by `Fork::fork_join` for children are never given to root mementos, since a child may be resumed
+//! with its tid after a crash.
+
+use std::alloc::Layout;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::ptr;
+use std::sync::{atomic::Ordering, TryLockError};
+
+use libc::c_void;
+
+use crate::pmem::alloc::{PAllocator, PMEMAllocator};
+use crate::pmem::ll::persist_obj;
+use crate::pmem::pool::{Pool, PoolHandle, RootIdx, RootObj};
+use crate::pmem::superblock::{root_table_checksum, SuperBlock};
+use crate::Memento;
+
+unsafe fn set_nr_memento(allocator: &PMEMAllocator, nr_memento: usize) {
+    let ptr = &mut *(allocator.get_root(RootIdx::NrMemento as u64) as *mut usize);
+    *ptr = nr_memento;
+    persist_obj(ptr, true);
+}
+
+/// Make the `NrMemento` root of the pool mapped by `allocator` the same as the superblock
+///
+/// It should be called after the superblock is checked.
+pub(crate) unsafe fn recover_nr_memento(allocator: &PMEMAllocator) {
+    let nr_memento = Pool::superblock(allocator).nr_memento();
+    if *(allocator.get_root(RootIdx::NrMemento as u64) as *const usize) != nr_memento {
+        set_nr_memento(allocator, nr_memento);
+    }
+}
+
+impl PoolHandle {
+    /// Number of root mementos
+    pub fn nr_memento(&self) -> usize {
+        unsafe { *(self.allocator.get_root(RootIdx::NrMemento as u64) as *const usize) }
+    }
+
+    /// Reserve the tids from `tid` up for children of forks, so that `add_memento` does not give them to root mementos
+    pub(crate) fn reserve_fork_tid(&self, tid: usize) {
+        let sb = unsafe {
+            (self.allocator.get_root(RootIdx::SuperBlock as u64) as *const SuperBlock)
+                .as_ref()
+                .unwrap()
+        };
+        sb.reserve_fork_tid(tid);
+    }
+
+    /// Add a slot of root memento, which is run by the next `execute`
+    ///
+    /// Return the tid of the new root memento, i.e. the number of root mementos after it is added.
+    /// If a crash occurs before it returns, the pool is opened with or without the new slot.
+    ///
+    /// O: root obj
+    /// M: root memento(s)
+    ///
+    /// # Errors
+    ///
+    /// * Fail with `LayoutError` if not called with the root obj and root memento types of the pool
+    /// * Fail if root mementos are being executed
+    /// * Fail if the pool already has `max_threads` root mementos or is out of memory
+    /// * Fail if the tid of the new root memento is reserved for children of forks
+    pub fn add_memento<O: RootObj<M>, M: Memento>(&self) -> Result<usize, Error> {
+        unsafe { Pool::check_layout::<O, M>(&self.allocator) }
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let _lock = self.lock_slots()?;
+
+        let tid = self.nr_memento() + 1;
+        if tid > self.max_threads() {
+            return Err(Error::new(ErrorKind::Other, "Too many root mementos."));
+        }
+        if tid >= unsafe { Pool::superblock(&self.allocator) }.fork_base() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Tid {tid} is reserved for children of forks."),
+            ));
+        }
+
+        unsafe {
+            // set root memento and its clearing flag, replacing those of a retired slot if any
+            let m_ptr = self.allocator.malloc_layout(Layout::new::<M>()) as *mut M;
+            if m_ptr.is_null() {
+                return Err(Error::new(ErrorKind::OutOfMemory, "Out of memory"));
+            }
+            let flag_ptr = self.allocator.malloc(mem::size_of::<bool>() as u64) as *mut bool;
+            if flag_ptr.is_null() {
+                self.allocator
+                    .free_layout(m_ptr as *mut c_void, Layout::new::<M>());
+                return Err(Error::new(ErrorKind::OutOfMemory, "Out of memory"));
+            }
+            m_ptr.write(M::default());
+            persist_obj(m_ptr.as_mut().unwrap(), true);
+            flag_ptr.write(false);
+            persist_obj(flag_ptr.as_mut().unwrap(), true);
+            let _prev = self.allocator.set_root(
+                m_ptr as *mut c_void,
+                RootIdx::MementoStart as u64 + tid as u64,
+            );
+            let _prev = self.allocator.set_root(
+                flag_ptr as *mut c_void,
+                RootIdx::MementoClearingFlagStart as u64 + tid as u64,
+            );
+
+            // forget what a retired slot left
+            self.outputs::<()>().forget(tid);
+            self.exec_info.cas_info.own[tid].clear();
+            self.exec_info.cas_info.help[tid].clear();
+
+            // commit
+            Pool::superblock(&self.allocator)
+                .commit(tid, root_table_checksum(&self.allocator, tid));
+            set_nr_memento(&self.allocator, tid);
+        }
+        Ok(tid)
+    }
+
+    /// Retire the last slot of root memento, whose root memento should have finished
+    ///
+    /// Return the tid of the retired root memento. Its root memento and output are reclaimed by the recovery GC
+    /// of the next open. If a crash occurs before it returns, the pool is opened with or without the slot.
+    ///
+    /// # Errors
+    ///
+    /// * Fail if root mementos are being executed
+    /// * Fail if the pool has no root memento, or the last root memento has not finished
+    pub fn retire_memento(&self) -> Result<usize, Error> {
+        let _lock = self.lock_slots()?;
+
+        let tid = self.nr_memento();
+        if tid == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Pool has no root memento.",
+            ));
+        }
+        let outputs = self.outputs::<()>();
+        if !outputs.is_finished(tid) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Root memento {tid} has not finished."),
+            ));
+        }
+
+        unsafe {
+            // commit
+            Pool::superblock(&self.allocator)
+                .commit(tid - 1, root_table_checksum(&self.allocator, tid - 1));
+            set_nr_memento(&self.allocator, tid - 1);
+
+            // unset roots of the slot
+            outputs.forget(tid);
+            let _prev = self
+                .allocator
+                .set_root(ptr::null_mut(), RootIdx::MementoStart as u64 + tid as u64);
+            let _prev = self.allocator.set_root(
+                ptr::null_mut(),
+                RootIdx::MementoClearingFlagStart as u64 + tid as u64,
+            );
+        }
+        Ok(tid)
+    }
+
+    pub(crate) fn lock_slots(&self) -> Result<std::sync::MutexGuard<'_, ()>, Error> {
+        let lock = match self.slots_lock.try_lock() {
+            Ok(lock) => lock,
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::new(
+                    ErrorKind::WouldBlock,
+                    "Root mementos are being executed.",
+                ))
+            }
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        };
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::new(ErrorKind::NotConnected, "Pool is closed."));
+        }
+        Ok(lock)
+    }
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod test {
+    use super::*;
+    use crate::test_utils::tests::{DummyRootMemento, DummyRootObj};
+
+    #[test]
+    fn add_and_retire() {
+        const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let pool =
+            Pool::create::<DummyRootObj, DummyRootMemento>(&path("pool"), FILE_SIZE, 1).unwrap();
+        let err = pool.retire_memento().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // Scale up
+        assert_eq!(pool.execute::<DummyRootObj, DummyRootMemento>().len(), 1);
+        assert_eq!(
+            pool.add_memento::<DummyRootObj, DummyRootMemento>()
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            pool.add_memento::<DummyRootObj, DummyRootMemento>()
+                .unwrap(),
+            3
+        );
+        assert_eq!(pool.nr_memento(), 3);
+        let res = pool.execute_with::<DummyRootObj, DummyRootMemento>(&Default::default());
+        let finished_before = res
+            .iter()
+            .map(|res| res.as_ref().unwrap().finished_before)
+            .collect::<Vec<_>>();
+        assert_eq!(finished_before, [true, false, false]);
+
+        // Scale down
+        assert_eq!(pool.retire_memento().unwrap(), 3);
+        assert_eq!(pool.nr_memento(), 2);
+
+        // A slot added again does not keep the output of the retired one.
+        assert_eq!(
+            pool.add_memento::<DummyRootObj, DummyRootMemento>()
+                .unwrap(),
+            3
+        );
+        assert!(pool.output::<DummyRootObj, DummyRootMemento>(3).is_none());
+
+        // The slots are kept when the pool is opened again.
+        assert!(unsafe { pool.close() }.unwrap());
+        let pool = Pool::open::<DummyRootObj, DummyRootMemento>(&path("pool"), FILE_SIZE).unwrap();
+        assert_eq!(pool.nr_memento(), 3);
+
+        // A tid reserved for children of forks is not given to a root memento, even after the pool is opened again.
+        pool.reserve_fork_tid(4);
+        assert!(unsafe { pool.close() }.unwrap());
+        let pool = Pool::open::<DummyRootObj, DummyRootMemento>(&path("pool"), FILE_SIZE).unwrap();
+        let err = pool
+            .add_memento::<DummyRootObj, DummyRootMemento>()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(pool.nr_memento(), 3);
+    }
+}
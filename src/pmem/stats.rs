@@ -0,0 +1,70 @@
+//! Heap statistics of pool
+
+/// Heap statistics of pool
+///
+/// It is taken without stopping other threads, so it may be slightly off while they allocate or free.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Capacity of the heap in bytes
+    pub heap_size: usize,
+
+    /// Bytes of live blocks
+    pub used: usize,
+
+    /// Bytes not used by live blocks (i.e. `heap_size - used`)
+    pub free: usize,
+
+    /// Bytes of free blocks in chunks holding live blocks of a size class
+    ///
+    /// They are included in `free`, but can be reused only for the same size class.
+    /// It is 0 if the allocator does not track it (PMDK).
+    pub fragmented: usize,
+
+    /// Live blocks of each size class in ascending order of block size
+    ///
+    /// Classes without live blocks are omitted.
+    pub classes: Vec<ClassStats>,
+
+    /// Result of the recovery GC of the last `Pool::open`
+    ///
+    /// It is `None` if GC has not run since the pool was mapped (e.g. after `Pool::create`, or when the pool
+    /// was closed cleanly by `PoolHandle::close`).
+    pub recovery: Option<RecoveryStats>,
+}
+
+impl PoolStats {
+    /// Ratio of `fragmented` to `free`
+    pub fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            return 0.0;
+        }
+        self.fragmented as f64 / self.free as f64
+    }
+}
+
+/// Live blocks of a size class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// Size of a block of this class (0 for large blocks, whose sizes vary)
+    pub block_size: usize,
+
+    /// Number of live blocks
+    pub live_blocks: usize,
+
+    /// Bytes of live blocks
+    pub live_bytes: usize,
+}
+
+/// Result of a recovery GC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// Number of blocks reachable from the roots
+    pub reachable: usize,
+
+    /// Number of blocks that were allocated but unreachable, thus freed by GC
+    ///
+    /// Ralloc and the mmap allocator count allocated blocks with volatile counters, so the count is exact
+    /// after a process crash but approximate after a power failure. A nonzero count after a clean run
+    /// means that some `Collectable` does not mark all of its blocks, or that blocks leaked.
+    pub reclaimed: usize,
+}
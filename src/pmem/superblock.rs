@@ -0,0 +1,308 @@
+//! Superblock of pool
+//!
+//! `Pool::create` writes the superblock after every other root is set, and its creation-complete flag last of all.
+//! `Pool::open` validates it before trusting anything else in the pool.
+//!
+//! The number of root mementos and the checksum are kept in two slots, and a change of the root table is committed
+//! by writing the inactive slot and then switching to it, so that they are never torn by a crash.
+//!
+//! The tids from the fork base up are reserved for the children of forks, and are never given to root mementos.
+
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::ploc::NR_MAX_THREADS;
+use crate::pmem::alloc::{PAllocator, PMEMAllocator};
+use crate::pmem::layout::fnv1a;
+use crate::pmem::ll::persist_obj;
+use crate::pmem::pool::RootIdx;
+
+/// Magic number identifying a memento pool ("MEMENTO\0")
+pub const POOL_MAGIC: u64 = u64::from_le_bytes(*b"MEMENTO\0");
+
+/// Root table recorded in the superblock
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+struct RootTable {
+    /// Number of root mementos whose roots are covered by `checksum`
+    nr_memento: u64,
+
+    /// Checksum over the root table
+    checksum: u64,
+}
+
+/// Superblock of pool stored at `RootIdx::SuperBlock`
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct SuperBlock {
+    magic: u64,
+
+    /// Maximum number of threads, for which per-thread metadata is allocated
+    max_threads: u64,
+
+    /// Root tables, of which `tables[current]` is valid
+    tables: [RootTable; 2],
+
+    /// Index of the valid root table
+    current: u64,
+
+    /// Lowest tid reserved for the children of forks (`max_threads + 1` if none is reserved)
+    fork_base: AtomicU64,
+
+    /// Creation-complete flag (written last)
+    complete: u64,
+}
+
+impl SuperBlock {
+    /// Superblock of a pool in creation
+    pub(crate) fn new(max_threads: usize, nr_memento: usize, checksum: u64) -> Self {
+        let table = RootTable {
+            nr_memento: nr_memento as u64,
+            checksum,
+        };
+        Self {
+            magic: POOL_MAGIC,
+            max_threads: max_threads as u64,
+            tables: [table, RootTable::default()],
+            current: 0,
+            fork_base: AtomicU64::new(max_threads as u64 + 1),
+            complete: 0,
+        }
+    }
+
+    fn table(&self) -> &RootTable {
+        &self.tables[self.current as usize % 2]
+    }
+
+    /// Maximum number of threads of the pool
+    pub(crate) fn max_threads(&self) -> usize {
+        self.max_threads as usize
+    }
+
+    /// Number of root mementos recorded in the superblock
+    pub(crate) fn nr_memento(&self) -> usize {
+        self.table().nr_memento as usize
+    }
+
+    /// Lowest tid reserved for the children of forks
+    pub(crate) fn fork_base(&self) -> usize {
+        self.fork_base.load(Ordering::SeqCst) as usize
+    }
+
+    /// Reserve the tids from `tid` up for the children of forks
+    ///
+    /// The fork base only goes down, so it may be called by many threads at once.
+    pub(crate) fn reserve_fork_tid(&self, tid: usize) {
+        let _ = self.fork_base.fetch_min(tid as u64, Ordering::SeqCst);
+        persist_obj(&self.fork_base, true);
+    }
+
+    /// Replace the checksum after the root table is changed (e.g. by a migration of the root object)
+    pub(crate) fn set_checksum(&mut self, checksum: u64) {
+        self.commit(self.nr_memento(), checksum);
+    }
+
+    /// Replace the number of root mementos and the checksum at once
+    ///
+    /// The roots of the root mementos being added should be persisted before.
+    pub(crate) fn commit(&mut self, nr_memento: usize, checksum: u64) {
+        let next = (self.current + 1) % 2;
+        let table = &mut self.tables[next as usize];
+        *table = RootTable {
+            nr_memento: nr_memento as u64,
+            checksum,
+        };
+        persist_obj(table, true);
+
+        self.current = next;
+        persist_obj(&self.current, true);
+    }
+
+    /// Mark the creation of pool as complete
+    ///
+    /// It should be called after every other root of the pool is persisted.
+    pub(crate) fn mark_complete(&mut self) {
+        self.complete = 1;
+        persist_obj(&self.complete, true);
+    }
+
+    /// Address range of the creation-complete flag
+    pub(crate) fn complete_flag(&self) -> Range<usize> {
+        let start = &self.complete as *const u64 as usize;
+        start..start + mem::size_of::<u64>()
+    }
+
+    /// Check if the pool is complete and its root table has the checksum computed by `checksum`
+    ///
+    /// `checksum` is called with the number of root mementos only after the other fields are validated,
+    /// as it reads the roots of that many root mementos.
+    pub(crate) fn check<F: FnOnce(usize) -> u64>(
+        &self,
+        checksum: F,
+    ) -> Result<(), SuperBlockError> {
+        if self.magic != POOL_MAGIC {
+            return Err(SuperBlockError::Magic { found: self.magic });
+        }
+        if !(1..=NR_MAX_THREADS as u64).contains(&self.max_threads) {
+            return Err(SuperBlockError::MaxThreads {
+                found: self.max_threads,
+            });
+        }
+        if self.complete != 1 {
+            return Err(SuperBlockError::Incomplete);
+        }
+        if self.table().nr_memento > self.max_threads {
+            return Err(SuperBlockError::NrMemento {
+                found: self.table().nr_memento,
+            });
+        }
+        let checksum = checksum(self.nr_memento());
+        if self.table().checksum != checksum {
+            return Err(SuperBlockError::Checksum {
+                expected: self.table().checksum,
+                found: checksum,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Checksum over the root table of the pool mapped by `allocator`
+///
+/// It covers the metadata roots and the roots of `nr_memento` root mementos and their clearing flags.
+/// Roots are hashed as offsets from the start of the pool, so the checksum does not depend on where the pool is mapped.
+pub(crate) unsafe fn root_table_checksum(allocator: &PMEMAllocator, nr_memento: usize) -> u64 {
+    let start = allocator.mmapped_addr();
+    let metadata = (RootIdx::RootObj as u64..=RootIdx::Layout as u64)
+        .chain([RootIdx::Directory as u64, RootIdx::Outputs as u64]);
+    let mementos = (1..=nr_memento as u64).map(|tid| RootIdx::MementoStart as u64 + tid);
+    let flags = (1..=nr_memento as u64).map(|tid| RootIdx::MementoClearingFlagStart as u64 + tid);
+
+    let mut bytes = Vec::new();
+    for ix in metadata.chain(mementos).chain(flags) {
+        let ptr = allocator.get_root(ix) as usize;
+        let offset = if ptr == 0 { 0 } else { ptr - start };
+        bytes.extend_from_slice(&ix.to_le_bytes());
+        bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+    }
+    fnv1a(&bytes)
+}
+
+/// Error of opening a file which is not a complete and intact pool
+///
+/// `Pool::open` returns it wrapped in `std::io::Error` of `ErrorKind::InvalidData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuperBlockError {
+    /// The pool has no superblock (e.g. the creation was interrupted before writing it)
+    Missing,
+
+    /// The magic number is different
+    Magic {
+        /// Magic number recorded in the pool
+        found: u64,
+    },
+
+    /// The maximum number of threads is not in `1..=NR_MAX_THREADS`
+    MaxThreads {
+        /// Maximum number of threads recorded in the pool
+        found: u64,
+    },
+
+    /// The creation of pool was interrupted
+    Incomplete,
+
+    /// The number of root mementos is more than the maximum number of threads
+    NrMemento {
+        /// Number of root mementos recorded in the pool
+        found: u64,
+    },
+
+    /// The root table is different from the one recorded during `Pool::create`
+    Checksum {
+        /// Checksum recorded in the superblock
+        expected: u64,
+        /// Checksum of the current root table
+        found: u64,
+    },
+}
+
+impl fmt::Display for SuperBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuperBlockError::Missing => write!(f, "pool has no superblock"),
+            SuperBlockError::Magic { found } => {
+                write!(f, "magic number {found:#x} (expected {POOL_MAGIC:#x})")
+            }
+            SuperBlockError::MaxThreads { found } => {
+                write!(
+                    f,
+                    "maximum number of threads {found} (expected 1..={NR_MAX_THREADS})"
+                )
+            }
+            SuperBlockError::Incomplete => write!(f, "pool creation was not completed"),
+            SuperBlockError::NrMemento { found } => {
+                write!(
+                    f,
+                    "{found} root mementos (more than the maximum number of threads)"
+                )
+            }
+            SuperBlockError::Checksum { expected, found } => {
+                write!(f, "root table checksum {found:#x} (expected {expected:#x})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SuperBlockError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_superblock() {
+        let mut sb = SuperBlock::new(NR_MAX_THREADS, 1, 42);
+        assert_eq!(sb.check(|_| 42), Err(SuperBlockError::Incomplete));
+
+        sb.mark_complete();
+        assert_eq!(sb.check(|_| 42), Ok(()));
+        assert!(matches!(
+            sb.check(|_| 43),
+            Err(SuperBlockError::Checksum { .. })
+        ));
+
+        sb.commit(2, 43);
+        assert_eq!((sb.nr_memento(), sb.check(|_| 43)), (2, Ok(())));
+        sb.commit(1, 42);
+        assert_eq!((sb.nr_memento(), sb.check(|_| 42)), (1, Ok(())));
+
+        sb.max_threads = NR_MAX_THREADS as u64 + 1;
+        assert!(matches!(
+            sb.check(|_| 42),
+            Err(SuperBlockError::MaxThreads { .. })
+        ));
+        sb.max_threads = 1;
+
+        // The checksum is not computed over the roots of too many root mementos.
+        sb.commit(2, 42);
+        assert_eq!(
+            sb.check(|_| unreachable!()),
+            Err(SuperBlockError::NrMemento { found: 2 })
+        );
+        sb.commit(1, 42);
+
+        // The fork base only goes down.
+        assert_eq!(sb.fork_base(), NR_MAX_THREADS + 1);
+        sb.reserve_fork_tid(1);
+        sb.reserve_fork_tid(2);
+        assert_eq!(sb.fork_base(), 1);
+
+        sb.magic = 0;
+        assert!(matches!(
+            sb.check(|_| 42),
+            Err(SuperBlockError::Magic { .. })
+        ));
+    }
+}
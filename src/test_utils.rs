@@ -153,7 +153,7 @@ pub mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use tempfile::NamedTempFile;
 
-    use crate::ploc::Handle;
+    use crate::ploc::{Handle, NR_MAX_THREADS};
     use crate::pmem::alloc::{Collectable, GarbageCollection};
     use crate::pmem::pool::*;
     use crate::test_utils::thread;
@@ -237,13 +237,12 @@ pub mod tests {
     pub static mut TESTER_FLAG: AtomicBool = AtomicBool::new(false);
 
     /// run test op
+    ///
+    /// The pool left by a crashed run is opened again to recover it, while the pool left by a completed run is
+    /// replaced with a new one, since its root mementos would return their outputs without running.
     #[allow(box_pointers)]
-    pub fn run_test<O, M>(
-        pool_name: &str,
-        pool_len: usize,
-        nr_memento: usize,
-        nr_count: usize,
-    ) where
+    pub fn run_test<O, M>(pool_name: &str, pool_len: usize, nr_memento: usize, nr_count: usize)
+    where
         O: RootObj<M> + Send + Sync + 'static,
         M: Memento + Send + Sync,
     {
@@ -292,17 +291,23 @@ pub mod tests {
     {
         let filepath = get_test_abs_path(pool_name);
 
-        // remove pool
-        // let _ = Pool::remove(&filepath);
+        // open pool, unless it is left by a completed run
+        let pool_handle = match Pool::open::<O, M>(&filepath, pool_len) {
+            Ok(pool) if (1..=pool.nr_memento()).all(|tid| pool.output::<O, M>(tid).is_some()) => {
+                let _ = unsafe { pool.close() };
+                None
+            }
+            res => res.ok(),
+        };
 
-        // open pool
-        let pool_handle = unsafe { Pool::open::<O, M>(&filepath, pool_len) }.unwrap_or_else(|_| {
+        // create new pool
+        let pool_handle = pool_handle.unwrap_or_else(|| {
             let _ = Pool::remove(&filepath);
             Pool::create::<O, M>(&filepath, pool_len, nr_memento).unwrap()
         });
 
         // run root memento(s)
-        pool_handle.execute::<O, M>();
+        let _ = pool_handle.execute::<O, M>();
     }
 
     /// child thread handler: thread exit
@@ -318,13 +323,15 @@ pub mod tests {
     }
 
     impl TestValue {
-        const TID_LIMIT: usize = 100;
+        // Above every tid of a pool, so that values of distinct tids never alias.
+        const TID_LIMIT: usize = NR_MAX_THREADS + 1;
 
         /// (tid, seq) -> unique repr
         ///
         /// - tid must be less than TID_LIMIT
         #[inline]
         pub fn new(tid: usize, seq: usize) -> Self {
+            debug_assert!(tid < Self::TID_LIMIT);
             Self::compose(tid, seq)
         }
 
@@ -450,19 +457,19 @@ pub mod tests {
 
     #[derive(Debug)]
     pub struct Tester {
-        infos: [TestInfo; Self::MAX_THREAD],
+        infos: Vec<TestInfo>,
         nr_thread: usize,
         nr_count: usize,
     }
 
     impl Tester {
-        const MAX_THREAD: usize = 30;
-
         fn new(nr_thread: usize, nr_count: usize) -> Self {
-            assert!(nr_thread <= Self::MAX_THREAD);
+            assert!(nr_thread <= NR_MAX_THREADS && nr_thread < TestValue::TID_LIMIT);
 
             Self {
-                infos: array_init::array_init(|tid| TestInfo::new(tid + 1, nr_count)),
+                infos: (0..nr_thread)
+                    .map(|tid| TestInfo::new(tid + 1, nr_count))
+                    .collect(),
                 nr_thread,
                 nr_count,
             }
@@ -562,6 +569,10 @@ pub mod tests {
         }
 
         fn check(&self) {
+            // A testee that has not checked in did not run (e.g. its root memento returned its output instead),
+            // so nothing would be checked for it.
+            assert!(self.is_started(), "Some testees have not checked in.");
+
             let mut checked_map = vec![vec![false; self.nr_count]; self.nr_thread + 1];
 
             for (to_tid, results) in self.infos.iter().filter_map(|info| {